@@ -1,10 +1,13 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
 use kodama::RECEIVE_BUFFER_SIZE;
+use kodama::common::GameData;
 use kodama::config::get_config;
 use kodama::ipc::kodama::{CustomIpcData, CustomIpcSegment, CustomIpcType};
-use kodama::ipc::lobby::ServiceAccount;
 use kodama::ipc::lobby::{ClientLobbyIpcData, ServerLobbyIpcSegment};
-use kodama::lobby::LobbyConnection;
-use kodama::packet::{ConnectionType, send_custom_world_packet};
+use kodama::lobby::{LobbyConnection, LobbyError, LoginQueue, WorldRegistry, WorldStatus};
+use kodama::packet::{ConnectionType, CustomIpcAuth};
 use kodama::packet::{PacketState, SegmentData, send_keep_alive};
 use tokio::io::AsyncReadExt;
 use tokio::net::TcpListener;
@@ -15,13 +18,24 @@ async fn main() {
 
     let config = get_config();
 
+    if config.shared_secret.is_empty() {
+        tracing::error!(
+            "No shared secret is configured, which would let anyone register a world server! Please set shared_secret in the config."
+        );
+        return;
+    }
+
     let addr = config.lobby.get_socketaddr();
 
     let listener = TcpListener::bind(addr).await.unwrap();
 
     tracing::info!("Server started on {addr}");
 
-    let world_name = "Gilgamesh".to_string(); // TODO: hardcoded
+    let world_registry = Arc::new(Mutex::new(WorldRegistry::new(
+        &config,
+        &mut GameData::new(),
+    )));
+    let login_queue = Arc::new(Mutex::new(LoginQueue::default()));
     loop {
         let (socket, _) = listener.accept().await.unwrap();
 
@@ -32,148 +46,280 @@ async fn main() {
             state,
             session_id: None,
             stored_character_creation_name: String::new(),
-            world_name: world_name.clone(),
+            world_registry: world_registry.clone(),
+            character_worlds: HashMap::new(),
+            character_list_complete: false,
+            retainer_worlds: HashMap::new(),
             service_accounts: Vec::new(),
             selected_service_account: None,
+            custom_ipc_auth: CustomIpcAuth::default(),
+            settings_upload: None,
+            login_queue: login_queue.clone(),
+            deferred_segments: Vec::new(),
         };
 
         tokio::spawn(async move {
             let mut buf = vec![0; RECEIVE_BUFFER_SIZE];
             loop {
-                let n = connection
-                    .socket
-                    .read(&mut buf)
-                    .await
-                    .expect("Failed to read data!");
+                let Ok(n) = connection.socket.read(&mut buf).await else {
+                    tracing::warn!("Failed to read from the client, dropping the connection!");
+                    break;
+                };
 
                 if n != 0 {
-                    let (segments, _) = connection.parse_packet(&buf[..n]);
-                    for segment in &segments {
-                        match &segment.data {
-                            SegmentData::SecuritySetup { phrase, key } => {
-                                connection.initialize_encryption(phrase, *key).await
-                            }
-                            SegmentData::KeepAliveRequest { id, timestamp } => {
-                                send_keep_alive::<ServerLobbyIpcSegment>(
-                                    &mut connection.socket,
-                                    &mut connection.state,
-                                    ConnectionType::Lobby,
-                                    *id,
-                                    *timestamp,
-                                )
-                                .await
-                            }
-                            SegmentData::KeepAliveResponse { .. } => {
-                                // we can throw this away
-                            }
-                            SegmentData::Ipc { data } => match &data.data {
-                                ClientLobbyIpcData::LoginEx {
-                                    session_id,
-                                    version_info,
-                                    ..
-                                } => {
-                                    tracing::info!(
-                                        "Client logging in! {session_id} {version_info}"
-                                    );
-                                    let config = get_config();
-
-                                    let Ok(login_reply) = reqwest::get(format!(
-                                        "http://{}/_private/service_accounts?sid={}",
-                                        config.login.server_name, session_id
-                                    ))
+                    let (mut segments, _) = connection.parse_packet(&buf[..n]);
+                    // anything sent while waiting in the login queue is handled after what came before it
+                    while !segments.is_empty() {
+                        for segment in &segments {
+                            match &segment.data {
+                                SegmentData::SecuritySetup { phrase, key } => {
+                                    connection.initialize_encryption(phrase, *key).await
+                                }
+                                SegmentData::KeepAliveRequest { id, timestamp } => {
+                                    send_keep_alive::<ServerLobbyIpcSegment>(
+                                        &mut connection.socket,
+                                        &mut connection.state,
+                                        ConnectionType::Lobby,
+                                        *id,
+                                        *timestamp,
+                                    )
                                     .await
-                                    else {
-                                        tracing::warn!(
-                                            "Failed to contact login server, is it running?"
-                                        );
-                                        break;
-                                    };
+                                }
+                                SegmentData::KeepAliveResponse { .. } => {
+                                    // we can throw this away
+                                }
+                                SegmentData::KodamaIpc { data } => {
+                                    connection.handle_custom_ipc(data).await
+                                }
+                                SegmentData::Ipc { data } => match &data.data {
+                                    ClientLobbyIpcData::LoginEx {
+                                        sequence,
+                                        session_id,
+                                        version_info,
+                                        ..
+                                    } => {
+                                        tracing::info!("Client logging in! {version_info}");
+                                        match LobbyConnection::fetch_service_accounts(session_id)
+                                            .await
+                                        {
+                                            Ok(service_accounts) => {
+                                                connection.service_accounts = service_accounts;
+                                                connection.session_id = Some(session_id.clone());
+                                                connection.send_account_list().await;
+                                            }
+                                            Err(error) => {
+                                                connection.send_error(*sequence, error).await;
+                                            }
+                                        }
+                                    }
+                                    ClientLobbyIpcData::ServiceLogin {
+                                        sequence,
+                                        account_index,
+                                        ..
+                                    } => {
+                                        let Some(service_account_id) = connection
+                                            .service_accounts
+                                            .get(*account_index as usize)
+                                            .map(|service_account| service_account.id)
+                                        else {
+                                            tracing::warn!(
+                                                "Client picked service account {account_index}, which doesn't exist!"
+                                            );
+                                            connection
+                                                .send_error(
+                                                    *sequence,
+                                                    LobbyError::InvalidServiceAccount,
+                                                )
+                                                .await;
+                                            continue;
+                                        };
 
-                                    let Ok(body) = login_reply.text().await else {
-                                        tracing::warn!(
-                                            "Failed to contact login server, is it running?"
-                                        );
-                                        break;
-                                    };
+                                        // it may have been disabled or deleted on the website since the list was sent
+                                        if let Some(session_id) = connection.session_id.clone() {
+                                            match LobbyConnection::fetch_service_accounts(
+                                                &session_id,
+                                            )
+                                            .await
+                                            {
+                                                Ok(service_accounts) => {
+                                                    // so the client doesn't keep showing accounts that are gone, or miss new ones
+                                                    if service_accounts
+                                                        != connection.service_accounts
+                                                    {
+                                                        connection.service_accounts =
+                                                            service_accounts;
+                                                        connection.send_account_list().await;
+                                                    }
+                                                }
+                                                Err(error) => {
+                                                    connection.send_error(*sequence, error).await;
+                                                    continue;
+                                                }
+                                            }
+                                        }
 
-                                    let service_accounts: Option<Vec<ServiceAccount>> =
-                                        serde_json::from_str(&body).ok();
-                                    if let Some(service_accounts) = service_accounts {
-                                        if service_accounts.is_empty() {
+                                        if !connection.service_accounts.iter().any(
+                                            |service_account| {
+                                                service_account.id == service_account_id
+                                            },
+                                        ) {
                                             tracing::warn!(
-                                                "This account has no service accounts attached, how did this happen?"
+                                                "Service account {service_account_id} is no longer available!"
                                             );
-                                        } else {
-                                            connection.service_accounts = service_accounts;
-                                            connection.session_id = Some(session_id.clone());
-                                            connection.send_account_list().await;
+                                            connection
+                                                .send_error(
+                                                    *sequence,
+                                                    LobbyError::InvalidServiceAccount,
+                                                )
+                                                .await;
+                                            continue;
+                                        }
+
+                                        match LobbyConnection::fetch_suspension(
+                                            service_account_id as u32,
+                                        )
+                                        .await
+                                        {
+                                            Ok(None) => {}
+                                            Ok(Some(suspension)) => {
+                                                tracing::info!(
+                                                    "Service account {service_account_id} is suspended: {}",
+                                                    suspension.describe()
+                                                );
+                                                connection
+                                                    .send_error(*sequence, LobbyError::Suspended)
+                                                    .await;
+                                                continue;
+                                            }
+                                            Err(error) => {
+                                                connection.send_error(*sequence, error).await;
+                                                continue;
+                                            }
                                         }
+
+                                        connection.selected_service_account =
+                                            Some(service_account_id as u32);
+                                        connection.send_lobby_info(*sequence).await
+                                    }
+                                    ClientLobbyIpcData::CharaMake(chara_make) => {
+                                        dbg!(chara_make);
+                                        connection.handle_character_action(&chara_make).await;
                                     }
+                                    ClientLobbyIpcData::GameLogin {
+                                        sequence,
+                                        content_id,
+                                        ..
+                                    } => {
+                                        tracing::info!(
+                                            "Client is joining the world with {content_id}"
+                                        );
 
-                                    connection.send_account_list().await;
-                                }
-                                ClientLobbyIpcData::ServiceLogin {
-                                    sequence,
-                                    account_index,
-                                    ..
-                                } => {
-                                    connection.selected_service_account = Some(
-                                        connection.service_accounts[*account_index as usize].id
-                                            as u32,
-                                    );
-                                    connection.send_lobby_info(*sequence).await
-                                }
-                                ClientLobbyIpcData::CharaMake(chara_make) => {
-                                    dbg!(chara_make);
-                                    connection.handle_character_action(&chara_make).await;
-                                }
-                                ClientLobbyIpcData::GameLogin {
-                                    sequence,
-                                    content_id,
-                                    ..
-                                } => {
-                                    tracing::info!("Client is joining the world with {content_id}");
-
-                                    let our_actor_id;
-
-                                    // find the actor id for this content id
-                                    // NOTE: This is NOT the ideal solution. I theorize the lobby server has it's own records with this information.
-                                    {
-                                        let ipc_segment = CustomIpcSegment {
-                                            unk1: 0,
-                                            unk2: 0,
-                                            op_code: CustomIpcType::GetActorId,
-                                            option: 0,
-                                            timestamp: 0,
-                                            data: CustomIpcData::GetActorId {
-                                                content_id: *content_id as u64,
-                                            },
+                                        let Some(world) =
+                                            connection.get_character_world(*content_id)
+                                        else {
+                                            tracing::warn!(
+                                                "Couldn't find the world for {content_id}!"
+                                            );
+                                            connection
+                                                .send_error(*sequence, LobbyError::Failed)
+                                                .await;
+                                            continue;
                                         };
 
-                                        let response_segment =
-                                            send_custom_world_packet(ipc_segment).await.unwrap();
+                                        // don't send them to a world that can't take them
+                                        let status = connection
+                                            .world_registry
+                                            .lock()
+                                            .unwrap()
+                                            .get_status(world.id);
+                                        if status == WorldStatus::Offline {
+                                            tracing::warn!(
+                                                "{} is offline, refusing login!",
+                                                world.name
+                                            );
+                                            connection
+                                                .send_error(*sequence, LobbyError::WorldOffline)
+                                                .await;
+                                            continue;
+                                        }
+
+                                        // full worlds make them wait in line
+                                        if !connection.wait_in_queue(*sequence, &world).await {
+                                            continue;
+                                        }
+
+                                        let our_actor_id;
 
-                                        match &response_segment.data {
-                                            CustomIpcData::ActorIdFound { actor_id } => {
-                                                our_actor_id = *actor_id;
+                                        // find the actor id for this content id
+                                        // NOTE: This is NOT the ideal solution. I theorize the lobby server has it's own records with this information.
+                                        {
+                                            let ipc_segment = CustomIpcSegment {
+                                                op_code: CustomIpcType::GetActorId,
+                                                data: CustomIpcData::GetActorId {
+                                                    content_id: *content_id as u64,
+                                                },
+                                                ..Default::default()
+                                            };
+
+                                            match world.channel.request(ipc_segment).await {
+                                                Some(CustomIpcSegment {
+                                                    data: CustomIpcData::ActorIdFound { actor_id },
+                                                    ..
+                                                }) => {
+                                                    our_actor_id = actor_id;
+                                                }
+                                                _ => {
+                                                    tracing::warn!(
+                                                        "{} didn't tell us the actor id of {content_id}!",
+                                                        world.name
+                                                    );
+                                                    connection
+                                                        .send_error(*sequence, LobbyError::Failed)
+                                                        .await;
+                                                    continue;
+                                                }
                                             }
-                                            _ => panic!("Unexpected custom IPC packet type here!"),
                                         }
-                                    }
 
-                                    connection
-                                        .send_enter_world(
-                                            *sequence,
-                                            *content_id as u64,
-                                            our_actor_id,
-                                        )
-                                        .await;
-                                }
+                                        let Some(token) = connection
+                                            .grant_world_entry(
+                                                &world,
+                                                *content_id as u64,
+                                                our_actor_id,
+                                            )
+                                            .await
+                                        else {
+                                            tracing::warn!(
+                                                "{} didn't let {content_id} in, refusing login!",
+                                                world.name
+                                            );
+                                            connection
+                                                .send_error(*sequence, LobbyError::Failed)
+                                                .await;
+                                            continue;
+                                        };
+
+                                        connection
+                                            .send_enter_world(
+                                                *sequence,
+                                                *content_id as u64,
+                                                our_actor_id,
+                                                &token,
+                                                &world,
+                                            )
+                                            .await;
+                                    }
+                                    _ => {}
+                                },
                                 _ => {}
-                            },
-                            _ => {}
+                            }
                         }
+
+                        segments = std::mem::take(&mut connection.deferred_segments);
                     }
+                } else {
+                    // the other side hung up, e.g. a world server shutting down
+                    break;
                 }
             }
         });
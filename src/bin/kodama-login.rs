@@ -1,16 +1,25 @@
+use std::net::{IpAddr, SocketAddr};
 use std::sync::Arc;
 
-use axum::extract::{Query, State};
-use axum::response::{Html, Redirect};
+use axum::extract::{ConnectInfo, Query, Request, State};
+use axum::http::{HeaderMap, HeaderValue, StatusCode, header};
+use axum::middleware::{self, Next};
+use axum::response::{Html, IntoResponse, Redirect, Response};
 use axum::routing::post;
-use axum::{Form, Router, routing::get};
+use axum::{Form, Json, Router, routing::get};
 use axum_extra::extract::CookieJar;
-use axum_extra::extract::cookie::{Cookie, Expiration};
-use kodama::config::get_config;
-use kodama::login::{LoginDatabase, LoginError};
+use axum_extra::extract::cookie::{Cookie, Expiration, SameSite};
+use kodama::common::{PRIVATE_API_HEADER, timestamp_secs, verify_private_api_token};
+use kodama::config::{RegistrationMode, get_config};
+use kodama::ipc::kodama::{CustomIpcData, CustomIpcSegment, CustomIpcType};
+use kodama::login::{
+    AccountRole, ApiError, Ban, LoginDatabase, LoginError, RegistrationError, ServiceAccountInfo,
+    SessionKind, client_ip, csrf_token, generate_qr_code, validate_password, verify_csrf_token,
+};
+use kodama::packet::WorldChannel;
 use minijinja::{Environment, context};
-use serde::Deserialize;
-use tower_http::cors::{Any, CorsLayer};
+use serde::{Deserialize, Serialize};
+use tower_http::cors::{AllowOrigin, CorsLayer};
 use tower_http::services::ServeDir;
 
 fn setup_default_environment() -> Environment<'static> {
@@ -57,6 +66,29 @@ fn setup_default_environment() -> Environment<'static> {
             .expect("Failed to find template!"),
     )
     .unwrap();
+    env.add_template_owned(
+        "serviceaccounts.html",
+        std::fs::read_to_string("resources/templates/serviceaccounts.html")
+            .expect("Failed to find template!"),
+    )
+    .unwrap();
+    env.add_template_owned(
+        "moderation.html",
+        std::fs::read_to_string("resources/templates/moderation.html")
+            .expect("Failed to find template!"),
+    )
+    .unwrap();
+    env.add_template_owned(
+        "otp.html",
+        std::fs::read_to_string("resources/templates/otp.html").expect("Failed to find template!"),
+    )
+    .unwrap();
+    env.add_template_owned(
+        "cancelaccount.html",
+        std::fs::read_to_string("resources/templates/cancelaccount.html")
+            .expect("Failed to find template!"),
+    )
+    .unwrap();
 
     env
 }
@@ -64,6 +96,8 @@ fn setup_default_environment() -> Environment<'static> {
 #[derive(Clone)]
 struct LoginServerState {
     database: Arc<LoginDatabase>,
+    /// Connection to the lobby server, which knows every world server.
+    lobby: Arc<WorldChannel>,
 }
 
 #[derive(Deserialize)]
@@ -88,13 +122,41 @@ struct Input {
     _STORED_: String,
     sqexid: String,
     password: String,
+    #[serde(default)]
+    otppw: String,
+}
+
+/// Returns the address of the client that sent a request, looking past trusted reverse proxies.
+fn get_client_ip(addr: &SocketAddr, headers: &HeaderMap) -> String {
+    let trusted_proxies: Vec<IpAddr> = get_config()
+        .login
+        .trusted_proxies
+        .iter()
+        .filter_map(|ip| ip.parse().ok())
+        .collect();
+    let forwarded_for = headers
+        .get("x-forwarded-for")
+        .and_then(|value| value.to_str().ok());
+
+    client_ip(addr.ip().to_canonical(), forwarded_for, &trusted_proxies).to_string()
 }
 
 async fn login_send(
     State(state): State<LoginServerState>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
     Form(input): Form<Input>,
 ) -> Html<String> {
-    let user = state.database.login_user(&input.sqexid, &input.password);
+    let user = state
+        .database
+        .attempt_login(
+            &get_client_ip(&addr, &headers),
+            &input.sqexid,
+            &input.password,
+            &input.otppw,
+            SessionKind::Game,
+        )
+        .await;
     match user {
         Ok(session_id) => Html(format!(
             "window.external.user(\"login=auth,ok,sid,{session_id},terms,1,region,2,etmadd,0,playable,1,ps3pkg,0,maxex,5,product,1\");"
@@ -102,12 +164,29 @@ async fn login_send(
         Err(err) => {
             // TODO: see what the official error messages are
             match err {
-                LoginError::WrongUsername => {
-                    Html("window.external.user(\"login=auth,ng,err,Wrong Username\");".to_string())
-                }
-                LoginError::WrongPassword => {
-                    Html("window.external.user(\"login=auth,ng,err,Wrong Password\");".to_string())
+                // the same message for both, so it can't be used to find out which usernames exist
+                LoginError::WrongUsername | LoginError::WrongPassword => Html(
+                    "window.external.user(\"login=auth,ng,err,Wrong Username or Password\");"
+                        .to_string(),
+                ),
+                LoginError::WrongOtp => Html(
+                    "window.external.user(\"login=auth,ng,err,Wrong One-Time Password\");"
+                        .to_string(),
+                ),
+                LoginError::Banned(ban) => {
+                    // the reason is written by a GM, and must not break out of the reply
+                    let description: String = ban
+                        .describe()
+                        .chars()
+                        .filter(|c| !matches!(c, ',' | '"' | '\\'))
+                        .collect();
+                    Html(format!(
+                        "window.external.user(\"login=auth,ng,err,This account is banned: {description}\");"
+                    ))
                 }
+                LoginError::LockedOut(remaining) => Html(format!(
+                    "window.external.user(\"login=auth,ng,err,Too many failed logins. Please try again in {remaining} seconds.\");"
+                )),
                 LoginError::InternalError => Html(
                     "window.external.user(\"login=auth,ng,err,Internal Server Error\");"
                         .to_string(),
@@ -118,41 +197,90 @@ async fn login_send(
 }
 
 #[derive(Deserialize, Debug)]
-#[allow(dead_code)]
 struct RegisterInput {
     username: Option<String>,
     password: Option<String>,
+    invite: Option<String>,
 }
 
 async fn do_register(
     jar: CookieJar,
     State(state): State<LoginServerState>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
     Form(input): Form<RegisterInput>,
-) -> (CookieJar, Redirect) {
-    tracing::info!(
-        "Registering with {:#?} and {:#?}!",
-        input.username,
-        input.password
-    );
-
-    let Some(username) = input.username else {
-        panic!("Expected username!");
-    };
-    let Some(password) = input.password else {
-        panic!("Expected password!");
+) -> Result<(CookieJar, Redirect), Html<String>> {
+    tracing::info!("Registering with {:#?}!", input.username);
+
+    let username = input.username.unwrap_or_default();
+    let password = input.password.unwrap_or_default();
+
+    let invite = match get_config().login.registration {
+        RegistrationMode::Open => None,
+        RegistrationMode::InviteOnly => Some(input.invite.unwrap_or_default()),
+        RegistrationMode::Closed => {
+            return Err(render_register_page(Some(
+                &RegistrationError::Closed.to_string(),
+            )));
+        }
     };
 
-    state.database.add_user(&username, &password);
+    // guessing invite codes is throttled like guessing passwords
+    let ip = get_client_ip(&addr, &headers);
+    if let Some(remaining) = state.database.get_lockout(&ip, &username) {
+        return Err(render_register_page(Some(&lockout_message(remaining))));
+    }
+
+    state
+        .database
+        .add_user(&username, &password, invite.as_deref().map(str::trim))
+        .await
+        .map_err(|err| {
+            if err == RegistrationError::InvalidInvite {
+                state
+                    .database
+                    .record_failed_login(&ip, &username, "Wrong invite code");
+            }
+            render_register_page(Some(&err.to_string()))
+        })?;
 
     // redirect to account management page
-    let sid = state.database.login_user(&username, &password).unwrap();
+    let sid = state
+        .database
+        .login_user(&username, &password, "", SessionKind::Web)
+        .await
+        .map_err(|_| render_register_page(Some(&RegistrationError::InternalError.to_string())))?;
 
-    let cookie = Cookie::build(("cis_sessid", sid))
-        .path("/")
-        .secure(false)
-        .expires(Expiration::Session)
-        .http_only(true);
-    (jar.add(cookie), Redirect::to("/account/app/svc/manage"))
+    Ok((
+        jar.add(session_cookie(sid)),
+        Redirect::to("/account/app/svc/manage"),
+    ))
+}
+
+/// Rejects requests to the private endpoints that don't come from one of our servers.
+async fn require_private_api_token(
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    request: Request,
+    next: Next,
+) -> Response {
+    // without a secret, every token would be valid
+    let secret = get_config().shared_secret;
+    let authorized = !secret.is_empty()
+        && request
+            .headers()
+            .get(PRIVATE_API_HEADER)
+            .and_then(|value| value.to_str().ok())
+            .is_some_and(|token| verify_private_api_token(&secret, token));
+
+    if !authorized {
+        tracing::warn!(
+            "{addr} tried to use {} without a valid token, is shared_secret the same on every server?",
+            request.uri().path()
+        );
+        return StatusCode::UNAUTHORIZED.into_response();
+    }
+
+    next.run(request).await
 }
 
 #[derive(Deserialize)]
@@ -169,92 +297,928 @@ async fn check_session(
     serde_json::to_string(&accounts).unwrap_or(String::new())
 }
 
-async fn login() -> Html<String> {
+/// Shown on the website when the IP address or username has too many failed attempts.
+fn lockout_message(remaining: u32) -> String {
+    format!("Too many failed logins. Please try again in {remaining} seconds.")
+}
+
+/// Renders the login page, showing `error` above the form if there is one.
+fn render_login_page(error: Option<&str>) -> Html<String> {
     let config = get_config();
     let environment = setup_default_environment();
     let template = environment.get_template("login.html").unwrap();
     Html(
         template
-            .render(context! { web_server_name => config.web.server_name })
+            .render(context! { web_server_name => config.web.server_name, error => error })
             .unwrap(),
     )
 }
 
-async fn register() -> Html<String> {
+async fn login() -> Html<String> {
+    render_login_page(None)
+}
+
+/// Renders the registration page, showing `error` above the form if there is one.
+fn render_register_page(error: Option<&str>) -> Html<String> {
     let config = get_config();
     let environment = setup_default_environment();
     let template = environment.get_template("register.html").unwrap();
     Html(
         template
-            .render(context! { web_server_name => config.web.server_name })
+            .render(context! {
+                web_server_name => config.web.server_name,
+                error => error,
+                registration => config.login.registration,
+            })
             .unwrap(),
     )
 }
 
+async fn register() -> Html<String> {
+    render_register_page(None)
+}
+
 #[derive(Deserialize, Debug)]
 #[allow(dead_code)]
 struct LoginInput {
     username: Option<String>,
     password: Option<String>,
+    otp: Option<String>,
 }
 
 async fn do_login(
     State(state): State<LoginServerState>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
     jar: CookieJar,
     Form(input): Form<LoginInput>,
-) -> (CookieJar, Redirect) {
+) -> Result<(CookieJar, Redirect), Html<String>> {
     tracing::info!("{:#?} logging in!", input.username,);
 
-    let Some(username) = input.username else {
-        panic!("Expected username!");
-    };
-    let Some(password) = input.password else {
-        panic!("Expected password!");
-    };
-
-    let sid = state.database.login_user(&username, &password).unwrap();
+    let username = input.username.unwrap_or_default();
+    let password = input.password.unwrap_or_default();
+    let otp = input.otp.unwrap_or_default();
 
-    let cookie = Cookie::build(("cis_sessid", sid))
-        .path("/")
-        .secure(false)
-        .expires(Expiration::Session)
-        .http_only(true);
+    let sid = state
+        .database
+        .attempt_login(
+            &get_client_ip(&addr, &headers),
+            &username,
+            &password,
+            &otp,
+            SessionKind::Web,
+        )
+        .await
+        .map_err(|err| {
+            render_login_page(Some(&match err {
+                LoginError::WrongUsername | LoginError::WrongPassword => {
+                    "The username or password is wrong.".to_string()
+                }
+                LoginError::WrongOtp => "The one-time password is missing or wrong.".to_string(),
+                LoginError::Banned(ban) => format!("This account is banned: {}", ban.describe()),
+                LoginError::LockedOut(remaining) => lockout_message(remaining),
+                LoginError::InternalError => {
+                    "Something went wrong, please try again later.".to_string()
+                }
+            }))
+        })?;
 
-    (jar.add(cookie), Redirect::to("/account/app/svc/manage"))
+    Ok((
+        jar.add(session_cookie(sid)),
+        Redirect::to("/account/app/svc/manage"),
+    ))
 }
 
 async fn account(State(state): State<LoginServerState>, jar: CookieJar) -> Html<String> {
-    if let Some(session_id) = jar.get("cis_sessid") {
-        let user_id = state.database.get_user_id(session_id.value());
-        let username = state.database.get_username(user_id);
-
+    if let Some(user) = get_logged_in_user(&state, &jar) {
         let environment = setup_default_environment();
         let template = environment.get_template("account.html").unwrap();
-        Html(template.render(context! { username => username }).unwrap())
+        Html(template.render(account_context(&state, &user)).unwrap())
     } else {
         Html("You need to be logged in!".to_string())
     }
 }
 
-async fn logout(jar: CookieJar) -> (CookieJar, Redirect) {
+async fn logout(State(state): State<LoginServerState>, jar: CookieJar) -> (CookieJar, Redirect) {
     let config = get_config();
-    // TODO: remove session from database
+    if let Some(session_id) = jar.get("cis_sessid") {
+        state.database.remove_session(session_id.value());
+    }
     (
         jar.remove("cis_sessid"),
         Redirect::to(&format!("http://{}/", config.web.server_name)),
     )
 }
 
-async fn change_password() -> Html<String> {
-    // TODO: actually change password
+/// A user logged in on the website.
+struct WebUser {
+    id: u32,
+    session_id: String,
+}
+
+impl WebUser {
+    /// Returns whether `token` came from a form we rendered for this session, and not from another site.
+    fn check_csrf_token(&self, token: &str) -> bool {
+        verify_csrf_token(&self.session_id, token)
+    }
+}
+
+/// Returns the logged in user, if their session is still valid.
+fn get_logged_in_user(state: &LoginServerState, jar: &CookieJar) -> Option<WebUser> {
+    let session_id = jar.get("cis_sessid")?.value().to_string();
+    let id = state.database.get_user_id(&session_id)?;
+
+    Some(WebUser { id, session_id })
+}
+
+/// Builds the session cookie for the website. Other sites can't make the browser send it along.
+fn session_cookie(sid: String) -> Cookie<'static> {
+    Cookie::build(("cis_sessid", sid))
+        .path("/")
+        .secure(false)
+        .expires(Expiration::Session)
+        .http_only(true)
+        .same_site(SameSite::Strict)
+        .build()
+}
+
+/// Shown when a form was sent without a valid CSRF token, e.g. from another site.
+const INVALID_CSRF_TOKEN: &str = "The page expired, please try again.";
+
+/// Returns what every account page needs to render the sidebar, and the CSRF token for it's forms.
+fn account_context(state: &LoginServerState, user: &WebUser) -> minijinja::Value {
+    context! {
+        username => state.database.get_username(user.id),
+        is_gm => state.database.get_role(user.id) >= AccountRole::Gm,
+        csrf_token => csrf_token(&user.session_id),
+    }
+}
+
+/// Renders an account page, showing `error` above the form if there is one.
+fn render_account_page(
+    state: &LoginServerState,
+    user: &WebUser,
+    template: &str,
+    error: Option<&str>,
+) -> Html<String> {
+    let environment = setup_default_environment();
+    let template = environment.get_template(template).unwrap();
+    Html(
+        template
+            .render(context! { error => error, ..account_context(state, user) })
+            .unwrap(),
+    )
+}
+
+async fn change_password(
+    State(state): State<LoginServerState>,
+    jar: CookieJar,
+) -> Result<Html<String>, Redirect> {
+    let user = get_logged_in_user(&state, &jar).ok_or(Redirect::to("/oauth/oa/oauthlogin"))?;
+
+    Ok(render_account_page(
+        &state,
+        &user,
+        "changepassword.html",
+        None,
+    ))
+}
+
+#[derive(Deserialize, Debug)]
+struct ChangePasswordInput {
+    old_password: String,
+    new_password: String,
+    /// Has to match the one rendered with the form.
+    #[serde(default)]
+    csrf_token: String,
+}
+
+async fn do_change_password(
+    State(state): State<LoginServerState>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    jar: CookieJar,
+    Form(input): Form<ChangePasswordInput>,
+) -> Result<(CookieJar, Redirect), Html<String>> {
+    let Some(user) = get_logged_in_user(&state, &jar) else {
+        return Ok((jar, Redirect::to("/oauth/oa/oauthlogin")));
+    };
+
+    if !user.check_csrf_token(&input.csrf_token) {
+        return Err(render_account_page(
+            &state,
+            &user,
+            "changepassword.html",
+            Some(INVALID_CSRF_TOKEN),
+        ));
+    }
+
+    // a stolen session can't be used to guess the password
+    let ip = get_client_ip(&addr, &headers);
+    let username = state.database.get_username(user.id);
+    if let Some(remaining) = state.database.get_lockout(&ip, &username) {
+        return Err(render_account_page(
+            &state,
+            &user,
+            "changepassword.html",
+            Some(&lockout_message(remaining)),
+        ));
+    }
+
+    if !state
+        .database
+        .check_password(user.id, &input.old_password)
+        .await
+    {
+        state
+            .database
+            .record_failed_login(&ip, &username, "Wrong password");
+        return Err(render_account_page(
+            &state,
+            &user,
+            "changepassword.html",
+            Some("The old password is wrong."),
+        ));
+    }
+
+    if let Err(err) = validate_password(&input.new_password) {
+        return Err(render_account_page(
+            &state,
+            &user,
+            "changepassword.html",
+            Some(&err.to_string()),
+        ));
+    }
+
+    if state
+        .database
+        .set_password(user.id, &input.new_password)
+        .await
+        .is_err()
+    {
+        return Err(render_account_page(
+            &state,
+            &user,
+            "changepassword.html",
+            Some("Failed to change the password, please try again later."),
+        ));
+    }
+
+    tracing::info!("Changed the password of {}", user.id);
+
+    // every session was invalidated, including this one
+    Ok((
+        jar.remove("cis_sessid"),
+        Redirect::to("/oauth/oa/oauthlogin"),
+    ))
+}
+
+/// Renders the two-factor authentication page. `recovery_codes` is only given right after enabling it.
+fn render_otp_page(
+    state: &LoginServerState,
+    user: &WebUser,
+    error: Option<&str>,
+    recovery_codes: Option<Vec<String>>,
+) -> Html<String> {
+    let username = state.database.get_username(user.id);
+    let enabled = state.database.is_otp_enabled(user.id);
+
+    let mut secret = None;
+    let mut qr_code = None;
+    if !enabled && let Some(new_secret) = state.database.begin_otp_enrollment(user.id) {
+        qr_code = generate_qr_code(&new_secret, &username);
+        secret = Some(new_secret);
+    }
+
+    let environment = setup_default_environment();
+    let template = environment.get_template("otp.html").unwrap();
+    Html(
+        template
+            .render(context! {
+                error => error,
+                enabled => enabled,
+                secret => secret,
+                qr_code => qr_code,
+                recovery_codes => recovery_codes,
+                ..account_context(state, user)
+            })
+            .unwrap(),
+    )
+}
+
+async fn otp(
+    State(state): State<LoginServerState>,
+    jar: CookieJar,
+) -> Result<Html<String>, Redirect> {
+    let user = get_logged_in_user(&state, &jar).ok_or(Redirect::to("/oauth/oa/oauthlogin"))?;
+
+    Ok(render_otp_page(&state, &user, None, None))
+}
+
+#[derive(Deserialize, Debug)]
+struct OtpInput {
+    action: String,
+    otp: Option<String>,
+    password: Option<String>,
+    /// Has to match the one rendered with the form.
+    #[serde(default)]
+    csrf_token: String,
+}
+
+async fn do_otp(
+    State(state): State<LoginServerState>,
+    jar: CookieJar,
+    Form(input): Form<OtpInput>,
+) -> Result<Html<String>, Redirect> {
+    let user = get_logged_in_user(&state, &jar).ok_or(Redirect::to("/oauth/oa/oauthlogin"))?;
+
+    if !user.check_csrf_token(&input.csrf_token) {
+        return Ok(render_otp_page(
+            &state,
+            &user,
+            Some(INVALID_CSRF_TOKEN),
+            None,
+        ));
+    }
+
+    match input.action.as_str() {
+        "enable" => {
+            let otp = input.otp.unwrap_or_default();
+            match state.database.enable_otp(user.id, &otp).await {
+                Some(recovery_codes) => {
+                    Ok(render_otp_page(&state, &user, None, Some(recovery_codes)))
+                }
+                None => Ok(render_otp_page(
+                    &state,
+                    &user,
+                    Some("The one-time password is wrong."),
+                    None,
+                )),
+            }
+        }
+        "disable" => {
+            let password = input.password.unwrap_or_default();
+            if state.database.check_password(user.id, &password).await {
+                state.database.disable_otp(user.id);
+                Ok(render_otp_page(&state, &user, None, None))
+            } else {
+                Ok(render_otp_page(
+                    &state,
+                    &user,
+                    Some("The password is wrong."),
+                    None,
+                ))
+            }
+        }
+        _ => Ok(render_otp_page(&state, &user, None, None)),
+    }
+}
+
+async fn cancel_account(
+    State(state): State<LoginServerState>,
+    jar: CookieJar,
+) -> Result<Html<String>, Redirect> {
+    let user = get_logged_in_user(&state, &jar).ok_or(Redirect::to("/oauth/oa/oauthlogin"))?;
+
+    Ok(render_account_page(
+        &state,
+        &user,
+        "cancelaccount.html",
+        None,
+    ))
+}
+
+/// Asks the lobby server to delete the characters of `service_account_id` on every world server.
+/// Returns false if the lobby or any of the worlds couldn't be reached, so the account can be cancelled again later.
+async fn remove_service_account_characters(
+    state: &LoginServerState,
+    service_account_id: u32,
+) -> bool {
+    let response = state
+        .lobby
+        .request(CustomIpcSegment {
+            op_code: CustomIpcType::RemoveServiceAccount,
+            data: CustomIpcData::RemoveServiceAccount { service_account_id },
+            ..Default::default()
+        })
+        .await;
+
+    match response.map(|response| response.data) {
+        Some(CustomIpcData::ServiceAccountRemoved {
+            deleted_characters,
+            unreachable_worlds: 0,
+        }) => {
+            tracing::info!("Deleted {deleted_characters} characters of {service_account_id}");
+            true
+        }
+        Some(CustomIpcData::ServiceAccountRemoved {
+            unreachable_worlds, ..
+        }) => {
+            tracing::warn!(
+                "Failed to delete the characters of {service_account_id}, {unreachable_worlds} worlds couldn't be reached"
+            );
+            false
+        }
+        _ => {
+            tracing::warn!(
+                "Failed to delete the characters of {service_account_id}, is the lobby server running?"
+            );
+            false
+        }
+    }
+}
+
+/// Renders the service account management page, showing `error` above the list if there is one.
+fn render_service_accounts_page(
+    state: &LoginServerState,
+    user: &WebUser,
+    error: Option<&str>,
+) -> Html<String> {
+    let service_accounts = state.database.get_service_accounts(user.id);
+
+    let environment = setup_default_environment();
+    let template = environment.get_template("serviceaccounts.html").unwrap();
+    Html(
+        template
+            .render(context! {
+                error => error,
+                service_accounts => service_accounts,
+                ..account_context(state, user)
+            })
+            .unwrap(),
+    )
+}
+
+async fn service_accounts(
+    State(state): State<LoginServerState>,
+    jar: CookieJar,
+) -> Result<Html<String>, Redirect> {
+    let user = get_logged_in_user(&state, &jar).ok_or(Redirect::to("/oauth/oa/oauthlogin"))?;
+
+    Ok(render_service_accounts_page(&state, &user, None))
+}
+
+#[derive(Deserialize, Debug)]
+struct ServiceAccountInput {
+    action: String,
+    id: Option<u32>,
+    name: Option<String>,
+    password: Option<String>,
+    /// Has to match the one rendered with the form.
+    #[serde(default)]
+    csrf_token: String,
+}
+
+async fn do_service_accounts(
+    State(state): State<LoginServerState>,
+    jar: CookieJar,
+    Form(input): Form<ServiceAccountInput>,
+) -> Result<Html<String>, Redirect> {
+    let user = get_logged_in_user(&state, &jar).ok_or(Redirect::to("/oauth/oa/oauthlogin"))?;
+
+    if !user.check_csrf_token(&input.csrf_token) {
+        return Ok(render_service_accounts_page(
+            &state,
+            &user,
+            Some(INVALID_CSRF_TOKEN),
+        ));
+    }
+
+    if input.action == "add" {
+        let error = state
+            .database
+            .add_service_account(user.id)
+            .is_none()
+            .then_some("You can't have any more service accounts.");
+        return Ok(render_service_accounts_page(&state, &user, error));
+    }
+
+    // every other action is done to an existing service account
+    let Some(service_account_id) = input
+        .id
+        .filter(|id| state.database.owns_service_account(user.id, *id))
+    else {
+        return Ok(render_service_accounts_page(
+            &state,
+            &user,
+            Some("That service account doesn't exist."),
+        ));
+    };
+
+    let error = match input.action.as_str() {
+        "rename" => {
+            let name = input.name.unwrap_or_default();
+            (!state
+                .database
+                .rename_service_account(service_account_id, name.trim()))
+            .then_some("That name is too long.")
+        }
+        "enable" => {
+            state
+                .database
+                .set_service_account_enabled(service_account_id, true);
+            None
+        }
+        "disable" => {
+            state
+                .database
+                .set_service_account_enabled(service_account_id, false);
+            None
+        }
+        "delete" => {
+            let password = input.password.unwrap_or_default();
+            if !state.database.check_password(user.id, &password).await {
+                Some("The password is wrong.")
+            } else if !remove_service_account_characters(&state, service_account_id).await {
+                Some("Some of the world servers couldn't be reached, please try again later.")
+            } else {
+                state.database.remove_service_account(service_account_id);
+                None
+            }
+        }
+        _ => None,
+    };
+
+    Ok(render_service_accounts_page(&state, &user, error))
+}
+
+/// Number of failed logins shown on the moderation page.
+const LOGIN_AUDIT_PAGE_SIZE: u32 = 100;
+
+/// Renders the moderation page, showing `error` above the list if there is one.
+fn render_moderation_page(
+    state: &LoginServerState,
+    user: &WebUser,
+    error: Option<&str>,
+) -> Html<String> {
+    let is_admin = state.database.get_role(user.id) >= AccountRole::Admin;
+    let (invites, login_audit) = if is_admin {
+        (
+            state.database.get_unused_invites(),
+            state.database.get_login_audit(LOGIN_AUDIT_PAGE_SIZE),
+        )
+    } else {
+        (Vec::new(), Vec::new())
+    };
+
     let environment = setup_default_environment();
-    let template = environment.get_template("changepassword.html").unwrap();
-    Html(template.render(context! {}).unwrap())
+    let template = environment.get_template("moderation.html").unwrap();
+    Html(
+        template
+            .render(context! {
+                error => error,
+                bans => state.database.get_active_bans(),
+                is_admin => is_admin,
+                invites => invites,
+                login_audit => login_audit,
+                ..account_context(state, user)
+            })
+            .unwrap(),
+    )
+}
+
+/// Returns the logged in user, if they are allowed to moderate.
+fn get_logged_in_gm(state: &LoginServerState, jar: &CookieJar) -> Option<WebUser> {
+    get_logged_in_user(state, jar)
+        .filter(|user| state.database.get_role(user.id) >= AccountRole::Gm)
+}
+
+async fn moderation(
+    State(state): State<LoginServerState>,
+    jar: CookieJar,
+) -> Result<Html<String>, Redirect> {
+    let user = get_logged_in_gm(&state, &jar).ok_or(Redirect::to("/account/app/svc/manage"))?;
+
+    Ok(render_moderation_page(&state, &user, None))
+}
+
+#[derive(Deserialize, Debug)]
+struct ModerationInput {
+    action: String,
+    username: Option<String>,
+    service_account_id: Option<String>,
+    reason: Option<String>,
+    days: Option<String>,
+    role: Option<String>,
+    /// Has to match the one rendered with the form.
+    #[serde(default)]
+    csrf_token: String,
+}
+
+async fn do_moderation(
+    State(state): State<LoginServerState>,
+    jar: CookieJar,
+    Form(input): Form<ModerationInput>,
+) -> Result<Html<String>, Redirect> {
+    let user = get_logged_in_gm(&state, &jar).ok_or(Redirect::to("/account/app/svc/manage"))?;
+
+    if !user.check_csrf_token(&input.csrf_token) {
+        return Ok(render_moderation_page(
+            &state,
+            &user,
+            Some(INVALID_CSRF_TOKEN),
+        ));
+    }
+
+    let role = state.database.get_role(user.id);
+
+    let ban = Ban {
+        reason: input
+            .reason
+            .filter(|reason| !reason.trim().is_empty())
+            .unwrap_or_else(|| "No reason given".to_string()),
+        expires: input
+            .days
+            .and_then(|days| days.trim().parse::<u32>().ok())
+            .map(|days| timestamp_secs().saturating_add(days.saturating_mul(24 * 60 * 60))),
+    };
+
+    // users can only be moderated by someone with a higher role
+    let target_user = input
+        .username
+        .and_then(|username| state.database.find_user_id(username.trim()))
+        .filter(|target| state.database.get_role(*target) < role);
+    let target_service_account = input
+        .service_account_id
+        .and_then(|id| id.trim().parse::<u32>().ok())
+        .filter(|id| {
+            state
+                .database
+                .get_service_account_role(*id)
+                .is_some_and(|target_role| target_role < role)
+        });
+    let new_role = input.role.as_deref().and_then(AccountRole::from_name);
+
+    const NO_USER: &str = "That user doesn't exist, or you can't moderate them.";
+    const NO_SERVICE_ACCOUNT: &str =
+        "That service account doesn't exist, or you can't moderate it.";
+
+    let error = match input.action.as_str() {
+        "ban" => target_user
+            .map(|target| state.database.ban_user(target, &ban))
+            .map_or(Some(NO_USER), |_| None),
+        "unban" => target_user
+            .map(|target| state.database.unban_user(target))
+            .map_or(Some(NO_USER), |_| None),
+        "setrole" => match (target_user, new_role) {
+            (None, _) => Some(NO_USER),
+            (Some(target), Some(new_role)) if role >= AccountRole::Admin => {
+                state.database.set_role(target, new_role);
+                None
+            }
+            _ => Some("You can't give out that role."),
+        },
+        "suspend" => target_service_account
+            .map(|target| state.database.suspend_service_account(target, &ban))
+            .map_or(Some(NO_SERVICE_ACCOUNT), |_| None),
+        "unsuspend" => target_service_account
+            .map(|target| state.database.unsuspend_service_account(target))
+            .map_or(Some(NO_SERVICE_ACCOUNT), |_| None),
+        "invite" if role >= AccountRole::Admin => state
+            .database
+            .create_invite(user.id)
+            .map_or(Some("Failed to create an invite code."), |_| None),
+        _ => None,
+    };
+
+    Ok(render_moderation_page(&state, &user, error))
+}
+
+#[derive(Deserialize)]
+struct ServiceAccountParams {
+    service_account_id: u32,
+}
+
+async fn check_suspension(
+    State(state): State<LoginServerState>,
+    Query(params): Query<ServiceAccountParams>,
+) -> String {
+    let suspension = state.database.get_suspension(params.service_account_id);
+    serde_json::to_string(&suspension).unwrap_or(String::new())
+}
+
+async fn check_role(
+    State(state): State<LoginServerState>,
+    Query(params): Query<ServiceAccountParams>,
+) -> String {
+    let role = state
+        .database
+        .get_service_account_role(params.service_account_id);
+    serde_json::to_string(&role).unwrap_or(String::new())
+}
+
+#[derive(Deserialize, Debug)]
+struct CancelAccountInput {
+    password: String,
+    /// Has to match the one rendered with the form.
+    #[serde(default)]
+    csrf_token: String,
+}
+
+async fn do_cancel_account(
+    State(state): State<LoginServerState>,
+    jar: CookieJar,
+    Form(input): Form<CancelAccountInput>,
+) -> Result<(CookieJar, Redirect), Html<String>> {
+    let Some(user) = get_logged_in_user(&state, &jar) else {
+        return Ok((jar, Redirect::to("/oauth/oa/oauthlogin")));
+    };
+
+    if !user.check_csrf_token(&input.csrf_token) {
+        return Err(render_account_page(
+            &state,
+            &user,
+            "cancelaccount.html",
+            Some(INVALID_CSRF_TOKEN),
+        ));
+    }
+
+    if !state
+        .database
+        .check_password(user.id, &input.password)
+        .await
+    {
+        return Err(render_account_page(
+            &state,
+            &user,
+            "cancelaccount.html",
+            Some("The password is wrong."),
+        ));
+    }
+
+    // characters are removed first, so nothing is left behind if a world is unreachable
+    for service_account in state.database.get_service_accounts(user.id) {
+        if !remove_service_account_characters(&state, service_account.id).await {
+            return Err(render_account_page(
+                &state,
+                &user,
+                "cancelaccount.html",
+                Some("Some of the world servers couldn't be reached, please try again later."),
+            ));
+        }
+    }
+
+    state.database.remove_user(user.id);
+
+    let config = get_config();
+    Ok((
+        jar.remove("cis_sessid"),
+        Redirect::to(&format!("http://{}/", config.web.server_name)),
+    ))
+}
+
+type ApiResult<T> = Result<Json<T>, (StatusCode, Json<ApiError>)>;
+
+fn api_error(err: impl Into<ApiError>) -> (StatusCode, Json<ApiError>) {
+    let err = err.into();
+    let status = StatusCode::from_u16(err.status_code()).unwrap_or(StatusCode::BAD_REQUEST);
+    (status, Json(err))
 }
 
-async fn cancel_account(jar: CookieJar) -> (CookieJar, Redirect) {
-    // TODO: actually delete account
-    (jar.remove("cis_sessid"), Redirect::to("/"))
+/// Returns the user of the SID sent in the `Authorization: Bearer <sid>` header.
+fn get_api_user(state: &LoginServerState, headers: &HeaderMap) -> Result<u32, ApiError> {
+    headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .and_then(|sid| state.database.get_user_id(sid.trim()))
+        .ok_or_else(ApiError::invalid_session)
+}
+
+#[derive(Deserialize, Debug)]
+struct ApiLoginInput {
+    username: String,
+    password: String,
+    #[serde(default)]
+    otp: String,
+}
+
+#[derive(Serialize)]
+struct ApiSession {
+    sid: String,
+}
+
+async fn api_login(
+    State(state): State<LoginServerState>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    Json(input): Json<ApiLoginInput>,
+) -> ApiResult<ApiSession> {
+    let sid = state
+        .database
+        .attempt_login(
+            &get_client_ip(&addr, &headers),
+            &input.username,
+            &input.password,
+            &input.otp,
+            SessionKind::Game,
+        )
+        .await
+        .map_err(api_error)?;
+
+    Ok(Json(ApiSession { sid }))
+}
+
+#[derive(Deserialize, Debug)]
+struct ApiRegisterInput {
+    username: String,
+    password: String,
+    invite: Option<String>,
+}
+
+async fn api_register(
+    State(state): State<LoginServerState>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    Json(input): Json<ApiRegisterInput>,
+) -> ApiResult<ApiSession> {
+    tracing::info!("Registering {} through the API!", input.username);
+
+    let invite = match get_config().login.registration {
+        RegistrationMode::Open => None,
+        RegistrationMode::InviteOnly => Some(input.invite.unwrap_or_default()),
+        RegistrationMode::Closed => return Err(api_error(RegistrationError::Closed)),
+    };
+
+    // guessing invite codes is throttled like guessing passwords
+    let ip = get_client_ip(&addr, &headers);
+    if let Some(remaining) = state.database.get_lockout(&ip, &input.username) {
+        return Err(api_error(LoginError::LockedOut(remaining)));
+    }
+
+    state
+        .database
+        .add_user(
+            &input.username,
+            &input.password,
+            invite.as_deref().map(str::trim),
+        )
+        .await
+        .map_err(|err| {
+            if err == RegistrationError::InvalidInvite {
+                state
+                    .database
+                    .record_failed_login(&ip, &input.username, "Wrong invite code");
+            }
+            api_error(err)
+        })?;
+
+    let sid = state
+        .database
+        .login_user(&input.username, &input.password, "", SessionKind::Game)
+        .await
+        .map_err(api_error)?;
+
+    Ok(Json(ApiSession { sid }))
+}
+
+async fn api_service_accounts(
+    State(state): State<LoginServerState>,
+    headers: HeaderMap,
+) -> ApiResult<Vec<ServiceAccountInfo>> {
+    let user_id = get_api_user(&state, &headers).map_err(api_error)?;
+
+    Ok(Json(state.database.get_service_accounts(user_id)))
+}
+
+async fn api_change_password(
+    State(state): State<LoginServerState>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    Json(input): Json<ChangePasswordInput>,
+) -> Result<StatusCode, (StatusCode, Json<ApiError>)> {
+    let user_id = get_api_user(&state, &headers).map_err(api_error)?;
+
+    // a stolen session can't be used to guess the password
+    let ip = get_client_ip(&addr, &headers);
+    let username = state.database.get_username(user_id);
+    if let Some(remaining) = state.database.get_lockout(&ip, &username) {
+        return Err(api_error(LoginError::LockedOut(remaining)));
+    }
+
+    if !state
+        .database
+        .check_password(user_id, &input.old_password)
+        .await
+    {
+        state
+            .database
+            .record_failed_login(&ip, &username, "Wrong password");
+        return Err(api_error(LoginError::WrongPassword));
+    }
+
+    validate_password(&input.new_password).map_err(api_error)?;
+
+    state
+        .database
+        .set_password(user_id, &input.new_password)
+        .await
+        .map_err(api_error)?;
+
+    tracing::info!("Changed the password of {user_id} through the API");
+
+    // like on the website, every session (including the one used here) is now invalid
+    Ok(StatusCode::NO_CONTENT)
 }
 
 #[tokio::main]
@@ -263,17 +1227,73 @@ async fn main() {
 
     let state = LoginServerState {
         database: Arc::new(LoginDatabase::new()),
+        lobby: Arc::new(WorldChannel::new(
+            get_config().lobby.get_public_socketaddr(),
+        )),
     };
 
-    let cors = CorsLayer::new().allow_origin(Any);
+    for username in &get_config().login.admins {
+        match state.database.find_user_id(username) {
+            Some(user_id) => state.database.set_role(user_id, AccountRole::Admin),
+            None => tracing::warn!("Can't make {username} an admin, they don't exist!"),
+        }
+    }
+
+    let unmigrated_users = state.database.get_unmigrated_users();
+    if !unmigrated_users.is_empty() {
+        tracing::warn!(
+            "These accounts still have a plaintext password, it will be hashed the next time they log in: {}",
+            unmigrated_users.join(", ")
+        );
+    }
+
+    // periodically remove expired sessions
+    {
+        let database = state.database.clone();
+        let interval = get_config().login.session_cleanup_interval;
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(std::time::Duration::from_secs(interval));
+            loop {
+                interval.tick().await;
+
+                let removed = database.cleanup_sessions();
+                if removed > 0 {
+                    tracing::info!("Removed {removed} expired sessions");
+                }
+
+                database.cleanup_login_attempts();
+            }
+        });
+    }
+
+    if get_config().shared_secret.is_empty() {
+        tracing::warn!(
+            "No shared secret is configured, so the lobby can't use the private API! Please set shared_secret in the config."
+        );
+    }
+
+    // private server<->server API, only usable by servers knowing the shared secret
+    let private_api = Router::new()
+        .route("/_private/service_accounts", get(check_session))
+        .route("/_private/suspension", get(check_suspension))
+        .route("/_private/role", get(check_role))
+        .route_layer(middleware::from_fn(require_private_api_token))
+        .with_state(state.clone());
+
+    // only our own website can call us from a browser, launchers don't care about CORS
+    let website = format!("http://{}", get_config().web.server_name);
+    let cors =
+        CorsLayer::new().allow_origin(AllowOrigin::list(website.parse::<HeaderValue>().ok()));
 
     let app = Router::new()
         // retail API
         .route("/oauth/ffxiv/login/top", get(top))
         .route("/oauth/ffxiv/login/login.send", post(login_send))
-        // private server<->server API
-        // TODO: make these actually private
-        .route("/_private/service_accounts", get(check_session))
+        // JSON API for third-party launchers
+        .route("/api/v1/login", post(api_login))
+        .route("/api/v1/register", post(api_register))
+        .route("/api/v1/service_accounts", get(api_service_accounts))
+        .route("/api/v1/password", post(api_change_password))
         // public website
         .route("/oauth/oa/oauthlogin", get(login))
         .route("/oauth/oa/oauthlogin", post(do_login))
@@ -282,15 +1302,33 @@ async fn main() {
         .route("/account/app/svc/manage", get(account))
         .route("/account/app/svc/logout", get(logout))
         .route("/account/app/svc/mbrPasswd", get(change_password))
+        .route("/account/app/svc/mbrPasswd", post(do_change_password))
+        .route("/account/app/svc/mbrServiceAccounts", get(service_accounts))
+        .route(
+            "/account/app/svc/mbrServiceAccounts",
+            post(do_service_accounts),
+        )
+        .route("/account/app/svc/moderation", get(moderation))
+        .route("/account/app/svc/moderation", post(do_moderation))
+        .route("/account/app/svc/mbrOtp", get(otp))
+        .route("/account/app/svc/mbrOtp", post(do_otp))
         .route("/account/app/svc/mbrCancel", get(cancel_account))
+        .route("/account/app/svc/mbrCancel", post(do_cancel_account))
         .with_state(state)
         .nest_service("/static", ServeDir::new("resources/static"))
-        .layer(cors);
+        .layer(cors)
+        // merged after the CORS layer, so browsers can't call it from other sites
+        .merge(private_api);
 
     let config = get_config();
 
     let addr = config.login.get_socketaddr();
     tracing::info!("Server started on {addr}");
     let listener = tokio::net::TcpListener::bind(addr).await.unwrap();
-    axum::serve(listener, app).await.unwrap();
+    axum::serve(
+        listener,
+        app.into_make_service_with_connect_info::<SocketAddr>(),
+    )
+    .await
+    .unwrap();
 }
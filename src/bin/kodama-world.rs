@@ -1,15 +1,21 @@
+use std::collections::HashMap;
 use std::net::SocketAddr;
+use std::sync::atomic::AtomicU32;
 use std::sync::{Arc, Mutex};
 use std::time::{Duration, Instant};
 
 use kodama::RECEIVE_BUFFER_SIZE;
+use kodama::common::GameData;
 use kodama::config::get_config;
 use kodama::ipc::zone::ServerZoneIpcSegment;
-use kodama::packet::{ConnectionType, PacketState, SegmentData, send_keep_alive};
+use kodama::login::AccountRole;
+use kodama::packet::{
+    ConnectionType, CustomIpcAuth, PacketBuffer, PacketState, SegmentData, send_keep_alive,
+};
 use kodama::world::ZoneConnection;
 use kodama::world::{
     ClientHandle, FromServer, ServerHandle, ToServer, WorldDatabase, handle_custom_ipc,
-    server_main_loop,
+    handle_rcon_command, lobby_registration_loop, server_main_loop,
 };
 
 use mlua::Lua;
@@ -20,7 +26,7 @@ use tokio::sync::mpsc::{Receiver, UnboundedReceiver, UnboundedSender, channel, u
 use tokio::sync::oneshot;
 use tokio::task::JoinHandle;
 
-fn spawn_main_loop() -> (ServerHandle, JoinHandle<()>) {
+fn spawn_main_loop(population: Arc<AtomicU32>) -> (ServerHandle, JoinHandle<()>) {
     let (send, recv) = channel(64);
 
     let handle = ServerHandle {
@@ -29,7 +35,7 @@ fn spawn_main_loop() -> (ServerHandle, JoinHandle<()>) {
     };
 
     let join = tokio::spawn(async move {
-        let res = server_main_loop(recv).await;
+        let res = server_main_loop(recv, population).await;
         match res {
             Ok(()) => {}
             Err(err) => {
@@ -101,10 +107,10 @@ async fn client_server_loop(
 async fn client_loop(
     mut connection: ZoneConnection,
     mut internal_recv: UnboundedReceiver<FromServer>,
-    client_handle: ClientHandle,
+    mut client_handle: ClientHandle,
 ) {
     let mut buf = vec![0; RECEIVE_BUFFER_SIZE];
-    loop {
+    'conn: loop {
         tokio::select! {
             biased; // client data should always be prioritized
             n = connection.socket.read(&mut buf) => {
@@ -122,11 +128,26 @@ async fn client_loop(
                         if n > 0 {
                             connection.last_keep_alive = Instant::now();
 
-                            let (segments, _connection_type) = connection.parse_packet(&buf[..n]);
+                            let Some(segments) = connection.parse_packet(&buf[..n]) else {
+                                tracing::info!("Connection {:#?} was killed because it sent an invalid packet!", client_handle.id);
+                                break;
+                            };
                             for segment in &segments {
                                 match &segment.data {
                                     SegmentData::None() => {},
-                                    SegmentData::Setup { .. } => todo!(),
+                                    SegmentData::Setup { actor_id } => {
+                                        // only let them in if the lobby server said so
+                                        let actor_id = actor_id.parse::<u32>().unwrap_or_default();
+                                        if !connection.check_entry_token(actor_id) {
+                                            tracing::warn!("Connection {:#?} tried to enter as actor {actor_id} without an entry token, disconnecting!", client_handle.id);
+                                            break 'conn;
+                                        }
+
+                                        connection.initialize(actor_id).await;
+
+                                        client_handle.actor_id = actor_id;
+                                        connection.handle.send(ToServer::NewClient(client_handle.clone())).await;
+                                    }
                                     SegmentData::Ipc { .. } => todo!(),
                                     SegmentData::KeepAliveRequest { id, timestamp } => {
                                         send_keep_alive::<ServerZoneIpcSegment>(
@@ -190,6 +211,13 @@ async fn main() {
 
     let config = get_config();
 
+    if config.shared_secret.is_empty() {
+        tracing::error!(
+            "No shared secret is configured, which would let anyone manage characters on this world server! Please set shared_secret in the config."
+        );
+        return;
+    }
+
     let addr = config.world.get_socketaddr();
 
     let listener = TcpListener::bind(addr).await.unwrap();
@@ -208,8 +236,14 @@ async fn main() {
 
     let database = Arc::new(WorldDatabase::new());
     let lua = Arc::new(Mutex::new(Lua::new()));
+    let gamedata = Arc::new(Mutex::new(GameData::new()));
+    let entry_tokens = Arc::new(Mutex::new(HashMap::new()));
+
+    let population = Arc::new(AtomicU32::new(0));
+
+    let (handle, _) = spawn_main_loop(population.clone());
 
-    let (handle, _) = spawn_main_loop();
+    tokio::spawn(lobby_registration_loop(population, gamedata.clone()));
 
     loop {
         tokio::select! {
@@ -224,13 +258,18 @@ async fn main() {
                     config: get_config().world,
                     socket,
                     state,
+                    received: PacketBuffer::default(),
                     ip,
                     id,
                     handle: handle.clone(),
                     database: database.clone(),
                     lua: lua.clone(),
+                    gamedata: gamedata.clone(),
                     last_keep_alive: Instant::now(),
                     gracefully_logged_out: false,
+                    custom_ipc_auth: CustomIpcAuth::default(),
+                    entry_tokens: entry_tokens.clone(),
+                    account_role: AccountRole::default(),
                 });
             }
             Some((mut socket, _)) = handle_rcon(&rcon_listener) => {
@@ -249,7 +288,7 @@ async fn main() {
                                     let response = rkon::Packet {
                                         request_id: request.request_id,
                                         packet_type: rkon::PacketType::Command,
-                                        body: "hello world!".to_string()
+                                        body: handle_rcon_command(&database, &request.body)
                                     };
                                     let encoded = response.encode();
                                     socket.write_all(&encoded).await.unwrap();
@@ -0,0 +1,111 @@
+use base64::{Engine as _, engine::general_purpose::URL_SAFE_NO_PAD};
+
+use super::{sign, timestamp_secs, verify_truncated_signature};
+
+/// Prepended to the token data before signing, so these signatures can't be confused with other uses of the shared secret.
+const TOKEN_CONTEXT: &[u8] = b"kodama entry token";
+
+/// Size of the signed data in bytes.
+const TOKEN_DATA_SIZE: usize = 20;
+
+/// The signature is truncated so the token fits in `GameLoginReply`.
+const TOKEN_SIGNATURE_SIZE: usize = 16;
+
+/// Proof that the lobby server allowed a character to enter a world.
+/// This is given to the client in `GameLoginReply`, and handed to the world server ahead of time.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EntryToken {
+    pub service_account_id: u32,
+    pub content_id: u64,
+    pub actor_id: u32,
+    /// When this token expires, in seconds since UNIX epoch.
+    pub expires: u32,
+}
+
+impl EntryToken {
+    /// Creates a new token that's valid for `lifetime` seconds.
+    pub fn new(service_account_id: u32, content_id: u64, actor_id: u32, lifetime: u32) -> Self {
+        Self {
+            service_account_id,
+            content_id,
+            actor_id,
+            expires: timestamp_secs() + lifetime,
+        }
+    }
+
+    fn data(&self) -> Vec<u8> {
+        let mut data = Vec::with_capacity(TOKEN_DATA_SIZE);
+        data.extend_from_slice(&self.service_account_id.to_le_bytes());
+        data.extend_from_slice(&self.content_id.to_le_bytes());
+        data.extend_from_slice(&self.actor_id.to_le_bytes());
+        data.extend_from_slice(&self.expires.to_le_bytes());
+        data
+    }
+
+    fn signed_message(data: &[u8]) -> Vec<u8> {
+        [TOKEN_CONTEXT, data].concat()
+    }
+
+    /// Signs this token with `secret`, and returns it's string representation.
+    pub fn encode(&self, secret: &str) -> String {
+        let data = self.data();
+        let signature = sign(secret, &Self::signed_message(&data));
+
+        URL_SAFE_NO_PAD.encode([&data, &signature[..TOKEN_SIGNATURE_SIZE]].concat())
+    }
+
+    /// Reads a token created by `encode()`. Returns `None` if it's malformed or the signature doesn't match.
+    /// This does *not* check if the token expired, see `is_expired()`.
+    pub fn decode(secret: &str, token: &str) -> Option<Self> {
+        let bytes = URL_SAFE_NO_PAD.decode(token).ok()?;
+        if bytes.len() != TOKEN_DATA_SIZE + TOKEN_SIGNATURE_SIZE {
+            return None;
+        }
+
+        let (data, signature) = bytes.split_at(TOKEN_DATA_SIZE);
+        if !verify_truncated_signature(secret, &Self::signed_message(data), signature) {
+            return None;
+        }
+
+        Some(Self {
+            service_account_id: u32::from_le_bytes(data[0..4].try_into().ok()?),
+            content_id: u64::from_le_bytes(data[4..12].try_into().ok()?),
+            actor_id: u32::from_le_bytes(data[12..16].try_into().ok()?),
+            expires: u32::from_le_bytes(data[16..20].try_into().ok()?),
+        })
+    }
+
+    pub fn is_expired(&self) -> bool {
+        timestamp_secs() > self.expires
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn entry_token_roundtrip() {
+        let token = EntryToken::new(1, 2, 3, 60);
+        let encoded = token.encode("secret");
+
+        // GameLoginReply only has room for 65 characters
+        assert!(encoded.len() <= 65);
+        assert_eq!(EntryToken::decode("secret", &encoded), Some(token.clone()));
+        assert!(!token.is_expired());
+    }
+
+    #[test]
+    fn entry_token_tampered() {
+        let encoded = EntryToken::new(1, 2, 3, 60).encode("secret");
+        assert_eq!(EntryToken::decode("other secret", &encoded), None);
+
+        // claim a different actor id
+        let mut bytes = URL_SAFE_NO_PAD.decode(&encoded).unwrap();
+        bytes[12] ^= 1;
+        assert_eq!(
+            EntryToken::decode("secret", &URL_SAFE_NO_PAD.encode(bytes)),
+            None
+        );
+    }
+}
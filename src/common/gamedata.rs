@@ -0,0 +1,313 @@
+use std::collections::HashMap;
+
+use physis::{
+    common::{Language, Platform},
+    exd::{ColumnData, EXD, ExcelRowKind},
+    exh::EXH,
+    resource::{
+        ResourceResolver, SqPackResource, UnpackedResource, read_excel_sheet,
+        read_excel_sheet_header,
+    },
+};
+
+use crate::config::get_config;
+
+/// A row from the World Excel sheet.
+#[derive(Debug, Clone)]
+pub struct WorldInfo {
+    pub id: u16,
+    pub name: String,
+}
+
+/// A row from the TerritoryType Excel sheet.
+#[derive(Debug, Clone)]
+pub struct ZoneInfo {
+    pub id: u16,
+    /// Internal name of the zone, e.g. "prv0Inn01".
+    pub name: String,
+}
+
+/// A row from the Item Excel sheet.
+#[derive(Debug, Clone)]
+pub struct ItemInfo {
+    pub id: u32,
+    pub name: String,
+}
+
+/// A row from the ClassJob Excel sheet.
+#[derive(Debug, Clone)]
+pub struct ClassInfo {
+    pub id: u8,
+    pub name: String,
+    pub abbreviation: String,
+}
+
+/// A row from the ENpcResident Excel sheet.
+#[derive(Debug, Clone)]
+pub struct NpcInfo {
+    pub id: u32,
+    pub name: String,
+}
+
+/// A row from the Status Excel sheet.
+#[derive(Debug, Clone)]
+pub struct StatusInfo {
+    pub id: u16,
+    pub name: String,
+}
+
+// Column indices of the sheets we read.
+// NOTE: These are taken from the sheet layouts we know of, if a sheet changes these need to be adjusted.
+const WORLD_NAME_COLUMN: usize = 1;
+const TERRITORY_NAME_COLUMN: usize = 0;
+const ITEM_NAME_COLUMN: usize = 9;
+const CLASSJOB_NAME_COLUMN: usize = 0;
+const CLASSJOB_ABBREVIATION_COLUMN: usize = 1;
+const ENPC_NAME_COLUMN: usize = 0;
+const STATUS_NAME_COLUMN: usize = 0;
+
+/// Zone name used when a zone can't be found in the TerritoryType sheet.
+const FALLBACK_ZONE_NAME: &str = "prv0Inn01";
+
+impl WorldInfo {
+    fn from_row(id: u16, columns: &[ColumnData]) -> Option<Self> {
+        Some(Self {
+            id,
+            name: GameData::get_string(columns, WORLD_NAME_COLUMN)?,
+        })
+    }
+
+    /// Returns the name of `world`, or a placeholder if it's `None`.
+    fn name_or_placeholder(world_id: u16, world: Option<Self>) -> String {
+        match world {
+            Some(world) => world.name,
+            None => {
+                tracing::warn!(
+                    "World {world_id} isn't in the World sheet, or column {WORLD_NAME_COLUMN} isn't it's name. Using a placeholder name!"
+                );
+                format!("World {world_id}")
+            }
+        }
+    }
+}
+
+impl ZoneInfo {
+    fn from_row(id: u16, columns: &[ColumnData]) -> Option<Self> {
+        Some(Self {
+            id,
+            name: GameData::get_string(columns, TERRITORY_NAME_COLUMN)?,
+        })
+    }
+
+    /// Returns the name of `zone`, or `FALLBACK_ZONE_NAME` if it's `None`.
+    fn name_or_fallback(zone_id: u16, zone: Option<Self>) -> String {
+        match zone {
+            Some(zone) => zone.name,
+            None => {
+                tracing::warn!(
+                    "Zone {zone_id} isn't in the TerritoryType sheet, or column {TERRITORY_NAME_COLUMN} isn't it's name. Using {FALLBACK_ZONE_NAME} instead!"
+                );
+                FALLBACK_ZONE_NAME.to_string()
+            }
+        }
+    }
+}
+
+/// An Excel sheet that has been read, including all of it's pages.
+struct Sheet {
+    exh: EXH,
+    pages: Vec<EXD>,
+}
+
+/// Convenient methods to access game data, backed by physis.
+/// Sheets are read on first use and then kept around.
+pub struct GameData {
+    resource: ResourceResolver,
+    /// Sheets we tried to read so far. `None` means the sheet couldn't be read.
+    sheets: HashMap<&'static str, Option<Sheet>>,
+}
+
+impl Default for GameData {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl GameData {
+    pub fn new() -> Self {
+        let config = get_config();
+
+        let mut resource = ResourceResolver::new();
+
+        // Extracted files are preferred over the game install
+        for path in &config.filesystem.additional_search_paths {
+            resource.add_source(Box::new(UnpackedResource::from_existing(path)));
+        }
+
+        if config.filesystem.game_path.is_empty() {
+            tracing::warn!(
+                "No game path is configured, game data will only be read from the additional search paths!"
+            );
+        } else {
+            resource.add_source(Box::new(SqPackResource::from_existing(
+                Platform::Win32,
+                &config.filesystem.game_path,
+            )));
+        }
+
+        Self {
+            resource,
+            sheets: HashMap::new(),
+        }
+    }
+
+    /// Reads the sheet if we haven't yet, and returns it.
+    fn get_sheet(&mut self, name: &'static str, language: Language) -> Option<&Sheet> {
+        let resource = &mut self.resource;
+        self.sheets
+            .entry(name)
+            .or_insert_with(|| {
+                let Some(exh) = read_excel_sheet_header(resource, name) else {
+                    tracing::warn!(
+                        "Failed to read the {name} sheet header, is the game path correct?"
+                    );
+                    return None;
+                };
+
+                let mut pages = Vec::new();
+                for page in 0..exh.pages.len() {
+                    let Some(exd) = read_excel_sheet(resource, name, &exh, language, page) else {
+                        tracing::warn!("Failed to read page {page} of the {name} sheet!");
+                        return None;
+                    };
+                    pages.push(exd);
+                }
+
+                Some(Sheet { exh, pages })
+            })
+            .as_ref()
+    }
+
+    /// Returns the columns of `row_id` in sheet `name`.
+    fn get_row(
+        &mut self,
+        name: &'static str,
+        language: Language,
+        row_id: u32,
+    ) -> Option<Vec<ColumnData>> {
+        let sheet = self.get_sheet(name, language)?;
+
+        let page_index =
+            sheet.exh.pages.iter().position(|page| {
+                row_id >= page.start_id && row_id < page.start_id + page.row_count
+            })?;
+
+        let ExcelRowKind::SingleRow(row) = sheet.pages[page_index].get_row(row_id)? else {
+            tracing::warn!("Expected row {row_id} of the {name} sheet to be a single row!");
+            return None;
+        };
+
+        Some(row.columns)
+    }
+
+    fn get_string(columns: &[ColumnData], index: usize) -> Option<String> {
+        match columns.get(index)? {
+            ColumnData::String(value) => Some(value.clone()),
+            _ => None,
+        }
+    }
+
+    pub fn get_world(&mut self, world_id: u16) -> Option<WorldInfo> {
+        let columns = self.get_row("World", Language::None, world_id as u32)?;
+
+        WorldInfo::from_row(world_id, &columns)
+    }
+
+    /// Returns the name of the world, or a placeholder if it couldn't be found.
+    pub fn get_world_name(&mut self, world_id: u16) -> String {
+        WorldInfo::name_or_placeholder(world_id, self.get_world(world_id))
+    }
+
+    pub fn get_zone(&mut self, zone_id: u16) -> Option<ZoneInfo> {
+        let columns = self.get_row("TerritoryType", Language::None, zone_id as u32)?;
+
+        ZoneInfo::from_row(zone_id, &columns)
+    }
+
+    /// Returns the internal name of the zone, or the inn room if it couldn't be found.
+    pub fn get_zone_name(&mut self, zone_id: u16) -> String {
+        ZoneInfo::name_or_fallback(zone_id, self.get_zone(zone_id))
+    }
+
+    pub fn get_item(&mut self, item_id: u32) -> Option<ItemInfo> {
+        let columns = self.get_row("Item", Language::English, item_id)?;
+
+        Some(ItemInfo {
+            id: item_id,
+            name: Self::get_string(&columns, ITEM_NAME_COLUMN)?,
+        })
+    }
+
+    pub fn get_class(&mut self, class_id: u8) -> Option<ClassInfo> {
+        let columns = self.get_row("ClassJob", Language::English, class_id as u32)?;
+
+        Some(ClassInfo {
+            id: class_id,
+            name: Self::get_string(&columns, CLASSJOB_NAME_COLUMN)?,
+            abbreviation: Self::get_string(&columns, CLASSJOB_ABBREVIATION_COLUMN)?,
+        })
+    }
+
+    pub fn get_npc(&mut self, npc_id: u32) -> Option<NpcInfo> {
+        let columns = self.get_row("ENpcResident", Language::English, npc_id)?;
+
+        Some(NpcInfo {
+            id: npc_id,
+            name: Self::get_string(&columns, ENPC_NAME_COLUMN)?,
+        })
+    }
+
+    pub fn get_status(&mut self, status_id: u16) -> Option<StatusInfo> {
+        let columns = self.get_row("Status", Language::English, status_id as u32)?;
+
+        Some(StatusInfo {
+            id: status_id,
+            name: Self::get_string(&columns, STATUS_NAME_COLUMN)?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn world_name_fallback() {
+        let row = [
+            ColumnData::String("Internal".to_string()),
+            ColumnData::String("Gilgamesh".to_string()),
+        ];
+        let world = WorldInfo::from_row(63, &row);
+        assert_eq!(WorldInfo::name_or_placeholder(63, world), "Gilgamesh");
+
+        // the sheet layout changed, so the name column isn't a string anymore
+        let row = [
+            ColumnData::String("Gilgamesh".to_string()),
+            ColumnData::UInt8(1),
+        ];
+        let world = WorldInfo::from_row(63, &row);
+        assert_eq!(WorldInfo::name_or_placeholder(63, world), "World 63");
+
+        assert_eq!(WorldInfo::name_or_placeholder(63, None), "World 63");
+    }
+
+    #[test]
+    fn zone_name_fallback() {
+        let row = [ColumnData::String("s1t1".to_string())];
+        let zone = ZoneInfo::from_row(132, &row);
+        assert_eq!(ZoneInfo::name_or_fallback(132, zone), "s1t1");
+
+        let zone = ZoneInfo::from_row(132, &[]);
+        assert_eq!(ZoneInfo::name_or_fallback(132, zone), FALLBACK_ZONE_NAME);
+    }
+}
@@ -11,6 +11,18 @@ pub use position::Position;
 mod chara_info;
 pub use chara_info::CharaInfo;
 
+mod gamedata;
+pub use gamedata::{ClassInfo, GameData, ItemInfo, NpcInfo, StatusInfo, WorldInfo, ZoneInfo};
+
+mod signing;
+pub use signing::{SIGNATURE_SIZE, sign, verify_signature, verify_truncated_signature};
+
+mod entry_token;
+pub use entry_token::EntryToken;
+
+mod private_api;
+pub use private_api::{PRIVATE_API_HEADER, private_api_token, verify_private_api_token};
+
 #[binrw]
 #[brw(little)]
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
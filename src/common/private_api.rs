@@ -0,0 +1,35 @@
+use base64::{Engine as _, engine::general_purpose::URL_SAFE_NO_PAD};
+
+use super::{sign, verify_signature};
+
+/// HTTP header carrying the token for the login server's private endpoints.
+pub const PRIVATE_API_HEADER: &str = "x-kodama-token";
+
+/// Signed instead of sending the shared secret itself, so a leaked token can't be used to forge e.g. entry tokens.
+const PRIVATE_API_CONTEXT: &[u8] = b"kodama private api";
+
+/// Returns the token other servers send in `PRIVATE_API_HEADER`.
+pub fn private_api_token(secret: &str) -> String {
+    URL_SAFE_NO_PAD.encode(sign(secret, PRIVATE_API_CONTEXT))
+}
+
+/// Checks if `token` was created by `private_api_token()` with the same `secret`.
+pub fn verify_private_api_token(secret: &str, token: &str) -> bool {
+    URL_SAFE_NO_PAD
+        .decode(token)
+        .is_ok_and(|signature| verify_signature(secret, PRIVATE_API_CONTEXT, &signature))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn private_api_tokens() {
+        let token = private_api_token("secret");
+        assert!(verify_private_api_token("secret", &token));
+        assert!(!verify_private_api_token("other secret", &token));
+        assert!(!verify_private_api_token("secret", ""));
+        assert!(!verify_private_api_token("secret", "secret"));
+    }
+}
@@ -0,0 +1,50 @@
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Size of a signature in bytes.
+pub const SIGNATURE_SIZE: usize = 32;
+
+/// Signs `message` with `secret` using HMAC-SHA256.
+pub fn sign(secret: &str, message: &[u8]) -> [u8; SIGNATURE_SIZE] {
+    let mut mac =
+        HmacSha256::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any size");
+    mac.update(message);
+    mac.finalize().into_bytes().into()
+}
+
+/// Checks if `signature` is the signature of `message` with `secret`. This comparison is done in constant time.
+pub fn verify_signature(secret: &str, message: &[u8], signature: &[u8]) -> bool {
+    let mut mac =
+        HmacSha256::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any size");
+    mac.update(message);
+    mac.verify_slice(signature).is_ok()
+}
+
+/// Same as `verify_signature`, but `signature` may be truncated (keeping the leftmost bytes.)
+pub fn verify_truncated_signature(secret: &str, message: &[u8], signature: &[u8]) -> bool {
+    let mut mac =
+        HmacSha256::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any size");
+    mac.update(message);
+    mac.verify_truncated_left(signature).is_ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn signature_roundtrip() {
+        let signature = sign("secret", b"hello");
+        assert!(verify_signature("secret", b"hello", &signature));
+    }
+
+    #[test]
+    fn signature_mismatch() {
+        let signature = sign("secret", b"hello");
+        assert!(!verify_signature("other secret", b"hello", &signature));
+        assert!(!verify_signature("secret", b"goodbye", &signature));
+        assert!(!verify_signature("secret", b"hello", &signature[..16]));
+    }
+}
@@ -31,11 +31,55 @@ impl AdminConfig {
     }
 }
 
+/// A world server that the lobby sends players to.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct LobbyWorldConfig {
+    /// See the World Excel sheet.
+    pub id: u16,
+    /// Public-facing IP address of the world server.
+    pub server_name: String,
+    pub port: u16,
+}
+
+impl LobbyWorldConfig {
+    /// Returns the public IP address & port as a `SocketAddr`.
+    pub fn get_public_socketaddr(&self) -> SocketAddr {
+        SocketAddr::from((
+            IpAddr::from_str(&self.server_name).expect("Invalid IP address format in config!"),
+            self.port,
+        ))
+    }
+}
+
 /// Configuration for the lobby server.
 #[derive(Serialize, Deserialize)]
 pub struct LobbyConfig {
     pub port: u16,
     pub listen_address: String,
+    /// Public-facing IP address of the lobby server, world servers register themselves here and the login server talks to it.
+    #[serde(default = "LobbyConfig::default_server_name")]
+    pub server_name: String,
+    /// The world servers shown in the lobby.
+    /// If left empty (the default), only the world server from the `world` section is used.
+    /// World servers not listed here are added once they register themselves.
+    #[serde(default)]
+    pub worlds: Vec<LobbyWorldConfig>,
+    /// Number of seconds without a heartbeat before a world is considered offline.
+    #[serde(default = "LobbyConfig::default_world_timeout")]
+    pub world_timeout: u64,
+    /// Number of seconds a player has to connect to the world server after selecting their character.
+    #[serde(default = "LobbyConfig::default_entry_token_lifetime")]
+    pub entry_token_lifetime: u32,
+    /// Maximum number of characters a service account can have, across all worlds.
+    #[serde(default = "LobbyConfig::default_max_characters_per_account")]
+    pub max_characters_per_account: u32,
+    /// Number of seconds between checks if a player waiting for a full world can go in, they are told their position in the queue each time.
+    #[serde(default = "LobbyConfig::default_queue_update_interval")]
+    pub queue_update_interval: u64,
+    /// Whether players can move their characters to another world from the lobby.
+    /// Off by default, because it's not verified that the client sends the destination world where we expect it.
+    #[serde(default)]
+    pub allow_character_moves: bool,
 }
 
 impl Default for LobbyConfig {
@@ -43,11 +87,38 @@ impl Default for LobbyConfig {
         Self {
             port: 54994,
             listen_address: "0.0.0.0".to_string(),
+            server_name: Self::default_server_name(),
+            worlds: Vec::new(),
+            world_timeout: Self::default_world_timeout(),
+            entry_token_lifetime: Self::default_entry_token_lifetime(),
+            max_characters_per_account: Self::default_max_characters_per_account(),
+            queue_update_interval: Self::default_queue_update_interval(),
+            allow_character_moves: false,
         }
     }
 }
 
 impl LobbyConfig {
+    fn default_server_name() -> String {
+        "127.0.0.1".to_string()
+    }
+
+    fn default_world_timeout() -> u64 {
+        30
+    }
+
+    fn default_entry_token_lifetime() -> u32 {
+        60
+    }
+
+    fn default_max_characters_per_account() -> u32 {
+        8
+    }
+
+    fn default_queue_update_interval() -> u64 {
+        5
+    }
+
     /// Returns the configured IP address & port as a `SocketAddr`.
     pub fn get_socketaddr(&self) -> SocketAddr {
         SocketAddr::from((
@@ -55,6 +126,27 @@ impl LobbyConfig {
             self.port,
         ))
     }
+
+    /// Returns the public IP address & port as a `SocketAddr`.
+    pub fn get_public_socketaddr(&self) -> SocketAddr {
+        SocketAddr::from((
+            IpAddr::from_str(&self.server_name).expect("Invalid IP address format in config!"),
+            self.port,
+        ))
+    }
+}
+
+/// Who is allowed to register new accounts on the website.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RegistrationMode {
+    /// Anyone can register.
+    #[default]
+    Open,
+    /// Registering requires an invite code, which admins can generate on the website.
+    InviteOnly,
+    /// Nobody can register.
+    Closed,
 }
 
 /// Configuration for the login server.
@@ -64,6 +156,39 @@ pub struct LoginConfig {
     pub listen_address: String,
     /// Public-facing domain of the server.
     pub server_name: String,
+    /// Number of seconds a website session can go unused before it expires.
+    #[serde(default = "LoginConfig::default_web_session_lifetime")]
+    pub web_session_lifetime: u32,
+    /// Number of seconds a game session can go unused before it expires.
+    #[serde(default = "LoginConfig::default_game_session_lifetime")]
+    pub game_session_lifetime: u32,
+    /// Number of seconds between removing expired sessions from the database.
+    #[serde(default = "LoginConfig::default_session_cleanup_interval")]
+    pub session_cleanup_interval: u64,
+    /// Usernames that are given the admin role when the login server starts.
+    /// Admins can give other users roles on the website, so this is only needed for the first one.
+    #[serde(default)]
+    pub admins: Vec<String>,
+    /// Whether new accounts can be registered on the website.
+    #[serde(default)]
+    pub registration: RegistrationMode,
+    /// Number of failed logins in a row, from the same IP address or for the same username, before they are locked out.
+    /// Usernames are only locked out for IP addresses that never logged into them, so others can't lock someone out of their account.
+    #[serde(default = "LoginConfig::default_login_attempts")]
+    pub login_attempts: u32,
+    /// Number of seconds of the first lockout, each one after that is twice as long.
+    #[serde(default = "LoginConfig::default_lockout_duration")]
+    pub lockout_duration: u32,
+    /// Maximum number of seconds of a lockout. Failed logins are forgotten if there are none for this long.
+    #[serde(default = "LoginConfig::default_max_lockout_duration")]
+    pub max_lockout_duration: u32,
+    /// Number of seconds failed logins are kept for the audit log on the moderation page.
+    #[serde(default = "LoginConfig::default_login_audit_lifetime")]
+    pub login_audit_lifetime: u32,
+    /// IP addresses of reverse proxies in front of the login server.
+    /// Logins coming through them are throttled by the client address in their `X-Forwarded-For` header, instead of the proxy's.
+    #[serde(default)]
+    pub trusted_proxies: Vec<String>,
 }
 
 impl Default for LoginConfig {
@@ -72,11 +197,49 @@ impl Default for LoginConfig {
             port: 6700,
             listen_address: "0.0.0.0".to_string(),
             server_name: "ffxiv-login.square.localhost".to_string(),
+            web_session_lifetime: Self::default_web_session_lifetime(),
+            game_session_lifetime: Self::default_game_session_lifetime(),
+            session_cleanup_interval: Self::default_session_cleanup_interval(),
+            admins: Vec::new(),
+            registration: RegistrationMode::default(),
+            login_attempts: Self::default_login_attempts(),
+            lockout_duration: Self::default_lockout_duration(),
+            max_lockout_duration: Self::default_max_lockout_duration(),
+            login_audit_lifetime: Self::default_login_audit_lifetime(),
+            trusted_proxies: Vec::new(),
         }
     }
 }
 
 impl LoginConfig {
+    fn default_web_session_lifetime() -> u32 {
+        7 * 24 * 60 * 60 // one week
+    }
+
+    fn default_game_session_lifetime() -> u32 {
+        24 * 60 * 60 // one day
+    }
+
+    fn default_session_cleanup_interval() -> u64 {
+        60 * 60 // one hour
+    }
+
+    fn default_login_attempts() -> u32 {
+        5
+    }
+
+    fn default_lockout_duration() -> u32 {
+        30
+    }
+
+    fn default_max_lockout_duration() -> u32 {
+        60 * 60 // one hour
+    }
+
+    fn default_login_audit_lifetime() -> u32 {
+        30 * 24 * 60 * 60 // 30 days
+    }
+
     /// Returns the configured IP address & port as a `SocketAddr`.
     pub fn get_socketaddr(&self) -> SocketAddr {
         SocketAddr::from((
@@ -210,6 +373,21 @@ pub struct WorldConfig {
     /// Password of the RCON server, if left blank (the default) RCON is disabled.
     #[serde(default = "WorldConfig::default_rcon_password")]
     pub rcon_password: String,
+    /// Maximum number of players allowed on this world, 0 means no limit.
+    #[serde(default = "WorldConfig::default_capacity")]
+    pub capacity: u32,
+    /// Number of seconds between heartbeats sent to the lobby server.
+    #[serde(default = "WorldConfig::default_heartbeat_interval")]
+    pub heartbeat_interval: u64,
+    /// Maximum number of characters a service account can have on this world.
+    #[serde(default = "WorldConfig::default_max_characters")]
+    pub max_characters: u32,
+    /// Maximum number of retainers each character can hire.
+    #[serde(default = "WorldConfig::default_max_retainers")]
+    pub max_retainers: u32,
+    /// Directory character backups are imported from and exported to over RCON.
+    #[serde(default = "WorldConfig::default_backups_location")]
+    pub backups_location: String,
 }
 
 impl Default for WorldConfig {
@@ -222,6 +400,11 @@ impl Default for WorldConfig {
             scripts_location: Self::default_scripts_location(),
             rcon_port: Self::default_rcon_port(),
             rcon_password: Self::default_rcon_password(),
+            capacity: Self::default_capacity(),
+            heartbeat_interval: Self::default_heartbeat_interval(),
+            max_characters: Self::default_max_characters(),
+            max_retainers: Self::default_max_retainers(),
+            backups_location: Self::default_backups_location(),
         }
     }
 }
@@ -254,6 +437,26 @@ impl WorldConfig {
     fn default_rcon_password() -> String {
         String::default()
     }
+
+    fn default_capacity() -> u32 {
+        0
+    }
+
+    fn default_heartbeat_interval() -> u64 {
+        10
+    }
+
+    fn default_max_characters() -> u32 {
+        8
+    }
+
+    fn default_max_retainers() -> u32 {
+        2
+    }
+
+    fn default_backups_location() -> String {
+        "backups".to_string()
+    }
 }
 
 impl WorldConfig {
@@ -287,6 +490,10 @@ pub struct FilesystemConfig {
     /// Path to the game directory. For example, "C:\Program Files (x86)\SquareEnix\FINAL FANTASY XIV".
     #[serde(default)]
     pub game_path: String,
+    /// Additional search paths for extracted game files, such as Excel sheets.
+    /// These are ordered from highest-to-lowest priority, and are always preferred over the game directory.
+    #[serde(default)]
+    pub additional_search_paths: Vec<String>,
 }
 
 /// Global and all-encompassing config.
@@ -317,6 +524,16 @@ pub struct Config {
     /// Enable various validity checks for version and file hashes that emulate retail.
     #[serde(default = "Config::default_enforce_validity_checks")]
     pub enforce_validity_checks: bool,
+
+    /// Secret shared between all of the servers, used to authenticate private server-to-server communication.
+    /// This must be the same for every server, and should be kept secret! The lobby and world servers refuse to start without one,
+    /// and the login server refuses every private API request.
+    ///
+    /// The token sent to the login server's private API is derived from this, but never changes and is sent over plain HTTP.
+    /// Anyone who can see that traffic can replay it forever, so keep the login server's private endpoints on a trusted network.
+    /// Changing the secret is the only way to invalidate a leaked token.
+    #[serde(default = "Config::default_shared_secret")]
+    pub shared_secret: String,
 }
 
 impl Default for Config {
@@ -330,6 +547,7 @@ impl Default for Config {
             web: WebConfig::default(),
             world: WorldConfig::default(),
             enforce_validity_checks: Self::default_enforce_validity_checks(),
+            shared_secret: Self::default_shared_secret(),
         }
     }
 }
@@ -338,6 +556,23 @@ impl Config {
     fn default_enforce_validity_checks() -> bool {
         true
     }
+
+    fn default_shared_secret() -> String {
+        String::default()
+    }
+
+    /// Returns the world servers listed in the lobby section, or the one from the world section if none are.
+    pub fn get_lobby_worlds(&self) -> Vec<LobbyWorldConfig> {
+        if self.lobby.worlds.is_empty() {
+            vec![LobbyWorldConfig {
+                id: self.world.world_id,
+                server_name: self.world.server_name.clone(),
+                port: self.world.port,
+            }]
+        } else {
+            self.lobby.worlds.clone()
+        }
+    }
 }
 
 pub fn get_config() -> Config {
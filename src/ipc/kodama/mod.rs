@@ -1,30 +1,84 @@
 use binrw::binrw;
 
 use crate::{
-    common::{CHAR_NAME_MAX_LENGTH, read_bool_from, read_string, write_bool_as, write_string},
-    ipc::lobby::CharacterDetails,
-    packet::{IPC_HEADER_SIZE, IpcSegment, ReadWriteIpcSegment},
+    common::{
+        CHAR_NAME_MAX_LENGTH, SIGNATURE_SIZE, read_bool_from, read_string, write_bool_as,
+        write_string,
+    },
+    ipc::lobby::{CharacterDetails, RetainerInfo},
+    packet::{IPC_HEADER_SIZE, ReadWriteIpcSegment},
 };
 
-pub type CustomIpcSegment = IpcSegment<CustomIpcType, CustomIpcData>;
+/// An IPC segment used for private server-to-server communication.
+/// This has the same size header as a regular `IpcSegment`, but carries a request id instead of the unknown fields.
+#[binrw]
+#[derive(Debug, Clone)]
+#[br(import(_size: &u32))]
+pub struct CustomIpcSegment {
+    /// Used to match responses to their requests, a response always has the same id as the request.
+    pub request_id: u32,
+    /// The opcode for this segment.
+    pub op_code: CustomIpcType,
+    #[brw(pad_before = 2)] // empty
+    /// The timestamp of this packet in seconds since UNIX epoch.
+    pub timestamp: u32,
+    /// The data associated with the opcode.
+    #[brw(pad_before = 4)]
+    #[br(args(&op_code, &0))]
+    pub data: CustomIpcData,
+}
 
 impl ReadWriteIpcSegment for CustomIpcSegment {
     fn calc_size(&self) -> u32 {
         IPC_HEADER_SIZE
             + match self.op_code {
-                CustomIpcType::RequestCreateCharacter => 1024 + CHAR_NAME_MAX_LENGTH as u32,
+                CustomIpcType::RequestCreateCharacter => 4 + 1024 + CHAR_NAME_MAX_LENGTH as u32,
                 CustomIpcType::CharacterCreated => 12,
                 CustomIpcType::GetActorId => 8,
                 CustomIpcType::ActorIdFound => 4,
                 CustomIpcType::CheckNameIsAvailable => CHAR_NAME_MAX_LENGTH as u32,
                 CustomIpcType::NameIsAvailableResponse => 1,
-                CustomIpcType::RequestCharacterList => 4,
-                CustomIpcType::RequestCharacterListRepsonse => 1 + (1184 * 8),
+                CustomIpcType::RequestCharacterList => 8,
+                CustomIpcType::RequestCharacterListRepsonse => {
+                    4 + 1 + (CharacterDetails::SIZE * CHARACTER_LIST_PAGE_SIZE) as u32
+                }
                 CustomIpcType::DeleteCharacter => 8,
                 CustomIpcType::CharacterDeleted => 1,
-                CustomIpcType::ImportCharacter => 132,
+                CustomIpcType::ImportCharacter => 4 + CHARACTER_BACKUP_MAX_LENGTH as u32,
                 CustomIpcType::RemakeCharacter => 1024 + 8,
                 CustomIpcType::CharacterRemade => 8,
+                CustomIpcType::RegisterWorld => 12 + 48 + CHAR_NAME_MAX_LENGTH as u32,
+                CustomIpcType::WorldRegistered => 1,
+                CustomIpcType::WorldHeartbeat => 8,
+                CustomIpcType::RequestAuthChallenge => 0,
+                CustomIpcType::AuthChallenge => AUTH_CHALLENGE_SIZE as u32,
+                CustomIpcType::Authenticate => SIGNATURE_SIZE as u32,
+                CustomIpcType::Authenticated => 1,
+                CustomIpcType::GrantWorldEntry => ENTRY_TOKEN_MAX_LENGTH as u32 + 16,
+                CustomIpcType::WorldEntryGranted => 1,
+                CustomIpcType::RenameCharacter => 8 + CHAR_NAME_MAX_LENGTH as u32,
+                CustomIpcType::CharacterRenamed => 3,
+                CustomIpcType::CharacterImported => 8,
+                CustomIpcType::ExportCharacter => 8,
+                CustomIpcType::CharacterExported => CHARACTER_BACKUP_MAX_LENGTH as u32,
+                CustomIpcType::BeginTransfer => 8,
+                CustomIpcType::TransferBegun => CHARACTER_BACKUP_MAX_LENGTH as u32,
+                CustomIpcType::ReceiveTransfer => 4 + CHARACTER_BACKUP_MAX_LENGTH as u32,
+                CustomIpcType::TransferReceived => 12,
+                CustomIpcType::CancelTransfer => 8,
+                CustomIpcType::TransferCancelled => 1,
+                CustomIpcType::StoreSettings => 20 + SETTINGS_CHUNK_SIZE as u32,
+                CustomIpcType::SettingsStored => 1,
+                CustomIpcType::RequestRetainerList => 8,
+                CustomIpcType::RetainerListResponse => {
+                    4 + 4 + 1 + (RetainerInfo::SIZE * RETAINER_LIST_PAGE_SIZE) as u32
+                }
+                CustomIpcType::RemakeRetainer => 12 + CHAR_NAME_MAX_LENGTH as u32,
+                CustomIpcType::RetainerRemade => 2,
+                CustomIpcType::RemoveServiceAccount => 4,
+                CustomIpcType::ServiceAccountRemoved => 8,
+                CustomIpcType::FetchSettings => 16,
+                CustomIpcType::SettingsFetched => 16 + SETTINGS_CHUNK_SIZE as u32,
             }
     }
 
@@ -40,16 +94,32 @@ impl ReadWriteIpcSegment for CustomIpcSegment {
 impl Default for CustomIpcSegment {
     fn default() -> Self {
         Self {
-            unk1: 0,
-            unk2: 0,
+            request_id: 0,
             op_code: CustomIpcType::GetActorId,
-            option: 0,
             timestamp: 0,
             data: CustomIpcData::GetActorId { content_id: 0 },
         }
     }
 }
 
+/// Size of the challenge sent in `AuthChallenge`.
+pub const AUTH_CHALLENGE_SIZE: usize = 32;
+
+/// Maximum number of characters in a single `RequestCharacterListRepsonse`.
+pub const CHARACTER_LIST_PAGE_SIZE: usize = 8;
+
+/// Maximum length of an entry token, this is the same as in `GameLoginReply`.
+pub const ENTRY_TOKEN_MAX_LENGTH: usize = 66;
+
+/// Maximum length of a character backup sent between worlds, see `CharacterBackup`.
+pub const CHARACTER_BACKUP_MAX_LENGTH: usize = 4096;
+
+/// Maximum size of a settings chunk, this is how much fits in the base64 encoded field of `CharaMake`.
+pub const SETTINGS_CHUNK_SIZE: usize = 300;
+
+/// Maximum number of retainers in a single `RetainerListResponse`.
+pub const RETAINER_LIST_PAGE_SIZE: usize = 16;
+
 #[binrw]
 #[brw(repr = u16)]
 #[derive(Default, Clone, PartialEq, Debug)]
@@ -81,6 +151,67 @@ pub enum CustomIpcType {
     RemakeCharacter = 0x12,
     // Character has been remade
     CharacterRemade = 0x13,
+    /// Sent by a world server to announce itself to the lobby server
+    RegisterWorld = 0x14,
+    /// Response to RegisterWorld
+    WorldRegistered = 0x15,
+    /// Sent periodically by a world server to tell the lobby server it's still alive
+    WorldHeartbeat = 0x16,
+    /// Sent by the connecting server to start authenticating
+    RequestAuthChallenge = 0x17,
+    /// Response to RequestAuthChallenge, containing a random challenge to sign
+    AuthChallenge = 0x18,
+    /// The challenge signed with the shared secret
+    Authenticate = 0x19,
+    /// Response to Authenticate
+    Authenticated = 0x1A,
+    /// Sent by the lobby server to let a character into the world
+    GrantWorldEntry = 0x1B,
+    /// Response to GrantWorldEntry
+    WorldEntryGranted = 0x1C,
+    /// Request that a character be renamed on the world server
+    RenameCharacter = 0x1D,
+    /// Response to RenameCharacter
+    CharacterRenamed = 0x1E,
+    /// Response to ImportCharacter
+    CharacterImported = 0x1F,
+    /// Request to export a character backup
+    ExportCharacter = 0x20,
+    /// Response to ExportCharacter
+    CharacterExported = 0x21,
+    /// Request to lock a character and send it's backup, so it can be moved to another world
+    BeginTransfer = 0x22,
+    /// Response to BeginTransfer
+    TransferBegun = 0x23,
+    /// Request to create a character that is being moved from another world
+    ReceiveTransfer = 0x24,
+    /// Response to ReceiveTransfer
+    TransferReceived = 0x25,
+    /// Request to unlock a character after a failed transfer
+    CancelTransfer = 0x26,
+    /// Response to CancelTransfer
+    TransferCancelled = 0x27,
+    /// Request to store a chunk of client settings uploaded to the lobby
+    StoreSettings = 0x28,
+    /// Response to StoreSettings
+    SettingsStored = 0x29,
+    /// Request the retainers of every character on a service account
+    RequestRetainerList = 0x2A,
+    /// Response to RequestRetainerList
+    RetainerListResponse = 0x2B,
+    /// Request that a retainer be remade
+    RemakeRetainer = 0x2C,
+    /// Response to RemakeRetainer
+    RetainerRemade = 0x2D,
+    /// Request that every character on a service account be deleted, because the account was cancelled.
+    /// The login server sends this to the lobby server, which passes it on to every world it knows about.
+    RemoveServiceAccount = 0x2E,
+    /// Response to RemoveServiceAccount
+    ServiceAccountRemoved = 0x2F,
+    /// Request a chunk of the client settings stored for a character
+    FetchSettings = 0x30,
+    /// Response to FetchSettings
+    SettingsFetched = 0x31,
 }
 
 #[binrw]
@@ -102,7 +233,11 @@ pub enum CustomIpcData {
         encoded: String,
     },
     #[br(pre_assert(*magic == CustomIpcType::CharacterCreated))]
-    CharacterCreated { actor_id: u32, content_id: u64 },
+    CharacterCreated {
+        actor_id: u32,
+        /// 0 if the character couldn't be created, e.g. the service account has too many characters.
+        content_id: u64,
+    },
     #[br(pre_assert(*magic == CustomIpcType::GetActorId))]
     GetActorId { content_id: u64 },
     #[br(pre_assert(*magic == CustomIpcType::ActorIdFound))]
@@ -122,19 +257,287 @@ pub enum CustomIpcData {
         free: bool,
     },
     #[br(pre_assert(*magic == CustomIpcType::RequestCharacterList))]
-    RequestCharacterList { service_account_id: u32 },
+    RequestCharacterList {
+        service_account_id: u32,
+        /// Index of the first character to send, the list is sent in pages of `CHARACTER_LIST_PAGE_SIZE`.
+        offset: u32,
+    },
     #[br(pre_assert(*magic == CustomIpcType::RequestCharacterListRepsonse))]
     RequestCharacterListRepsonse {
+        /// Total number of characters, across all pages.
+        total: u32,
         #[bw(calc = characters.len() as u8)]
         num_characters: u8,
         #[br(count = num_characters)]
-        #[brw(pad_size_to = 1184 * 8)]
-        characters: Vec<CharacterDetails>, // TODO: maybe chunk this into 4 parts ala the lobby server?
+        #[brw(pad_size_to = CharacterDetails::SIZE * CHARACTER_LIST_PAGE_SIZE)]
+        characters: Vec<CharacterDetails>,
     },
     #[br(pre_assert(*magic == CustomIpcType::DeleteCharacter))]
     DeleteCharacter { content_id: u64 },
     #[br(pre_assert(*magic == CustomIpcType::CharacterDeleted))]
     CharacterDeleted { deleted: u8 },
+    #[br(pre_assert(*magic == CustomIpcType::ImportCharacter))]
+    ImportCharacter {
+        /// The service account the character is added to.
+        service_account_id: u32,
+        /// JSON of the `CharacterBackup`.
+        #[bw(pad_size_to = CHARACTER_BACKUP_MAX_LENGTH)]
+        #[br(count = CHARACTER_BACKUP_MAX_LENGTH)]
+        #[br(map = read_string)]
+        #[bw(map = write_string)]
+        backup: String,
+    },
+    #[br(pre_assert(*magic == CustomIpcType::CharacterImported))]
+    CharacterImported {
+        /// Zero if the character couldn't be imported.
+        content_id: u64,
+    },
+    #[br(pre_assert(*magic == CustomIpcType::ExportCharacter))]
+    ExportCharacter { content_id: u64 },
+    #[br(pre_assert(*magic == CustomIpcType::CharacterExported))]
+    CharacterExported {
+        /// JSON of the `CharacterBackup`, empty if the character couldn't be exported.
+        #[bw(pad_size_to = CHARACTER_BACKUP_MAX_LENGTH)]
+        #[br(count = CHARACTER_BACKUP_MAX_LENGTH)]
+        #[br(map = read_string)]
+        #[bw(map = write_string)]
+        backup: String,
+    },
+    #[br(pre_assert(*magic == CustomIpcType::BeginTransfer))]
+    BeginTransfer { content_id: u64 },
+    #[br(pre_assert(*magic == CustomIpcType::TransferBegun))]
+    TransferBegun {
+        /// JSON of the `CharacterBackup`, empty if the character can't be transferred.
+        #[bw(pad_size_to = CHARACTER_BACKUP_MAX_LENGTH)]
+        #[br(count = CHARACTER_BACKUP_MAX_LENGTH)]
+        #[br(map = read_string)]
+        #[bw(map = write_string)]
+        backup: String,
+    },
+    #[br(pre_assert(*magic == CustomIpcType::ReceiveTransfer))]
+    ReceiveTransfer {
+        /// The service account the character is added to.
+        service_account_id: u32,
+        /// JSON of the `CharacterBackup`, as sent in TransferBegun.
+        #[bw(pad_size_to = CHARACTER_BACKUP_MAX_LENGTH)]
+        #[br(count = CHARACTER_BACKUP_MAX_LENGTH)]
+        #[br(map = read_string)]
+        #[bw(map = write_string)]
+        backup: String,
+    },
+    #[br(pre_assert(*magic == CustomIpcType::TransferReceived))]
+    TransferReceived {
+        actor_id: u32,
+        /// Zero if the character couldn't be created on this world.
+        content_id: u64,
+    },
+    #[br(pre_assert(*magic == CustomIpcType::CancelTransfer))]
+    CancelTransfer { content_id: u64 },
+    #[br(pre_assert(*magic == CustomIpcType::TransferCancelled))]
+    TransferCancelled {
+        #[br(map = read_bool_from::<u8>)]
+        #[bw(map = write_bool_as::<u8>)]
+        cancelled: bool,
+    },
+    #[br(pre_assert(*magic == CustomIpcType::StoreSettings))]
+    StoreSettings {
+        content_id: u64,
+        /// What kind of settings these are, e.g. keybinds or hotbars. The lobby can't tell them apart yet, so it's always the same.
+        #[brw(pad_after = 3)]
+        kind: u8,
+        /// Where this chunk goes in the settings, an offset of 0 replaces any previously stored settings.
+        offset: u32,
+        #[bw(calc = data.len() as u32)]
+        length: u32,
+        #[br(count = length)]
+        #[brw(pad_size_to = SETTINGS_CHUNK_SIZE)]
+        data: Vec<u8>,
+    },
+    #[br(pre_assert(*magic == CustomIpcType::SettingsStored))]
+    SettingsStored {
+        #[br(map = read_bool_from::<u8>)]
+        #[bw(map = write_bool_as::<u8>)]
+        stored: bool,
+    },
+    #[br(pre_assert(*magic == CustomIpcType::RequestRetainerList))]
+    RequestRetainerList {
+        service_account_id: u32,
+        /// Index of the first retainer to send, the list is sent in pages of `RETAINER_LIST_PAGE_SIZE`.
+        offset: u32,
+    },
+    #[br(pre_assert(*magic == CustomIpcType::RetainerListResponse))]
+    RetainerListResponse {
+        /// Total number of retainers, across all pages.
+        total: u32,
+        /// How many retainers the service account can have on this world.
+        total_slots: u32,
+        #[bw(calc = retainers.len() as u8)]
+        num_retainers: u8,
+        #[br(count = num_retainers)]
+        #[brw(pad_size_to = RetainerInfo::SIZE * RETAINER_LIST_PAGE_SIZE)]
+        retainers: Vec<RetainerInfo>,
+    },
+    #[br(pre_assert(*magic == CustomIpcType::RemakeRetainer))]
+    RemakeRetainer {
+        retainer_id: u64,
+        /// The service account asking for the remake, which has to own the retainer.
+        service_account_id: u32,
+        #[bw(pad_size_to = CHAR_NAME_MAX_LENGTH)]
+        #[br(count = CHAR_NAME_MAX_LENGTH)]
+        #[br(map = read_string)]
+        #[bw(map = write_string)]
+        name: String,
+    },
+    #[br(pre_assert(*magic == CustomIpcType::RetainerRemade))]
+    RetainerRemade {
+        #[br(map = read_bool_from::<u8>)]
+        #[bw(map = write_bool_as::<u8>)]
+        remade: bool,
+        /// Whether the remake failed because the new name is used by another retainer.
+        #[br(map = read_bool_from::<u8>)]
+        #[bw(map = write_bool_as::<u8>)]
+        name_taken: bool,
+    },
+    #[br(pre_assert(*magic == CustomIpcType::RemoveServiceAccount))]
+    RemoveServiceAccount { service_account_id: u32 },
+    #[br(pre_assert(*magic == CustomIpcType::ServiceAccountRemoved))]
+    ServiceAccountRemoved {
+        /// Number of characters that were deleted.
+        deleted_characters: u32,
+        /// Number of worlds the lobby server couldn't reach, always zero when sent by a world server.
+        unreachable_worlds: u32,
+    },
+    #[br(pre_assert(*magic == CustomIpcType::FetchSettings))]
+    FetchSettings {
+        content_id: u64,
+        /// Which of the character's settings to send, they are ordered by kind.
+        index: u32,
+        /// Where in the settings the chunk starts.
+        offset: u32,
+    },
+    #[br(pre_assert(*magic == CustomIpcType::SettingsFetched))]
+    SettingsFetched {
+        /// False if the character has no settings at `index`.
+        #[br(map = read_bool_from::<u8>)]
+        #[bw(map = write_bool_as::<u8>)]
+        #[brw(pad_after = 3)]
+        found: bool,
+        #[brw(pad_after = 3)]
+        kind: u8,
+        /// Size of the whole settings, not just this chunk.
+        total: u32,
+        #[bw(calc = data.len() as u32)]
+        length: u32,
+        #[br(count = length)]
+        #[brw(pad_size_to = SETTINGS_CHUNK_SIZE)]
+        data: Vec<u8>,
+    },
+    #[br(pre_assert(*magic == CustomIpcType::RemakeCharacter))]
+    RemakeCharacter {
+        content_id: u64,
+        /// Base64 encoded CharaInfo, the same as in RequestCreateCharacter.
+        #[bw(pad_size_to = 1024)]
+        #[br(count = 1024)]
+        #[br(map = read_string)]
+        #[bw(map = write_string)]
+        encoded: String,
+    },
+    #[br(pre_assert(*magic == CustomIpcType::CharacterRemade))]
+    CharacterRemade {
+        /// Zero if the character isn't allowed to remake their appearance.
+        content_id: u64,
+    },
+    #[br(pre_assert(*magic == CustomIpcType::RegisterWorld))]
+    RegisterWorld {
+        world_id: u16,
+        port: u16,
+        /// Maximum number of players allowed on the world, 0 for no limit.
+        capacity: u32,
+        population: u32,
+        /// Public-facing IP address of the world server.
+        #[bw(pad_size_to = 48)]
+        #[br(count = 48)]
+        #[br(map = read_string)]
+        #[bw(map = write_string)]
+        server_name: String,
+        #[bw(pad_size_to = CHAR_NAME_MAX_LENGTH)]
+        #[br(count = CHAR_NAME_MAX_LENGTH)]
+        #[br(map = read_string)]
+        #[bw(map = write_string)]
+        name: String,
+    },
+    #[br(pre_assert(*magic == CustomIpcType::WorldRegistered))]
+    WorldRegistered {
+        #[br(map = read_bool_from::<u8>)]
+        #[bw(map = write_bool_as::<u8>)]
+        accepted: bool,
+    },
+    #[br(pre_assert(*magic == CustomIpcType::WorldHeartbeat))]
+    WorldHeartbeat {
+        #[brw(pad_after = 2)]
+        world_id: u16,
+        population: u32,
+    },
+    #[br(pre_assert(*magic == CustomIpcType::RequestAuthChallenge))]
+    RequestAuthChallenge {},
+    #[br(pre_assert(*magic == CustomIpcType::AuthChallenge))]
+    AuthChallenge {
+        #[br(count = AUTH_CHALLENGE_SIZE)]
+        #[bw(pad_size_to = AUTH_CHALLENGE_SIZE)]
+        challenge: Vec<u8>,
+    },
+    #[br(pre_assert(*magic == CustomIpcType::Authenticate))]
+    Authenticate {
+        #[br(count = SIGNATURE_SIZE)]
+        #[bw(pad_size_to = SIGNATURE_SIZE)]
+        signature: Vec<u8>,
+    },
+    #[br(pre_assert(*magic == CustomIpcType::Authenticated))]
+    Authenticated {
+        #[br(map = read_bool_from::<u8>)]
+        #[bw(map = write_bool_as::<u8>)]
+        accepted: bool,
+    },
+    #[br(pre_assert(*magic == CustomIpcType::GrantWorldEntry))]
+    GrantWorldEntry {
+        /// See `EntryToken`.
+        #[bw(pad_size_to = ENTRY_TOKEN_MAX_LENGTH)]
+        #[br(count = ENTRY_TOKEN_MAX_LENGTH)]
+        #[br(map = read_string)]
+        #[bw(map = write_string)]
+        token: String,
+        /// Address the client connected to the lobby from, IPv4 addresses are mapped to IPv6.
+        client_ip: [u8; 16],
+    },
+    #[br(pre_assert(*magic == CustomIpcType::WorldEntryGranted))]
+    WorldEntryGranted {
+        #[br(map = read_bool_from::<u8>)]
+        #[bw(map = write_bool_as::<u8>)]
+        accepted: bool,
+    },
+    #[br(pre_assert(*magic == CustomIpcType::RenameCharacter))]
+    RenameCharacter {
+        content_id: u64,
+        #[bw(pad_size_to = CHAR_NAME_MAX_LENGTH)]
+        #[br(count = CHAR_NAME_MAX_LENGTH)]
+        #[br(map = read_string)]
+        #[bw(map = write_string)]
+        name: String,
+    },
+    #[br(pre_assert(*magic == CustomIpcType::CharacterRenamed))]
+    CharacterRenamed {
+        #[br(map = read_bool_from::<u8>)]
+        #[bw(map = write_bool_as::<u8>)]
+        renamed: bool,
+        /// Whether the rename failed because the new name is used by another character.
+        #[br(map = read_bool_from::<u8>)]
+        #[bw(map = write_bool_as::<u8>)]
+        name_taken: bool,
+        /// Whether the rename failed because the character wasn't asked to change their name, see `CharacterFlag::NAME_CHANGE_REQUIRED`.
+        #[br(map = read_bool_from::<u8>)]
+        #[bw(map = write_bool_as::<u8>)]
+        not_allowed: bool,
+    },
 }
 
 impl Default for CustomIpcData {
@@ -146,3 +549,300 @@ impl Default for CustomIpcData {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use binrw::BinWrite;
+
+    use super::*;
+
+    /// Ensure that the IPC data size as reported matches up with what we write
+    #[test]
+    fn custom_ipc_sizes() {
+        let ipc_types = [
+            (
+                CustomIpcType::RequestCreateCharacter,
+                CustomIpcData::RequestCreateCharacter {
+                    service_account_id: 0,
+                    name: String::default(),
+                    encoded: String::default(),
+                },
+            ),
+            (
+                CustomIpcType::CharacterCreated,
+                CustomIpcData::CharacterCreated {
+                    actor_id: 0,
+                    content_id: 0,
+                },
+            ),
+            (
+                CustomIpcType::GetActorId,
+                CustomIpcData::GetActorId { content_id: 0 },
+            ),
+            (
+                CustomIpcType::ActorIdFound,
+                CustomIpcData::ActorIdFound { actor_id: 0 },
+            ),
+            (
+                CustomIpcType::CheckNameIsAvailable,
+                CustomIpcData::CheckNameIsAvailable {
+                    name: String::default(),
+                },
+            ),
+            (
+                CustomIpcType::NameIsAvailableResponse,
+                CustomIpcData::NameIsAvailableResponse { free: false },
+            ),
+            (
+                CustomIpcType::RequestCharacterList,
+                CustomIpcData::RequestCharacterList {
+                    service_account_id: 0,
+                    offset: 0,
+                },
+            ),
+            (
+                CustomIpcType::RequestCharacterListRepsonse,
+                CustomIpcData::RequestCharacterListRepsonse {
+                    total: 0,
+                    characters: Vec::new(),
+                },
+            ),
+            (
+                CustomIpcType::DeleteCharacter,
+                CustomIpcData::DeleteCharacter { content_id: 0 },
+            ),
+            (
+                CustomIpcType::CharacterDeleted,
+                CustomIpcData::CharacterDeleted { deleted: 0 },
+            ),
+            (
+                CustomIpcType::ImportCharacter,
+                CustomIpcData::ImportCharacter {
+                    service_account_id: 0,
+                    backup: String::default(),
+                },
+            ),
+            (
+                CustomIpcType::CharacterImported,
+                CustomIpcData::CharacterImported { content_id: 0 },
+            ),
+            (
+                CustomIpcType::ExportCharacter,
+                CustomIpcData::ExportCharacter { content_id: 0 },
+            ),
+            (
+                CustomIpcType::CharacterExported,
+                CustomIpcData::CharacterExported {
+                    backup: String::default(),
+                },
+            ),
+            (
+                CustomIpcType::RemakeCharacter,
+                CustomIpcData::RemakeCharacter {
+                    content_id: 0,
+                    encoded: String::default(),
+                },
+            ),
+            (
+                CustomIpcType::CharacterRemade,
+                CustomIpcData::CharacterRemade { content_id: 0 },
+            ),
+            (
+                CustomIpcType::RegisterWorld,
+                CustomIpcData::RegisterWorld {
+                    world_id: 0,
+                    port: 0,
+                    capacity: 0,
+                    population: 0,
+                    server_name: String::default(),
+                    name: String::default(),
+                },
+            ),
+            (
+                CustomIpcType::WorldRegistered,
+                CustomIpcData::WorldRegistered { accepted: false },
+            ),
+            (
+                CustomIpcType::WorldHeartbeat,
+                CustomIpcData::WorldHeartbeat {
+                    world_id: 0,
+                    population: 0,
+                },
+            ),
+            (
+                CustomIpcType::RequestAuthChallenge,
+                CustomIpcData::RequestAuthChallenge {},
+            ),
+            (
+                CustomIpcType::AuthChallenge,
+                CustomIpcData::AuthChallenge {
+                    challenge: Vec::new(),
+                },
+            ),
+            (
+                CustomIpcType::Authenticate,
+                CustomIpcData::Authenticate {
+                    signature: Vec::new(),
+                },
+            ),
+            (
+                CustomIpcType::Authenticated,
+                CustomIpcData::Authenticated { accepted: false },
+            ),
+            (
+                CustomIpcType::GrantWorldEntry,
+                CustomIpcData::GrantWorldEntry {
+                    token: String::default(),
+                    client_ip: [0; 16],
+                },
+            ),
+            (
+                CustomIpcType::WorldEntryGranted,
+                CustomIpcData::WorldEntryGranted { accepted: false },
+            ),
+            (
+                CustomIpcType::RenameCharacter,
+                CustomIpcData::RenameCharacter {
+                    content_id: 0,
+                    name: String::default(),
+                },
+            ),
+            (
+                CustomIpcType::CharacterRenamed,
+                CustomIpcData::CharacterRenamed {
+                    renamed: false,
+                    name_taken: false,
+                    not_allowed: false,
+                },
+            ),
+            (
+                CustomIpcType::BeginTransfer,
+                CustomIpcData::BeginTransfer { content_id: 0 },
+            ),
+            (
+                CustomIpcType::TransferBegun,
+                CustomIpcData::TransferBegun {
+                    backup: String::default(),
+                },
+            ),
+            (
+                CustomIpcType::ReceiveTransfer,
+                CustomIpcData::ReceiveTransfer {
+                    service_account_id: 0,
+                    backup: String::default(),
+                },
+            ),
+            (
+                CustomIpcType::TransferReceived,
+                CustomIpcData::TransferReceived {
+                    actor_id: 0,
+                    content_id: 0,
+                },
+            ),
+            (
+                CustomIpcType::CancelTransfer,
+                CustomIpcData::CancelTransfer { content_id: 0 },
+            ),
+            (
+                CustomIpcType::TransferCancelled,
+                CustomIpcData::TransferCancelled { cancelled: false },
+            ),
+            (
+                CustomIpcType::StoreSettings,
+                CustomIpcData::StoreSettings {
+                    content_id: 0,
+                    kind: 0,
+                    offset: 0,
+                    data: Vec::new(),
+                },
+            ),
+            (
+                CustomIpcType::SettingsStored,
+                CustomIpcData::SettingsStored { stored: false },
+            ),
+            (
+                CustomIpcType::RequestRetainerList,
+                CustomIpcData::RequestRetainerList {
+                    service_account_id: 0,
+                    offset: 0,
+                },
+            ),
+            (
+                CustomIpcType::RetainerListResponse,
+                CustomIpcData::RetainerListResponse {
+                    total: 0,
+                    total_slots: 0,
+                    retainers: Vec::new(),
+                },
+            ),
+            (
+                CustomIpcType::RemakeRetainer,
+                CustomIpcData::RemakeRetainer {
+                    retainer_id: 0,
+                    service_account_id: 0,
+                    name: String::default(),
+                },
+            ),
+            (
+                CustomIpcType::RetainerRemade,
+                CustomIpcData::RetainerRemade {
+                    remade: false,
+                    name_taken: false,
+                },
+            ),
+            (
+                CustomIpcType::RemoveServiceAccount,
+                CustomIpcData::RemoveServiceAccount {
+                    service_account_id: 0,
+                },
+            ),
+            (
+                CustomIpcType::ServiceAccountRemoved,
+                CustomIpcData::ServiceAccountRemoved {
+                    deleted_characters: 0,
+                    unreachable_worlds: 0,
+                },
+            ),
+            (
+                CustomIpcType::FetchSettings,
+                CustomIpcData::FetchSettings {
+                    content_id: 0,
+                    index: 0,
+                    offset: 0,
+                },
+            ),
+            (
+                CustomIpcType::SettingsFetched,
+                CustomIpcData::SettingsFetched {
+                    found: false,
+                    kind: 0,
+                    total: 0,
+                    data: Vec::new(),
+                },
+            ),
+        ];
+
+        for (opcode, ipc) in &ipc_types {
+            let mut cursor = Cursor::new(Vec::new());
+
+            let ipc_segment = CustomIpcSegment {
+                request_id: 0,
+                op_code: opcode.clone(),
+                timestamp: 0,
+                data: ipc.clone(),
+            };
+            ipc_segment.write_le(&mut cursor).unwrap();
+
+            let buffer = cursor.into_inner();
+
+            assert_eq!(
+                buffer.len(),
+                ipc_segment.calc_size() as usize,
+                "{:#?} did not match size!",
+                opcode
+            );
+        }
+    }
+}
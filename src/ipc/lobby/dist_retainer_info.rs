@@ -5,21 +5,31 @@ use crate::common::{CHAR_NAME_MAX_LENGTH, read_string, write_string};
 #[binrw]
 #[derive(Debug, Clone, Default)]
 pub struct RetainerInfo {
-    id: u64,
-    owner_id: u64,
-    slot_id: u8,
-    param1: u8,
-    status: u16,
-    param2: u32,
+    pub id: u64,
+    /// Content id of the character that hired this retainer.
+    pub owner_id: u64,
+    /// Which of the owner's retainer slots this is.
+    pub slot_id: u8,
+    pub param1: u8,
+    /// See `RetainerInfo::STATUS_ACTIVE`.
+    pub status: u16,
+    pub param2: u32,
     #[bw(pad_size_to = CHAR_NAME_MAX_LENGTH)]
     #[br(count = CHAR_NAME_MAX_LENGTH)]
     #[br(map = read_string)]
     #[bw(map = write_string)]
-    name: String,
+    pub name: String,
 }
 
 impl RetainerInfo {
     pub const SIZE: usize = 56;
+
+    /// The retainer is hired and working.
+    // TODO: this is a guess, none of the status values have been verified yet
+    pub const STATUS_ACTIVE: u16 = 1;
+
+    /// Maximum number of retainers in a single `DistRetainerInfo`.
+    pub const MAX_PER_PACKET: usize = 9;
 }
 
 #[binrw]
@@ -37,7 +47,7 @@ pub struct DistRetainerInfo {
     pub num_free_slots: u16,
     pub total_retainers: u16,
     pub active_retainers: u16,
-    #[br(count = 9)]
-    #[brw(pad_size_to = (9 * RetainerInfo::SIZE))]
+    #[br(count = RetainerInfo::MAX_PER_PACKET)]
+    #[brw(pad_size_to = (RetainerInfo::MAX_PER_PACKET * RetainerInfo::SIZE))]
     pub characters: Vec<RetainerInfo>,
 }
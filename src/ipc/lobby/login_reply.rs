@@ -4,17 +4,27 @@ use serde::{Deserialize, Serialize};
 use crate::common::{read_string, write_string};
 
 #[binrw]
-#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize, Serialize)]
 pub struct ServiceAccount {
     pub id: u64,
     pub index: u32,
-    #[bw(pad_size_to = 0x44)]
-    #[br(count = 0x44)]
+    #[bw(pad_size_to = ServiceAccount::NAME_SIZE)]
+    #[br(count = ServiceAccount::NAME_SIZE)]
     #[br(map = read_string)]
     #[bw(map = write_string)]
     pub name: String,
 }
 
+impl ServiceAccount {
+    pub const SIZE: usize = 80;
+
+    /// Size of `name` including the null terminator.
+    pub const NAME_SIZE: usize = 0x44;
+
+    /// Maximum number of service accounts in `LoginReply`.
+    pub const MAX_PER_PACKET: usize = 8;
+}
+
 #[binrw]
 #[derive(Debug, Clone, Default)]
 pub struct LoginReply {
@@ -24,7 +34,7 @@ pub struct LoginReply {
     pub unk1: u8,
     #[brw(pad_after = 4)]
     pub unk2: u8,
-    #[br(count = 8)]
-    #[brw(pad_size_to = (8 * 80))]
+    #[br(count = ServiceAccount::MAX_PER_PACKET)]
+    #[brw(pad_size_to = (ServiceAccount::MAX_PER_PACKET * ServiceAccount::SIZE))]
     pub service_accounts: Vec<ServiceAccount>,
 }
@@ -1,26 +1,55 @@
-use std::cmp::min;
+use std::{
+    collections::HashMap,
+    net::IpAddr,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
 
-use tokio::net::TcpStream;
+use base64::{Engine as _, engine::general_purpose::URL_SAFE};
+use tokio::{io::AsyncReadExt, net::TcpStream};
 
 use crate::{
+    RECEIVE_BUFFER_SIZE,
     blowfish::Blowfish,
-    common::timestamp_secs,
+    common::{EntryToken, PRIVATE_API_HEADER, private_api_token, timestamp_secs},
     config::get_config,
-    ipc::lobby::{DistRetainerInfo, NackReply},
+    ipc::lobby::{DistRetainerInfo, NackReply, RetainerInfo},
+    login::Ban,
     opcodes::ServerLobbyIpcType,
     packet::{
-        CompressionType, ConnectionType, PacketSegment, PacketState, SegmentData, SegmentType,
-        generate_encryption_key, parse_packet, send_custom_world_packet, send_packet,
+        CompressionType, ConnectionType, CustomIpcAuth, PacketSegment, PacketState, SegmentData,
+        SegmentType, generate_encryption_key, parse_packet, send_custom_ipc, send_keep_alive,
+        send_packet,
     },
 };
 
-use crate::ipc::kodama::{CustomIpcData, CustomIpcSegment, CustomIpcType};
+use crate::ipc::kodama::{CustomIpcData, CustomIpcSegment, CustomIpcType, SETTINGS_CHUNK_SIZE};
 use crate::ipc::lobby::{
     CharaMake, CharacterDetails, ClientLobbyIpcSegment, DistWorldInfo, LobbyCharacterActionKind,
     LoginReply, Server, ServerLobbyIpcData, ServerLobbyIpcSegment, ServiceAccount,
     ServiceLoginReply,
 };
 
+use super::{LobbyError, LoginQueue, RegisteredWorld, WorldRegistry, WorldStatus};
+
+/// Number of characters in each `ServiceLoginReply`.
+const CHARACTERS_PER_PACKET: usize = 2;
+
+/// Minimum number of `ServiceLoginReply` packets sent for the character list.
+const MIN_CHARACTER_LIST_PACKETS: usize = 4;
+
+/// The kind every uploaded settings are stored as.
+// TODO: it's not known which field of CharaMake tells us what kind of settings are being uploaded (keybinds, hotbars, etc.),
+// so each upload replaces whatever was stored for the character before. Give each kind it's own slot once that's known.
+const SETTINGS_KIND: u8 = 0;
+
+/// A settings upload that is in progress, see `LobbyCharacterActionKind::SettingsUploadBegin`.
+pub struct SettingsUpload {
+    pub content_id: u32,
+    /// How much of the settings has been stored so far.
+    pub offset: u32,
+}
+
 /// Represents a single connection between an instance of the client and the lobby server.
 pub struct LobbyConnection {
     pub socket: TcpStream,
@@ -31,11 +60,34 @@ pub struct LobbyConnection {
 
     pub stored_character_creation_name: String,
 
-    pub world_name: String,
+    /// The world servers shown in the lobby, shared between all connections.
+    pub world_registry: Arc<Mutex<WorldRegistry>>,
+
+    /// Which world each character in the list lives on, keyed by the `player_id` the character list gave the client.
+    // TODO: player_id isn't known to be what the client sends back as the content id
+    pub character_worlds: HashMap<u32, u16>,
+
+    /// Whether every world told us about it's characters, otherwise `character_worlds` may be missing some.
+    pub character_list_complete: bool,
+
+    /// Which world each retainer in the list lives on, keyed by retainer id.
+    pub retainer_worlds: HashMap<u64, u16>,
 
     pub service_accounts: Vec<ServiceAccount>,
 
     pub selected_service_account: Option<u32>,
+
+    /// Whether the other side is a world server that proved it knows the shared secret.
+    pub custom_ipc_auth: CustomIpcAuth,
+
+    /// The settings upload the client is currently doing, if any.
+    pub settings_upload: Option<SettingsUpload>,
+
+    /// Players waiting to enter full worlds, shared between all connections.
+    pub login_queue: Arc<Mutex<LoginQueue>>,
+
+    /// Segments the client sent while waiting in the login queue, which are handled once it's done waiting.
+    pub deferred_segments: Vec<PacketSegment<ClientLobbyIpcSegment>>,
 }
 
 impl LobbyConnection {
@@ -46,6 +98,35 @@ impl LobbyConnection {
         parse_packet(data, &mut self.state)
     }
 
+    /// Returns the world with `world_id`, or the first world if it doesn't exist.
+    pub fn get_world(&self, world_id: u16) -> Option<RegisteredWorld> {
+        let world_registry = self.world_registry.lock().unwrap();
+        world_registry
+            .get_world(world_id)
+            .or(world_registry.worlds().first())
+            .cloned()
+    }
+
+    /// Returns the world that the character with `content_id` lives on.
+    pub fn get_character_world(&self, content_id: u32) -> Option<RegisteredWorld> {
+        let world_id = self.character_worlds.get(&content_id)?;
+        self.world_registry
+            .lock()
+            .unwrap()
+            .get_world(*world_id)
+            .cloned()
+    }
+
+    /// Returns the world that the retainer with `retainer_id` lives on.
+    pub fn get_retainer_world(&self, retainer_id: u64) -> Option<RegisteredWorld> {
+        let world_id = self.retainer_worlds.get(&retainer_id)?;
+        self.world_registry
+            .lock()
+            .unwrap()
+            .get_world(*world_id)
+            .cloned()
+    }
+
     pub async fn send_segment(&mut self, segment: PacketSegment<ServerLobbyIpcSegment>) {
         send_packet(
             &mut self.socket,
@@ -77,6 +158,74 @@ impl LobbyConnection {
         .await;
     }
 
+    /// Asks the login server for the service accounts of `session_id`, disabled ones aren't included.
+    /// This is done every time they are needed, so changes made on the website show up right away.
+    pub async fn fetch_service_accounts(
+        session_id: &str,
+    ) -> Result<Vec<ServiceAccount>, LobbyError> {
+        let config = get_config();
+
+        let Ok(reply) = reqwest::Client::new()
+            .get(format!(
+                "http://{}/_private/service_accounts?sid={}",
+                config.login.server_name, session_id
+            ))
+            .header(PRIVATE_API_HEADER, private_api_token(&config.shared_secret))
+            .send()
+            .await
+        else {
+            tracing::warn!("Failed to contact login server, is it running?");
+            return Err(LobbyError::LoginServerUnavailable);
+        };
+
+        let Ok(body) = reply.text().await else {
+            tracing::warn!("Failed to contact login server, is it running?");
+            return Err(LobbyError::LoginServerUnavailable);
+        };
+
+        match serde_json::from_str::<Vec<ServiceAccount>>(&body) {
+            Ok(service_accounts) if !service_accounts.is_empty() => Ok(service_accounts),
+            Ok(_) => {
+                tracing::warn!(
+                    "The session is invalid, or the account has no enabled service accounts!"
+                );
+                Err(LobbyError::InvalidSession)
+            }
+            Err(_) => {
+                tracing::warn!("The login server sent an invalid service account list!");
+                Err(LobbyError::LoginServerUnavailable)
+            }
+        }
+    }
+
+    /// Asks the login server whether `service_account_id` is suspended.
+    pub async fn fetch_suspension(service_account_id: u32) -> Result<Option<Ban>, LobbyError> {
+        let config = get_config();
+
+        let Ok(reply) = reqwest::Client::new()
+            .get(format!(
+                "http://{}/_private/suspension?service_account_id={}",
+                config.login.server_name, service_account_id
+            ))
+            .header(PRIVATE_API_HEADER, private_api_token(&config.shared_secret))
+            .send()
+            .await
+        else {
+            tracing::warn!("Failed to contact login server, is it running?");
+            return Err(LobbyError::LoginServerUnavailable);
+        };
+
+        let Ok(body) = reply.text().await else {
+            tracing::warn!("Failed to contact login server, is it running?");
+            return Err(LobbyError::LoginServerUnavailable);
+        };
+
+        serde_json::from_str(&body).map_err(|_| {
+            tracing::warn!("The login server sent an invalid suspension!");
+            LobbyError::LoginServerUnavailable
+        })
+    }
+
     /// Send the service account list to the client.
     pub async fn send_account_list(&mut self) {
         let service_account_list = ServerLobbyIpcData::LoginReply(LoginReply {
@@ -102,62 +251,168 @@ impl LobbyConnection {
         .await;
     }
 
+    /// Asks every world for the retainers on the selected service account.
+    /// Returns the retainers, and how many retainers the service account can have in total.
+    async fn get_retainer_list(&mut self, worlds: &[RegisteredWorld]) -> (Vec<RetainerInfo>, u32) {
+        let mut retainers = Vec::new();
+        let mut total_slots = 0;
+        self.retainer_worlds.clear();
+
+        for world in worlds {
+            // the world sends it's retainers in pages
+            let mut offset = 0;
+            loop {
+                let request = CustomIpcSegment {
+                    op_code: CustomIpcType::RequestRetainerList,
+                    data: CustomIpcData::RequestRetainerList {
+                        service_account_id: self.selected_service_account.unwrap(),
+                        offset,
+                    },
+                    ..Default::default()
+                };
+
+                let Some(response) = world.channel.request(request).await else {
+                    tracing::warn!("Failed to get the retainer list from {}!", world.name);
+                    break;
+                };
+                let CustomIpcData::RetainerListResponse {
+                    total,
+                    total_slots: world_slots,
+                    retainers: world_retainers,
+                } = response.data
+                else {
+                    tracing::warn!("Unexpected custom IPC from {}!", world.name);
+                    break;
+                };
+
+                if offset == 0 {
+                    total_slots += world_slots;
+                }
+
+                offset += world_retainers.len() as u32;
+                let done = world_retainers.is_empty() || offset >= total;
+
+                for retainer in world_retainers {
+                    self.retainer_worlds.insert(retainer.id, world.id);
+                    retainers.push(retainer);
+                }
+
+                if done {
+                    break;
+                }
+            }
+        }
+
+        (retainers, total_slots)
+    }
+
     /// Send the world, retainer and character list to the client.
     pub async fn send_lobby_info(&mut self, sequence: u64) {
+        // offline worlds can't tell us about their characters or retainers anyway
+        let (worlds, any_offline): (Vec<RegisteredWorld>, bool) = {
+            let world_registry = self.world_registry.lock().unwrap();
+            let worlds: Vec<RegisteredWorld> = world_registry
+                .worlds()
+                .iter()
+                .filter(|world| world_registry.get_status(world.id) != WorldStatus::Offline)
+                .cloned()
+                .collect();
+            let any_offline = worlds.len() != world_registry.worlds().len();
+
+            (worlds, any_offline)
+        };
+
         let mut packets = Vec::new();
-        // send them the server list
+        // send them the server list, which fits 6 servers per packet
         {
-            let config = get_config();
+            let servers = self.world_registry.lock().unwrap().servers();
 
-            let mut servers = [Server {
-                id: config.world.world_id,
-                name: self.world_name.clone(),
-                ..Default::default()
-            }]
-            .to_vec();
-            // add any empty boys
-            servers.resize(6, Server::default());
-
-            let lobby_server_list = ServerLobbyIpcData::DistWorldInfo(DistWorldInfo {
-                sequence,
-                num_servers: 1,
-                servers,
-                ..Default::default()
-            });
+            for (i, chunk) in servers.chunks(6).enumerate() {
+                let mut servers = chunk.to_vec();
+                let num_servers = servers.len() as u32;
+                // add any empty boys
+                servers.resize(6, Server::default());
 
-            let ipc = ServerLobbyIpcSegment {
-                op_code: ServerLobbyIpcType::DistWorldInfo,
-                timestamp: timestamp_secs(),
-                data: lobby_server_list,
-                ..Default::default()
-            };
+                let lobby_server_list = ServerLobbyIpcData::DistWorldInfo(DistWorldInfo {
+                    sequence,
+                    offset: (i * 6) as u8,
+                    num_servers,
+                    servers,
+                });
 
-            let response_packet = PacketSegment {
-                segment_type: SegmentType::Ipc,
-                data: SegmentData::Ipc { data: ipc },
-                ..Default::default()
-            };
-            packets.push(response_packet);
+                let ipc = ServerLobbyIpcSegment {
+                    op_code: ServerLobbyIpcType::DistWorldInfo,
+                    timestamp: timestamp_secs(),
+                    data: lobby_server_list,
+                    ..Default::default()
+                };
+
+                let response_packet = PacketSegment {
+                    segment_type: SegmentType::Ipc,
+                    data: SegmentData::Ipc { data: ipc },
+                    ..Default::default()
+                };
+                packets.push(response_packet);
+            }
         }
 
-        // send them the retainer list
+        // send them the retainer list, which fits 9 retainers per packet
+        // TODO: how the client uses index, count and the counters hasn't been verified yet
         {
-            let lobby_retainer_list =
-                ServerLobbyIpcData::DistRetainerInfo(DistRetainerInfo::default());
+            let (retainers, total_slots) = self.get_retainer_list(&worlds).await;
 
-            let ipc = ServerLobbyIpcSegment {
-                op_code: ServerLobbyIpcType::DistRetainerInfo,
-                timestamp: timestamp_secs(),
-                data: lobby_retainer_list,
-                ..Default::default()
-            };
+            let num_contracted = retainers.len() as u16;
+            let num_active = retainers
+                .iter()
+                .filter(|retainer| retainer.status == RetainerInfo::STATUS_ACTIVE)
+                .count() as u16;
+            let num_total = total_slots as u16;
 
-            let response_packet = PacketSegment {
-                segment_type: SegmentType::Ipc,
-                data: SegmentData::Ipc { data: ipc },
-                ..Default::default()
-            };
-            packets.push(response_packet);
+            // always send at least one packet, even if there are no retainers
+            let num_packets = retainers
+                .len()
+                .div_ceil(RetainerInfo::MAX_PER_PACKET)
+                .max(1);
+            let mut retainers = retainers.into_iter();
+
+            for i in 0..num_packets {
+                let mut retainers_in_packet: Vec<RetainerInfo> = retainers
+                    .by_ref()
+                    .take(RetainerInfo::MAX_PER_PACKET)
+                    .collect();
+                let count = retainers_in_packet.len() as u8;
+                // add any empty boys
+                retainers_in_packet.resize(RetainerInfo::MAX_PER_PACKET, RetainerInfo::default());
+
+                let lobby_retainer_list = ServerLobbyIpcData::DistRetainerInfo(DistRetainerInfo {
+                    sequence,
+                    timestamp: timestamp_secs(),
+                    index: i as u8,
+                    count,
+                    num_contracted,
+                    num_active,
+                    num_total,
+                    num_free_slots: num_total.saturating_sub(num_contracted),
+                    total_retainers: num_contracted,
+                    active_retainers: num_active,
+                    characters: retainers_in_packet,
+                    ..Default::default()
+                });
+
+                let ipc = ServerLobbyIpcSegment {
+                    op_code: ServerLobbyIpcType::DistRetainerInfo,
+                    timestamp: timestamp_secs(),
+                    data: lobby_retainer_list,
+                    ..Default::default()
+                };
+
+                let response_packet = PacketSegment {
+                    segment_type: SegmentType::Ipc,
+                    data: SegmentData::Ipc { data: ipc },
+                    ..Default::default()
+                };
+                packets.push(response_packet);
+            }
         }
 
         send_packet(
@@ -169,39 +424,75 @@ impl LobbyConnection {
         )
         .await;
 
-        // now send them the character list
+        // now send them the character list, which is gathered from every world
         {
-            let charlist_request = CustomIpcSegment {
-                op_code: CustomIpcType::RequestCharacterList,
-                data: CustomIpcData::RequestCharacterList {
-                    service_account_id: self.selected_service_account.unwrap(),
-                },
-                ..Default::default()
-            };
+            let mut characters = Vec::new();
+            self.character_worlds.clear();
+            self.character_list_complete = !any_offline;
 
-            let name_response = send_custom_world_packet(charlist_request)
-                .await
-                .expect("Failed to get name request packet!");
-            let CustomIpcData::RequestCharacterListRepsonse { characters } = &name_response.data
-            else {
-                panic!("Unexpedted custom IPC type!")
-            };
+            for world in &worlds {
+                // the world sends it's characters in pages
+                let mut offset = 0;
+                loop {
+                    let charlist_request = CustomIpcSegment {
+                        op_code: CustomIpcType::RequestCharacterList,
+                        data: CustomIpcData::RequestCharacterList {
+                            service_account_id: self.selected_service_account.unwrap(),
+                            offset,
+                        },
+                        ..Default::default()
+                    };
+
+                    let Some(charlist_response) = world.channel.request(charlist_request).await
+                    else {
+                        tracing::warn!("Failed to get the character list from {}!", world.name);
+                        self.character_list_complete = false;
+                        break;
+                    };
+                    let CustomIpcData::RequestCharacterListRepsonse {
+                        total,
+                        characters: world_characters,
+                    } = &charlist_response.data
+                    else {
+                        tracing::warn!("Unexpected custom IPC from {}!", world.name);
+                        self.character_list_complete = false;
+                        break;
+                    };
+
+                    for character in world_characters {
+                        self.character_worlds.insert(character.player_id, world.id);
 
-            let mut characters = characters.to_vec();
+                        let mut character = character.clone();
+                        character.index = characters.len() as u8;
+                        characters.push(character);
+                    }
 
-            for i in 0..4 {
-                let mut characters_in_packet = Vec::new();
-                for _ in 0..min(characters.len(), 2) {
-                    characters_in_packet.push(characters.swap_remove(0));
+                    offset += world_characters.len() as u32;
+                    if world_characters.is_empty() || offset >= *total {
+                        break;
+                    }
                 }
+            }
+
+            // The list is sent two characters at a time, with the counter going up by 4 for each packet.
+            // NOTE: We always send at least 4 packets (8 slots) as retail does, it's unknown if the client accepts less.
+            let num_packets = characters
+                .len()
+                .div_ceil(CHARACTERS_PER_PACKET)
+                .max(MIN_CHARACTER_LIST_PACKETS);
+            let mut characters = characters.into_iter();
+
+            for i in 0..num_packets {
+                let mut characters_in_packet: Vec<CharacterDetails> =
+                    characters.by_ref().take(CHARACTERS_PER_PACKET).collect();
                 // add any empty boys
-                characters_in_packet.resize(2, CharacterDetails::default());
+                characters_in_packet.resize(CHARACTERS_PER_PACKET, CharacterDetails::default());
 
-                let lobby_character_list = if i == 3 {
+                let lobby_character_list = if i == num_packets - 1 {
                     // On the last packet, add the account-wide information
                     ServiceLoginReply {
                         sequence,
-                        counter: (i * 4) + 1, // TODO: why the + 1 here?
+                        counter: (i * 4) as u8 + 1, // TODO: why the + 1 here?
                         num_in_packet: characters_in_packet.len() as u8,
                         characters: characters_in_packet,
                         ..Default::default()
@@ -209,7 +500,7 @@ impl LobbyConnection {
                 } else {
                     ServiceLoginReply {
                         sequence,
-                        counter: i * 4,
+                        counter: (i * 4) as u8,
                         num_in_packet: characters_in_packet.len() as u8,
                         characters: characters_in_packet,
                         ..Default::default()
@@ -235,17 +526,64 @@ impl LobbyConnection {
         }
     }
 
-    /// Send the host information for the world server to the client.
-    pub async fn send_enter_world(&mut self, sequence: u64, content_id: u64, actor_id: u32) {
+    /// Creates an entry token for this character, and hands it to the world server so it can let them in.
+    /// The world server only accepts it once, and only from the address the client is connected to us from.
+    /// Returns the token if the world server accepted it.
+    pub async fn grant_world_entry(
+        &self,
+        world: &RegisteredWorld,
+        content_id: u64,
+        actor_id: u32,
+    ) -> Option<String> {
         let config = get_config();
 
+        let client_ip = self.socket.peer_addr().ok()?.ip();
+
+        let token = EntryToken::new(
+            self.selected_service_account?,
+            content_id,
+            actor_id,
+            config.lobby.entry_token_lifetime,
+        )
+        .encode(&config.shared_secret);
+
+        let response = world
+            .channel
+            .request(CustomIpcSegment {
+                op_code: CustomIpcType::GrantWorldEntry,
+                data: CustomIpcData::GrantWorldEntry {
+                    token: token.clone(),
+                    client_ip: match client_ip {
+                        IpAddr::V4(ip) => ip.to_ipv6_mapped().octets(),
+                        IpAddr::V6(ip) => ip.octets(),
+                    },
+                },
+                ..Default::default()
+            })
+            .await?;
+
+        match response.data {
+            CustomIpcData::WorldEntryGranted { accepted: true } => Some(token),
+            _ => None,
+        }
+    }
+
+    /// Send the host information for the world server to the client.
+    pub async fn send_enter_world(
+        &mut self,
+        sequence: u64,
+        content_id: u64,
+        actor_id: u32,
+        token: &str,
+        world: &RegisteredWorld,
+    ) {
         let enter_world = ServerLobbyIpcData::GameLoginReply {
             sequence,
             actor_id,
             content_id,
-            token: String::new(),
-            port: config.world.port,
-            host: config.world.server_name,
+            token: token.to_string(),
+            port: world.port,
+            host: world.server_name.clone(),
         };
 
         let ipc = ServerLobbyIpcSegment {
@@ -263,12 +601,161 @@ impl LobbyConnection {
         .await;
     }
 
+    /// Handles custom IPC sent by a world server, such as registration and heartbeats.
+    pub async fn handle_custom_ipc(&mut self, data: &CustomIpcSegment) {
+        if let Some(response) = self.custom_ipc_auth.handle_handshake(data) {
+            send_custom_ipc(&mut self.socket, &mut self.state, response).await;
+            return;
+        }
+
+        if !self.custom_ipc_auth.is_authenticated() {
+            tracing::warn!(
+                "Rejecting unauthenticated custom IPC {:?} from {:?}!",
+                data.op_code,
+                self.socket.peer_addr()
+            );
+            return;
+        }
+
+        match &data.data {
+            CustomIpcData::RegisterWorld {
+                world_id,
+                port,
+                capacity,
+                population,
+                server_name,
+                name,
+            } => {
+                let accepted = self.world_registry.lock().unwrap().register(
+                    *world_id,
+                    name,
+                    server_name,
+                    *port,
+                    *capacity,
+                    *population,
+                );
+
+                if accepted {
+                    tracing::info!(
+                        "World {name} ({world_id}) registered itself at {server_name}:{port}"
+                    );
+                } else {
+                    tracing::warn!(
+                        "World {name} ({world_id}) tried to register with an invalid address {server_name}!"
+                    );
+                }
+
+                send_custom_ipc(
+                    &mut self.socket,
+                    &mut self.state,
+                    CustomIpcSegment {
+                        op_code: CustomIpcType::WorldRegistered,
+                        data: CustomIpcData::WorldRegistered { accepted },
+                        request_id: data.request_id,
+                        ..Default::default()
+                    },
+                )
+                .await;
+            }
+            CustomIpcData::WorldHeartbeat {
+                world_id,
+                population,
+            } => {
+                if !self
+                    .world_registry
+                    .lock()
+                    .unwrap()
+                    .heartbeat(*world_id, *population)
+                {
+                    tracing::warn!(
+                        "Got a heartbeat from world {world_id}, but it never registered!"
+                    );
+                }
+            }
+            CustomIpcData::RemoveServiceAccount { service_account_id } => {
+                let (deleted_characters, unreachable_worlds) =
+                    self.remove_service_account(*service_account_id).await;
+
+                send_custom_ipc(
+                    &mut self.socket,
+                    &mut self.state,
+                    CustomIpcSegment {
+                        op_code: CustomIpcType::ServiceAccountRemoved,
+                        data: CustomIpcData::ServiceAccountRemoved {
+                            deleted_characters,
+                            unreachable_worlds,
+                        },
+                        request_id: data.request_id,
+                        ..Default::default()
+                    },
+                )
+                .await;
+            }
+            _ => tracing::warn!("The lobby is recieving unexpected custom IPC: {data:#?}"),
+        }
+    }
+
+    /// Asks every world we know about to delete the characters of `service_account_id`, including ones that aren't in the config.
+    /// Returns how many characters were deleted, and how many worlds couldn't be reached.
+    async fn remove_service_account(&self, service_account_id: u32) -> (u32, u32) {
+        let worlds = self.world_registry.lock().unwrap().worlds().to_vec();
+
+        let mut deleted_characters = 0;
+        let mut unreachable_worlds = 0;
+        for world in worlds {
+            let response = world
+                .channel
+                .request(CustomIpcSegment {
+                    op_code: CustomIpcType::RemoveServiceAccount,
+                    data: CustomIpcData::RemoveServiceAccount { service_account_id },
+                    ..Default::default()
+                })
+                .await;
+
+            match response.map(|response| response.data) {
+                Some(CustomIpcData::ServiceAccountRemoved {
+                    deleted_characters: deleted,
+                    ..
+                }) => {
+                    tracing::info!(
+                        "Deleted {deleted} characters of {service_account_id} on {}",
+                        world.name
+                    );
+                    deleted_characters += deleted;
+                }
+                _ => {
+                    tracing::warn!(
+                        "Failed to delete the characters of {service_account_id} on {}",
+                        world.name
+                    );
+                    unreachable_worlds += 1;
+                }
+            }
+        }
+
+        (deleted_characters, unreachable_worlds)
+    }
+
     /// Send a lobby error to the client.
-    pub async fn send_error(&mut self, sequence: u64, error: u32, exd_error: u16) {
+    pub async fn send_error(&mut self, sequence: u64, error: LobbyError) {
+        tracing::info!("Sending lobby error {error:?}");
+
+        self.send_nack(sequence, error, 0).await;
+    }
+
+    /// Tells a player waiting for a full world their position in the queue, starting at 1.
+    pub async fn send_queue_position(&mut self, sequence: u64, position: u32) {
+        self.send_nack(sequence, LobbyError::WorldFull, position)
+            .await;
+    }
+
+    async fn send_nack(&mut self, sequence: u64, error: LobbyError, value: u32) {
+        let (error, exd_error_id) = error.codes();
         let lobby_error = ServerLobbyIpcData::NackReply(NackReply {
             sequence,
             error,
-            exd_error_id: exd_error,
+            value,
+            exd_error_id,
             ..Default::default()
         });
 
@@ -287,10 +774,414 @@ impl LobbyConnection {
         .await;
     }
 
+    /// Lets the player into `world`, waiting in the login queue if it's full. Returns false if they can't get in, or hung up while waiting.
+    /// While waiting, the client is sent it's position every `queue_update_interval` seconds and anything else it sends is put in `deferred_segments`.
+    // TODO: it's not verified that retail sends the position this way, NackReply's `value` is only thought to be the position in queue
+    pub async fn wait_in_queue(&mut self, sequence: u64, world: &RegisteredWorld) -> bool {
+        // nobody is waiting and there's room, so they can go right in
+        {
+            let login_queue = self.login_queue.lock().unwrap();
+            if login_queue.is_empty(world.id) && self.world_registry.lock().unwrap().admit(world.id)
+            {
+                return true;
+            }
+        }
+
+        let ticket = self.login_queue.lock().unwrap().join(world.id);
+        tracing::info!("{} is full, player is waiting in the queue", world.name);
+
+        let mut interval = tokio::time::interval(Duration::from_secs(
+            get_config().lobby.queue_update_interval,
+        ));
+        let mut buf = vec![0; RECEIVE_BUFFER_SIZE];
+
+        loop {
+            tokio::select! {
+                _ = interval.tick() => {
+                    let (position, status) = {
+                        let mut login_queue = self.login_queue.lock().unwrap();
+                        let mut world_registry = self.world_registry.lock().unwrap();
+
+                        let position = login_queue.position(world.id, ticket).unwrap_or_default();
+                        if position == 0 && world_registry.admit(world.id) {
+                            login_queue.leave(world.id, ticket);
+                            tracing::info!("Player made it through the queue for {}", world.name);
+                            return true;
+                        }
+
+                        (position, world_registry.get_status(world.id))
+                    };
+
+                    // there's no point in waiting for a world that went away
+                    if status == WorldStatus::Offline {
+                        self.login_queue.lock().unwrap().leave(world.id, ticket);
+                        self.send_error(sequence, LobbyError::WorldOffline).await;
+                        return false;
+                    }
+
+                    tracing::debug!("Player is number {} in the queue for {}", position + 1, world.name);
+                    self.send_queue_position(sequence, position as u32 + 1).await;
+                }
+                n = self.socket.read(&mut buf) => {
+                    let n = match n {
+                        Ok(n) if n > 0 => n,
+                        _ => {
+                            tracing::info!("Player gave up waiting in the queue for {}", world.name);
+                            self.login_queue.lock().unwrap().leave(world.id, ticket);
+                            return false;
+                        }
+                    };
+
+                    // the client still expects it's keep alives to be answered while waiting
+                    let (segments, _) = self.parse_packet(&buf[..n]);
+                    for segment in segments {
+                        match segment.data {
+                            SegmentData::KeepAliveRequest { id, timestamp } => {
+                                send_keep_alive::<ServerLobbyIpcSegment>(
+                                    &mut self.socket,
+                                    &mut self.state,
+                                    ConnectionType::Lobby,
+                                    id,
+                                    timestamp,
+                                )
+                                .await;
+                            }
+                            SegmentData::KeepAliveResponse { .. } => {}
+                            _ => self.deferred_segments.push(segment),
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    /// Copies a character from `source` to `destination`, and returns the new (actor_id, content_id).
+    /// The character stays locked on `source` if this succeeds, otherwise it's unlocked again.
+    async fn transfer_character(
+        &self,
+        character_action: &CharaMake,
+        source: &RegisteredWorld,
+        destination: &RegisteredWorld,
+    ) -> Option<(u32, u64)> {
+        tracing::info!(
+            "Player is moving {} from {} to {}!",
+            character_action.content_id,
+            source.name,
+            destination.name
+        );
+
+        if self
+            .world_registry
+            .lock()
+            .unwrap()
+            .get_status(destination.id)
+            != WorldStatus::Online
+        {
+            tracing::warn!("{} isn't taking new characters!", destination.name);
+            return None;
+        }
+
+        // lock the character on the source world, and get a copy of them
+        let begin_request = CustomIpcSegment {
+            op_code: CustomIpcType::BeginTransfer,
+            data: CustomIpcData::BeginTransfer {
+                content_id: character_action.content_id as u64,
+            },
+            ..Default::default()
+        };
+
+        let backup = match source.channel.request(begin_request).await {
+            Some(CustomIpcSegment {
+                data: CustomIpcData::TransferBegun { backup },
+                ..
+            }) if !backup.is_empty() => backup,
+            _ => {
+                tracing::warn!("{} refused to transfer the character!", source.name);
+                return None;
+            }
+        };
+
+        // then create them on the destination world
+        let receive_request = CustomIpcSegment {
+            op_code: CustomIpcType::ReceiveTransfer,
+            data: CustomIpcData::ReceiveTransfer {
+                service_account_id: self.selected_service_account.unwrap(),
+                backup,
+            },
+            ..Default::default()
+        };
+
+        match destination.channel.request(receive_request).await {
+            Some(CustomIpcSegment {
+                data:
+                    CustomIpcData::TransferReceived {
+                        actor_id,
+                        content_id,
+                    },
+                ..
+            }) if content_id != 0 => {
+                // settings don't fit in the backup, so they are copied over afterwards
+                if Self::copy_settings(
+                    source,
+                    character_action.content_id as u64,
+                    destination,
+                    content_id,
+                )
+                .await
+                {
+                    return Some((actor_id, content_id));
+                }
+
+                tracing::warn!(
+                    "Failed to copy the settings of {} to {}!",
+                    character_action.content_id,
+                    destination.name
+                );
+                if !Self::delete_character(destination, content_id).await {
+                    tracing::error!(
+                        "Failed to remove {content_id} from {} after a failed transfer, the character now exists twice!",
+                        destination.name
+                    );
+                }
+            }
+            _ => tracing::warn!("{} refused to receive the character!", destination.name),
+        }
+
+        // the destination doesn't have them, so unlock the character again
+        if !Self::cancel_transfer(source, character_action.content_id as u64).await {
+            tracing::warn!(
+                "Failed to unlock {} on {}, it will stay locked!",
+                character_action.content_id,
+                source.name
+            );
+        }
+
+        None
+    }
+
+    /// Copies every uploaded setting of a character on `source` to another character on `destination`, a chunk at a time.
+    async fn copy_settings(
+        source: &RegisteredWorld,
+        content_id: u64,
+        destination: &RegisteredWorld,
+        new_content_id: u64,
+    ) -> bool {
+        for index in 0.. {
+            let mut offset = 0;
+            loop {
+                let fetch_request = CustomIpcSegment {
+                    op_code: CustomIpcType::FetchSettings,
+                    data: CustomIpcData::FetchSettings {
+                        content_id,
+                        index,
+                        offset,
+                    },
+                    ..Default::default()
+                };
+
+                let Some(CustomIpcSegment {
+                    data:
+                        CustomIpcData::SettingsFetched {
+                            found,
+                            kind,
+                            total,
+                            data,
+                        },
+                    ..
+                }) = source.channel.request(fetch_request).await
+                else {
+                    return false;
+                };
+
+                if !found {
+                    return true;
+                }
+
+                // empty settings still have to be stored, so they aren't lost
+                let length = data.len() as u32;
+                let store_request = CustomIpcSegment {
+                    op_code: CustomIpcType::StoreSettings,
+                    data: CustomIpcData::StoreSettings {
+                        content_id: new_content_id,
+                        kind,
+                        offset,
+                        data,
+                    },
+                    ..Default::default()
+                };
+
+                if !matches!(
+                    destination.channel.request(store_request).await,
+                    Some(CustomIpcSegment {
+                        data: CustomIpcData::SettingsStored { stored: true },
+                        ..
+                    })
+                ) {
+                    return false;
+                }
+
+                offset += length;
+                if length == 0 || offset >= total {
+                    break;
+                }
+            }
+        }
+
+        true
+    }
+
+    /// Unlocks a character on `world` after a transfer didn't go through. Returns false if they couldn't be unlocked, e.g. because they don't exist.
+    async fn cancel_transfer(world: &RegisteredWorld, content_id: u64) -> bool {
+        let cancel_request = CustomIpcSegment {
+            op_code: CustomIpcType::CancelTransfer,
+            data: CustomIpcData::CancelTransfer { content_id },
+            ..Default::default()
+        };
+
+        matches!(
+            world.channel.request(cancel_request).await,
+            Some(CustomIpcSegment {
+                data: CustomIpcData::TransferCancelled { cancelled: true },
+                ..
+            })
+        )
+    }
+
+    /// Deletes a character from `world`, and returns whether the world says it did.
+    async fn delete_character(world: &RegisteredWorld, content_id: u64) -> bool {
+        let delete_request = CustomIpcSegment {
+            op_code: CustomIpcType::DeleteCharacter,
+            data: CustomIpcData::DeleteCharacter { content_id },
+            ..Default::default()
+        };
+
+        matches!(
+            world.channel.request(delete_request).await,
+            Some(CustomIpcSegment {
+                data: CustomIpcData::CharacterDeleted { deleted: 1 },
+                ..
+            })
+        )
+    }
+
+    /// Sends the settings in `character_action` to the world server, as the next chunk of the current upload.
+    /// Returns false if there's no upload in progress for this character, or the world couldn't store it.
+    async fn upload_settings_chunk(
+        &mut self,
+        character_action: &CharaMake,
+        world: &RegisteredWorld,
+    ) -> bool {
+        let Some(upload) = self
+            .settings_upload
+            .as_mut()
+            .filter(|upload| upload.content_id == character_action.content_id)
+        else {
+            tracing::warn!(
+                "Got settings for {} without an upload in progress!",
+                character_action.content_id
+            );
+            return false;
+        };
+
+        // the begin packet may not have any settings in it
+        if character_action.encoded.is_empty() {
+            return true;
+        }
+
+        let Ok(data) = URL_SAFE.decode(&character_action.encoded) else {
+            tracing::warn!("Failed to decode uploaded settings!");
+            return false;
+        };
+        if data.len() > SETTINGS_CHUNK_SIZE {
+            tracing::warn!(
+                "Uploaded settings chunk is too large ({} bytes)!",
+                data.len()
+            );
+            return false;
+        }
+
+        let length = data.len() as u32;
+        let ipc_segment = CustomIpcSegment {
+            op_code: CustomIpcType::StoreSettings,
+            data: CustomIpcData::StoreSettings {
+                content_id: upload.content_id as u64,
+                kind: SETTINGS_KIND,
+                offset: upload.offset,
+                data,
+            },
+            ..Default::default()
+        };
+
+        let stored = matches!(
+            world.channel.request(ipc_segment).await,
+            Some(CustomIpcSegment {
+                data: CustomIpcData::SettingsStored { stored: true },
+                ..
+            })
+        );
+
+        if stored {
+            upload.offset += length;
+        } else {
+            tracing::warn!("{} refused to store the uploaded settings!", world.name);
+        }
+
+        stored
+    }
+
     pub async fn handle_character_action(&mut self, character_action: &CharaMake) {
         let mut player_id = character_action.person_type;
         let mut content_id = character_action.content_id;
 
+        // new characters go to the world the client picked, everything else goes to the world the character lives on
+        let world = match &character_action.action {
+            LobbyCharacterActionKind::ReserveName | LobbyCharacterActionKind::Create => {
+                self.get_world(character_action.world_id)
+            }
+            LobbyCharacterActionKind::RemakeRetainer => {
+                self.get_retainer_world(character_action.content_id as u64)
+            }
+            _ => self.get_character_world(character_action.content_id),
+        };
+        let Some(mut world) = world else {
+            tracing::warn!(
+                "Couldn't find the world for character action {:?}!",
+                character_action.action
+            );
+            self.send_error(character_action.sequence, LobbyError::Failed)
+                .await;
+            return;
+        };
+
+        if self.selected_service_account.is_none() {
+            tracing::warn!("Player tried a character action before picking a service account!");
+            self.send_error(character_action.sequence, LobbyError::InvalidServiceAccount)
+                .await;
+            return;
+        }
+
+        if matches!(
+            character_action.action,
+            LobbyCharacterActionKind::ReserveName | LobbyCharacterActionKind::Create
+        ) {
+            // characters on worlds we couldn't reach still count towards the limit
+            if !self.character_list_complete {
+                tracing::info!(
+                    "Not all worlds are reachable, so we can't tell if the player is allowed to create another character!"
+                );
+                self.send_error(character_action.sequence, LobbyError::Failed)
+                    .await;
+                return;
+            }
+
+            if self.character_worlds.len() as u32 >= get_config().lobby.max_characters_per_account {
+                tracing::info!("Player has too many characters to create another one!");
+                self.send_error(character_action.sequence, LobbyError::CharacterLimitReached)
+                    .await;
+                return;
+            }
+        }
+
         match &character_action.action {
             LobbyCharacterActionKind::ReserveName => {
                 tracing::info!(
@@ -307,36 +1198,26 @@ impl LobbyConnection {
                     ..Default::default()
                 };
 
-                let name_response = send_custom_world_packet(name_request)
-                    .await
-                    .expect("Failed to get name request packet!");
-                let CustomIpcData::NameIsAvailableResponse { free } = &name_response.data else {
-                    panic!("Unexpedted custom IPC type!")
+                let free = match world.channel.request(name_request).await {
+                    Some(CustomIpcSegment {
+                        data: CustomIpcData::NameIsAvailableResponse { free },
+                        ..
+                    }) => free,
+                    _ => {
+                        tracing::warn!("{} didn't tell us if the name is free!", world.name);
+                        self.send_error(character_action.sequence, LobbyError::Failed)
+                            .await;
+                        return;
+                    }
                 };
 
                 tracing::info!("Is name free? {free}");
 
-                if *free {
+                if free {
                     self.stored_character_creation_name = character_action.name.clone();
                 } else {
-                    let ipc = ServerLobbyIpcSegment {
-                        op_code: ServerLobbyIpcType::NackReply,
-                        data: ServerLobbyIpcData::NackReply(NackReply {
-                            sequence: character_action.sequence,
-                            error: 0x00000bdb,
-                            exd_error_id: 0x32cc,
-                            ..Default::default()
-                        }),
-                        ..Default::default()
-                    };
-
-                    let response_packet = PacketSegment {
-                        segment_type: SegmentType::Ipc,
-                        data: SegmentData::Ipc { data: ipc },
-                        ..Default::default()
-                    };
-                    self.send_segment(response_packet).await;
-
+                    self.send_error(character_action.sequence, LobbyError::NameTaken)
+                        .await;
                     return;
                 }
             }
@@ -358,16 +1239,37 @@ impl LobbyConnection {
                         ..Default::default()
                     };
 
-                    let response_segment = send_custom_world_packet(ipc_segment).await.unwrap();
-                    match &response_segment.data {
-                        CustomIpcData::CharacterCreated {
-                            actor_id,
-                            content_id,
-                        } => {
-                            our_actor_id = *actor_id;
-                            our_content_id = *content_id;
+                    match world.channel.request(ipc_segment).await {
+                        Some(CustomIpcSegment {
+                            data:
+                                CustomIpcData::CharacterCreated {
+                                    actor_id,
+                                    content_id,
+                                },
+                            ..
+                        }) if content_id != 0 => {
+                            our_actor_id = actor_id;
+                            our_content_id = content_id;
+                        }
+                        Some(CustomIpcSegment {
+                            data: CustomIpcData::CharacterCreated { .. },
+                            ..
+                        }) => {
+                            // the world only refuses if the service account has too many characters there
+                            tracing::warn!("{} refused to create the character!", world.name);
+                            self.send_error(
+                                character_action.sequence,
+                                LobbyError::CharacterLimitReached,
+                            )
+                            .await;
+                            return;
+                        }
+                        _ => {
+                            tracing::warn!("{} didn't create the character!", world.name);
+                            self.send_error(character_action.sequence, LobbyError::Failed)
+                                .await;
+                            return;
                         }
-                        _ => panic!("Unexpected custom IPC packet type here!"),
                     }
                 }
 
@@ -377,8 +1279,59 @@ impl LobbyConnection {
 
                 player_id = our_actor_id as u32;
                 content_id = our_content_id as u32;
+
+                self.character_worlds.insert(content_id, world.id);
+            }
+            LobbyCharacterActionKind::Rename => {
+                tracing::info!(
+                    "Player is renaming {} to {}!",
+                    character_action.content_id,
+                    character_action.name
+                );
+
+                let ipc_segment = CustomIpcSegment {
+                    op_code: CustomIpcType::RenameCharacter,
+                    data: CustomIpcData::RenameCharacter {
+                        content_id: character_action.content_id as u64,
+                        name: character_action.name.clone(),
+                    },
+                    ..Default::default()
+                };
+
+                let error = match world.channel.request(ipc_segment).await {
+                    Some(CustomIpcSegment {
+                        data: CustomIpcData::CharacterRenamed { renamed: true, .. },
+                        ..
+                    }) => None,
+                    Some(CustomIpcSegment {
+                        data:
+                            CustomIpcData::CharacterRenamed {
+                                name_taken: true, ..
+                            },
+                        ..
+                    }) => Some(LobbyError::NameTaken),
+                    Some(CustomIpcSegment {
+                        data:
+                            CustomIpcData::CharacterRenamed {
+                                not_allowed: true, ..
+                            },
+                        ..
+                    }) => Some(LobbyError::RenameNotAllowed),
+                    _ => {
+                        tracing::warn!(
+                            "{} didn't rename {}!",
+                            world.name,
+                            character_action.content_id
+                        );
+                        Some(LobbyError::Failed)
+                    }
+                };
+
+                if let Some(error) = error {
+                    self.send_error(character_action.sequence, error).await;
+                    return;
+                }
             }
-            LobbyCharacterActionKind::Rename => todo!(),
             LobbyCharacterActionKind::Delete => {
                 // tell the world server to yeet this guy
                 {
@@ -390,19 +1343,219 @@ impl LobbyConnection {
                         ..Default::default()
                     };
 
-                    let _ = send_custom_world_packet(ipc_segment).await.unwrap();
+                    // we intentionally don't care about what the response says right now, it's not expected to fail
+                    if world.channel.request(ipc_segment).await.is_none() {
+                        tracing::warn!(
+                            "{} didn't delete {}!",
+                            world.name,
+                            character_action.content_id
+                        );
+                        self.send_error(character_action.sequence, LobbyError::Failed)
+                            .await;
+                        return;
+                    }
+                }
 
-                    // we intentionally don't care about the response right now, it's not expected to fail
+                // frees up the slot for the character limit
+                self.character_worlds.remove(&character_action.content_id);
+            }
+            LobbyCharacterActionKind::WorldVisit => {
+                // NOTE: World visits are left for later, only moves are supported.
+                // TODO: visiting needs a way back, which unlocks the character on their home world and removes the visiting copy
+                tracing::warn!("World visits aren't supported yet!");
+                self.send_error(character_action.sequence, LobbyError::TransferRefused)
+                    .await;
+                return;
+            }
+            LobbyCharacterActionKind::Move => {
+                // TODO: it's assumed the client sends the destination world in world_id, this hasn't been verified. Until it is, moves are opt-in.
+                if !get_config().lobby.allow_character_moves {
+                    tracing::warn!(
+                        "Player tried to move {} to world {}, but moves aren't allowed!",
+                        character_action.content_id,
+                        character_action.world_id
+                    );
+                    self.send_error(character_action.sequence, LobbyError::TransferRefused)
+                        .await;
+                    return;
+                }
+
+                let destination = self
+                    .world_registry
+                    .lock()
+                    .unwrap()
+                    .get_world(character_action.world_id)
+                    .filter(|destination| destination.id != world.id)
+                    .cloned();
+                let Some(destination) = destination else {
+                    tracing::warn!(
+                        "Can't move {} to world {}!",
+                        character_action.content_id,
+                        character_action.world_id
+                    );
+                    self.send_error(character_action.sequence, LobbyError::TransferRefused)
+                        .await;
+                    return;
+                };
+
+                let Some((new_actor_id, new_content_id)) = self
+                    .transfer_character(character_action, &world, &destination)
+                    .await
+                else {
+                    self.send_error(character_action.sequence, LobbyError::TransferRefused)
+                        .await;
+                    return;
+                };
+
+                // a moved character no longer exists on the source world. The source world refuses moves of characters with retainers, so none are left behind.
+                let old_content_id = character_action.content_id as u64;
+                if !Self::delete_character(&world, old_content_id).await {
+                    // if they can still be unlocked they weren't deleted, so the move is undone. Otherwise they may have been deleted after all, and the new copy must be kept
+                    if Self::cancel_transfer(&world, old_content_id).await {
+                        tracing::warn!(
+                            "Failed to delete {old_content_id} from {} after moving it, so the move was undone!",
+                            world.name
+                        );
+
+                        if !Self::delete_character(&destination, new_content_id).await {
+                            tracing::error!(
+                                "Failed to remove {new_content_id} from {} while undoing a move, the character now exists twice!",
+                                destination.name
+                            );
+                        }
+
+                        self.send_error(character_action.sequence, LobbyError::TransferRefused)
+                            .await;
+                        return;
+                    }
+
+                    tracing::error!(
+                        "Couldn't tell if {old_content_id} was deleted from {} after moving it, it may be left behind locked!",
+                        world.name
+                    );
+                }
+
+                self.character_worlds.remove(&character_action.content_id);
+
+                player_id = new_actor_id;
+                content_id = new_content_id as u32;
+
+                self.character_worlds.insert(content_id, destination.id);
+                world = destination;
+            }
+            LobbyCharacterActionKind::RemakeRetainer => {
+                // TODO: it's assumed the client sends the retainer id in content_id, and only the name is changed for now
+                tracing::info!(
+                    "Player is remaking retainer {} as {}!",
+                    character_action.content_id,
+                    character_action.name
+                );
+
+                let ipc_segment = CustomIpcSegment {
+                    op_code: CustomIpcType::RemakeRetainer,
+                    data: CustomIpcData::RemakeRetainer {
+                        retainer_id: character_action.content_id as u64,
+                        service_account_id: self.selected_service_account.unwrap(),
+                        name: character_action.name.clone(),
+                    },
+                    ..Default::default()
+                };
+
+                match world.channel.request(ipc_segment).await {
+                    Some(CustomIpcSegment {
+                        data: CustomIpcData::RetainerRemade { remade: true, .. },
+                        ..
+                    }) => {}
+                    Some(CustomIpcSegment {
+                        data:
+                            CustomIpcData::RetainerRemade {
+                                name_taken: true, ..
+                            },
+                        ..
+                    }) => {
+                        self.send_error(character_action.sequence, LobbyError::NameTaken)
+                            .await;
+                        return;
+                    }
+                    _ => {
+                        tracing::warn!(
+                            "{} didn't remake retainer {}!",
+                            world.name,
+                            character_action.content_id
+                        );
+                        self.send_error(character_action.sequence, LobbyError::Failed)
+                            .await;
+                        return;
+                    }
+                }
+            }
+            LobbyCharacterActionKind::RemakeChara => {
+                tracing::info!(
+                    "Player is remaking the appearance of {}!",
+                    character_action.content_id
+                );
+
+                let ipc_segment = CustomIpcSegment {
+                    op_code: CustomIpcType::RemakeCharacter,
+                    data: CustomIpcData::RemakeCharacter {
+                        content_id: character_action.content_id as u64,
+                        encoded: character_action.encoded.clone(),
+                    },
+                    ..Default::default()
+                };
+
+                let remade = match world.channel.request(ipc_segment).await {
+                    Some(CustomIpcSegment {
+                        data: CustomIpcData::CharacterRemade { content_id },
+                        ..
+                    }) => content_id != 0,
+                    _ => false,
+                };
+
+                if !remade {
+                    tracing::warn!(
+                        "{} didn't remake {}!",
+                        world.name,
+                        character_action.content_id
+                    );
+                    self.send_error(character_action.sequence, LobbyError::Failed)
+                        .await;
+                    return;
+                }
+            }
+            LobbyCharacterActionKind::SettingsUploadBegin => {
+                tracing::info!(
+                    "Player is uploading settings for {}!",
+                    character_action.content_id
+                );
+
+                self.settings_upload = Some(SettingsUpload {
+                    content_id: character_action.content_id,
+                    offset: 0,
+                });
+
+                if !self.upload_settings_chunk(character_action, &world).await {
+                    self.send_error(character_action.sequence, LobbyError::Failed)
+                        .await;
+                    return;
                 }
             }
-            LobbyCharacterActionKind::Move => todo!(),
-            LobbyCharacterActionKind::RemakeRetainer => todo!(),
-            LobbyCharacterActionKind::RemakeChara => todo!(),
-            LobbyCharacterActionKind::SettingsUploadBegin => todo!(),
-            LobbyCharacterActionKind::SettingsUpload => todo!(),
-            LobbyCharacterActionKind::WorldVisit => todo!(),
-            LobbyCharacterActionKind::DataCenterToken => todo!(),
-            LobbyCharacterActionKind::Request => todo!(),
+            LobbyCharacterActionKind::SettingsUpload => {
+                if !self.upload_settings_chunk(character_action, &world).await {
+                    self.send_error(character_action.sequence, LobbyError::Failed)
+                        .await;
+                    return;
+                }
+            }
+            LobbyCharacterActionKind::DataCenterToken | LobbyCharacterActionKind::Request => {
+                tracing::warn!(
+                    "Character action {:?} isn't supported yet!",
+                    character_action.action
+                );
+                self.send_error(character_action.sequence, LobbyError::Failed)
+                    .await;
+                return;
+            }
         }
 
         // a slightly different character created packet now
@@ -413,13 +1566,17 @@ impl LobbyConnection {
                     sequence: character_action.sequence + 1,
                     unk1: 0x1,
                     unk2: 0x1,
-                    action: LobbyCharacterActionKind::Create,
+                    // deleting was always answered as if it were a creation and the client accepts that, so it's left alone
+                    action: match &character_action.action {
+                        LobbyCharacterActionKind::Delete => LobbyCharacterActionKind::Create,
+                        action => action.clone(),
+                    },
                     player_id,
                     content_id,
                     unk3: 0x400017,
                     ticket: 1,
                     character_name: character_action.name.clone(),
-                    server_name: "Gilgamesh".to_string(),
+                    server_name: world.name.clone(),
                 },
                 ..Default::default()
             };
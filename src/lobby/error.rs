@@ -0,0 +1,93 @@
+/// Errors the lobby can show to the client, sent in `NackReply`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LobbyError {
+    /// The character or retainer name is already used by someone else.
+    NameTaken,
+    /// The service account or world has as many characters as it's allowed.
+    CharacterLimitReached,
+    /// The world isn't online.
+    WorldOffline,
+    /// The world is at capacity, and the player is waiting in the login queue. Their position is sent alongside, see `LobbyConnection::send_queue_position()`.
+    WorldFull,
+    /// We couldn't talk to the login server, or it sent something we didn't expect.
+    LoginServerUnavailable,
+    /// The session id isn't valid, or the account has no enabled service accounts.
+    InvalidSession,
+    /// The client picked a service account that doesn't exist, or didn't pick one yet.
+    InvalidServiceAccount,
+    /// The service account was suspended by a GM.
+    Suspended,
+    /// The character couldn't be moved to, or visit another world.
+    TransferRefused,
+    /// The character wasn't asked by a GM to change their name.
+    RenameNotAllowed,
+    /// Anything else went wrong, e.g. a world couldn't be reached. The reason is logged by whoever sent it.
+    Failed,
+}
+
+impl LobbyError {
+    // Error code shown in the dialog, and the row of the Error Excel sheet used for the message.
+    const NAME_TAKEN: (u32, u16) = (0x00000bdb, 0x32cc);
+
+    // TODO: none of these have been verified for this version of the client. Each has it's own error code so they can be told apart in the dialog,
+    // but they all use the same row for the message until the retail ones are known.
+    const FAILED: (u32, u16) = (5006, 13001);
+    const CHARACTER_LIMIT_REACHED: (u32, u16) = (5007, 13001);
+    const WORLD_OFFLINE: (u32, u16) = (5008, 13001);
+    const WORLD_FULL: (u32, u16) = (5009, 13001);
+    const LOGIN_SERVER_UNAVAILABLE: (u32, u16) = (5010, 13001);
+    const INVALID_SESSION: (u32, u16) = (5011, 13001);
+    const INVALID_SERVICE_ACCOUNT: (u32, u16) = (5012, 13001);
+    const SUSPENDED: (u32, u16) = (5013, 13001);
+    const TRANSFER_REFUSED: (u32, u16) = (5014, 13001);
+    const RENAME_NOT_ALLOWED: (u32, u16) = (5015, 13001);
+
+    /// Returns the `error` and `exd_error_id` to send in `NackReply`.
+    pub fn codes(&self) -> (u32, u16) {
+        match self {
+            LobbyError::NameTaken => Self::NAME_TAKEN,
+            LobbyError::CharacterLimitReached => Self::CHARACTER_LIMIT_REACHED,
+            LobbyError::WorldOffline => Self::WORLD_OFFLINE,
+            LobbyError::WorldFull => Self::WORLD_FULL,
+            LobbyError::LoginServerUnavailable => Self::LOGIN_SERVER_UNAVAILABLE,
+            LobbyError::InvalidSession => Self::INVALID_SESSION,
+            LobbyError::InvalidServiceAccount => Self::INVALID_SERVICE_ACCOUNT,
+            LobbyError::Suspended => Self::SUSPENDED,
+            LobbyError::TransferRefused => Self::TRANSFER_REFUSED,
+            LobbyError::RenameNotAllowed => Self::RENAME_NOT_ALLOWED,
+            LobbyError::Failed => Self::FAILED,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lobby_error_codes_distinct() {
+        let errors = [
+            LobbyError::NameTaken,
+            LobbyError::CharacterLimitReached,
+            LobbyError::WorldOffline,
+            LobbyError::WorldFull,
+            LobbyError::LoginServerUnavailable,
+            LobbyError::InvalidSession,
+            LobbyError::InvalidServiceAccount,
+            LobbyError::Suspended,
+            LobbyError::TransferRefused,
+            LobbyError::RenameNotAllowed,
+            LobbyError::Failed,
+        ];
+
+        for (i, a) in errors.iter().enumerate() {
+            for b in &errors[i + 1..] {
+                assert_ne!(
+                    a.codes().0,
+                    b.codes().0,
+                    "{a:?} and {b:?} share an error code"
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,69 @@
+use std::collections::{HashMap, VecDeque};
+
+/// Players waiting to enter a full world, in the order they asked. Each player is identified by a ticket.
+#[derive(Debug, Default)]
+pub struct LoginQueue {
+    /// Tickets waiting for each world, keyed by world id.
+    queues: HashMap<u16, VecDeque<u64>>,
+    next_ticket: u64,
+}
+
+impl LoginQueue {
+    /// Adds a player to the back of the queue for `world_id`, and returns their ticket.
+    pub fn join(&mut self, world_id: u16) -> u64 {
+        let ticket = self.next_ticket;
+        self.next_ticket += 1;
+
+        self.queues.entry(world_id).or_default().push_back(ticket);
+
+        ticket
+    }
+
+    /// Returns how many players are ahead of `ticket`, or `None` if it isn't queued.
+    pub fn position(&self, world_id: u16, ticket: u64) -> Option<usize> {
+        self.queues
+            .get(&world_id)?
+            .iter()
+            .position(|queued| *queued == ticket)
+    }
+
+    /// Removes `ticket` from the queue, either because they got in or gave up.
+    pub fn leave(&mut self, world_id: u16, ticket: u64) {
+        if let Some(queue) = self.queues.get_mut(&world_id) {
+            queue.retain(|queued| *queued != ticket);
+        }
+    }
+
+    /// Returns whether anyone is waiting for `world_id`.
+    pub fn is_empty(&self, world_id: u16) -> bool {
+        self.queues
+            .get(&world_id)
+            .is_none_or(|queue| queue.is_empty())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn first_in_first_out() {
+        let mut queue = LoginQueue::default();
+        assert!(queue.is_empty(63));
+
+        let first = queue.join(63);
+        let second = queue.join(63);
+        let other_world = queue.join(64);
+
+        assert_eq!(queue.position(63, first), Some(0));
+        assert_eq!(queue.position(63, second), Some(1));
+        assert_eq!(queue.position(64, other_world), Some(0));
+
+        queue.leave(63, first);
+        assert_eq!(queue.position(63, first), None);
+        assert_eq!(queue.position(63, second), Some(0));
+
+        queue.leave(63, second);
+        assert!(queue.is_empty(63));
+    }
+}
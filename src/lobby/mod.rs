@@ -1,2 +1,11 @@
 mod connection;
-pub use connection::LobbyConnection;
+pub use connection::{LobbyConnection, SettingsUpload};
+
+mod error;
+pub use error::LobbyError;
+
+mod login_queue;
+pub use login_queue::LoginQueue;
+
+mod world_registry;
+pub use world_registry::{RegisteredWorld, WorldRegistry, WorldStatus};
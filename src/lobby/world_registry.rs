@@ -0,0 +1,250 @@
+use std::{
+    net::{IpAddr, SocketAddr},
+    str::FromStr,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    common::GameData,
+    config::{Config, LobbyWorldConfig},
+    ipc::lobby::Server,
+    packet::WorldChannel,
+};
+
+/// Status of a world server, as known by the lobby.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WorldStatus {
+    Online,
+    /// The world is online, but has no room for more players.
+    Full,
+    /// The world hasn't registered itself, or stopped sending heartbeats.
+    Offline,
+}
+
+impl WorldStatus {
+    /// The value sent to the client in `Server::population`.
+    // TODO: this mapping is unverified, these values are guesses and it's not known yet how the client interprets this field
+    pub fn population_value(&self) -> u32 {
+        match self {
+            WorldStatus::Online => 0,
+            WorldStatus::Full => 1,
+            WorldStatus::Offline => 2,
+        }
+    }
+}
+
+/// A world server that the lobby knows about.
+#[derive(Debug, Clone)]
+pub struct RegisteredWorld {
+    /// See the World Excel sheet.
+    pub id: u16,
+    pub name: String,
+    /// Public-facing IP address of the world server.
+    pub server_name: String,
+    pub port: u16,
+    /// Maximum number of players allowed on this world, 0 means no limit.
+    pub capacity: u32,
+    /// Number of players currently on this world.
+    pub population: u32,
+    /// When the world last registered itself or sent a heartbeat, `None` if it never did.
+    pub last_heartbeat: Option<Instant>,
+    /// Connection used to talk to this world, shared between all lobby connections.
+    pub channel: Arc<WorldChannel>,
+}
+
+impl RegisteredWorld {
+    fn new(id: u16, name: String, server_name: &str, port: u16) -> Self {
+        Self {
+            id,
+            name,
+            server_name: server_name.to_string(),
+            port,
+            capacity: 0,
+            population: 0,
+            last_heartbeat: None,
+            channel: Arc::new(WorldChannel::new(Self::socketaddr(server_name, port))),
+        }
+    }
+
+    fn from_config(config: &LobbyWorldConfig, game_data: &mut GameData) -> Self {
+        Self::new(
+            config.id,
+            game_data.get_world_name(config.id),
+            &config.server_name,
+            config.port,
+        )
+    }
+
+    fn socketaddr(server_name: &str, port: u16) -> SocketAddr {
+        SocketAddr::from((
+            IpAddr::from_str(server_name).expect("Invalid IP address format for world!"),
+            port,
+        ))
+    }
+
+    /// Returns the status of this world, where `timeout` is how long a world can go without a heartbeat.
+    pub fn status(&self, timeout: Duration) -> WorldStatus {
+        match self.last_heartbeat {
+            Some(last_heartbeat) if last_heartbeat.elapsed() <= timeout => {
+                if self.capacity != 0 && self.population >= self.capacity {
+                    WorldStatus::Full
+                } else {
+                    WorldStatus::Online
+                }
+            }
+            _ => WorldStatus::Offline,
+        }
+    }
+
+    /// Returns the public IP address & port as a `SocketAddr`.
+    pub fn get_public_socketaddr(&self) -> SocketAddr {
+        Self::socketaddr(&self.server_name, self.port)
+    }
+}
+
+/// Keeps track of every world server the lobby can send players to.
+#[derive(Debug, Default)]
+pub struct WorldRegistry {
+    worlds: Vec<RegisteredWorld>,
+    /// How long a world can go without a heartbeat before it's considered offline.
+    timeout: Duration,
+}
+
+impl WorldRegistry {
+    /// Creates a registry from the worlds listed in the config.
+    /// These worlds are considered offline until they register themselves.
+    pub fn new(config: &Config, game_data: &mut GameData) -> Self {
+        Self {
+            worlds: config
+                .get_lobby_worlds()
+                .iter()
+                .map(|world| RegisteredWorld::from_config(world, game_data))
+                .collect(),
+            timeout: Duration::from_secs(config.lobby.world_timeout),
+        }
+    }
+
+    /// Returns every world, in the order they are shown in the world list.
+    pub fn worlds(&self) -> &[RegisteredWorld] {
+        &self.worlds
+    }
+
+    pub fn get_world(&self, world_id: u16) -> Option<&RegisteredWorld> {
+        self.worlds.iter().find(|world| world.id == world_id)
+    }
+
+    /// Returns the current status of the world with `world_id`.
+    pub fn get_status(&self, world_id: u16) -> WorldStatus {
+        self.get_world(world_id)
+            .map(|world| world.status(self.timeout))
+            .unwrap_or(WorldStatus::Offline)
+    }
+
+    /// Called when a world server announces itself. Worlds we don't know about yet are added to the end of the list.
+    /// Returns false if `server_name` isn't an IP address, in which case the world isn't registered.
+    pub fn register(
+        &mut self,
+        world_id: u16,
+        name: &str,
+        server_name: &str,
+        port: u16,
+        capacity: u32,
+        population: u32,
+    ) -> bool {
+        if IpAddr::from_str(server_name).is_err() {
+            return false;
+        }
+
+        let world = match self.worlds.iter_mut().find(|world| world.id == world_id) {
+            Some(world) => world,
+            None => {
+                self.worlds.push(RegisteredWorld::new(
+                    world_id,
+                    name.to_string(),
+                    server_name,
+                    port,
+                ));
+                self.worlds.last_mut().unwrap()
+            }
+        };
+
+        // the world moved, so the old connection is no good anymore
+        if world.server_name != server_name || world.port != port {
+            world.server_name = server_name.to_string();
+            world.port = port;
+            world.channel = Arc::new(WorldChannel::new(world.get_public_socketaddr()));
+        }
+
+        world.capacity = capacity;
+        world.population = population;
+        world.last_heartbeat = Some(Instant::now());
+
+        true
+    }
+
+    /// Called when a world server sends a heartbeat. Returns false if the world never registered itself.
+    pub fn heartbeat(&mut self, world_id: u16, population: u32) -> bool {
+        let Some(world) = self
+            .worlds
+            .iter_mut()
+            .find(|world| world.id == world_id && world.last_heartbeat.is_some())
+        else {
+            return false;
+        };
+
+        world.population = population;
+        world.last_heartbeat = Some(Instant::now());
+
+        true
+    }
+
+    /// Lets one more player into the world if it has room, and returns whether they got in.
+    /// The population is counted right away, so players let in before the next heartbeat don't go over capacity.
+    pub fn admit(&mut self, world_id: u16) -> bool {
+        let timeout = self.timeout;
+        let Some(world) = self.worlds.iter_mut().find(|world| world.id == world_id) else {
+            return false;
+        };
+
+        if world.status(timeout) != WorldStatus::Online {
+            return false;
+        }
+
+        world.population += 1;
+
+        true
+    }
+
+    /// Returns the world list entries sent to the client.
+    pub fn servers(&self) -> Vec<Server> {
+        self.worlds
+            .iter()
+            .enumerate()
+            .map(|(index, world)| Server {
+                id: world.id,
+                index: index as u16,
+                population: world.status(self.timeout).population_value(),
+                name: world.name.clone(),
+                ..Default::default()
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn register_invalid_server_name() {
+        let mut registry = WorldRegistry::default();
+
+        assert!(!registry.register(1, "Test", "world.example.com", 7100, 0, 0));
+        assert!(registry.worlds().is_empty());
+
+        assert!(registry.register(1, "Test", "127.0.0.1", 7100, 0, 0));
+        assert!(!registry.register(1, "Test", "not an address", 7100, 0, 0));
+        assert_eq!(registry.get_world(1).unwrap().server_name, "127.0.0.1");
+    }
+}
@@ -0,0 +1,141 @@
+use serde::Serialize;
+
+use super::{LoginError, RegistrationError};
+
+/// Error codes returned by the JSON account API, so launchers don't have to parse the messages.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ApiErrorCode {
+    /// The username or password is wrong, which one isn't said so accounts can't be found by guessing.
+    InvalidCredentials,
+    WrongOtp,
+    Banned,
+    LockedOut,
+    /// The SID is missing, expired or was never valid.
+    InvalidSession,
+    RegistrationClosed,
+    InvalidInvite,
+    InvalidUsername,
+    InvalidPassword,
+    UsernameTaken,
+    InternalError,
+}
+
+/// An error returned by the JSON account API.
+#[derive(Debug, Clone, Serialize)]
+pub struct ApiError {
+    pub error: ApiErrorCode,
+    /// Human-readable explanation, which launchers can show as-is.
+    pub message: String,
+    /// Seconds until the client can try again, only sent for `locked_out`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub retry_after: Option<u32>,
+}
+
+impl ApiError {
+    pub fn new(error: ApiErrorCode, message: impl Into<String>) -> Self {
+        Self {
+            error,
+            message: message.into(),
+            retry_after: None,
+        }
+    }
+
+    pub fn invalid_session() -> Self {
+        Self::new(
+            ApiErrorCode::InvalidSession,
+            "The session is invalid or has expired.",
+        )
+    }
+
+    /// The HTTP status code to send alongside this error.
+    pub fn status_code(&self) -> u16 {
+        match self.error {
+            ApiErrorCode::InvalidCredentials
+            | ApiErrorCode::WrongOtp
+            | ApiErrorCode::InvalidSession => 401,
+            ApiErrorCode::Banned | ApiErrorCode::RegistrationClosed => 403,
+            ApiErrorCode::UsernameTaken => 409,
+            ApiErrorCode::InvalidInvite
+            | ApiErrorCode::InvalidUsername
+            | ApiErrorCode::InvalidPassword => 422,
+            ApiErrorCode::LockedOut => 429,
+            ApiErrorCode::InternalError => 500,
+        }
+    }
+}
+
+impl From<LoginError> for ApiError {
+    fn from(err: LoginError) -> Self {
+        match err {
+            LoginError::WrongUsername | LoginError::WrongPassword => Self::new(
+                ApiErrorCode::InvalidCredentials,
+                "The username or password is wrong.",
+            ),
+            LoginError::WrongOtp => Self::new(
+                ApiErrorCode::WrongOtp,
+                "The one-time password is missing or wrong.",
+            ),
+            LoginError::Banned(ban) => Self::new(
+                ApiErrorCode::Banned,
+                format!("This account is banned: {}", ban.describe()),
+            ),
+            LoginError::LockedOut(remaining) => Self {
+                retry_after: Some(remaining),
+                ..Self::new(
+                    ApiErrorCode::LockedOut,
+                    format!("Too many failed logins. Please try again in {remaining} seconds."),
+                )
+            },
+            LoginError::InternalError => Self::new(
+                ApiErrorCode::InternalError,
+                "Something went wrong, please try again later.",
+            ),
+        }
+    }
+}
+
+impl From<RegistrationError> for ApiError {
+    fn from(err: RegistrationError) -> Self {
+        let code = match err {
+            RegistrationError::Closed => ApiErrorCode::RegistrationClosed,
+            RegistrationError::InvalidInvite => ApiErrorCode::InvalidInvite,
+            RegistrationError::InvalidUsername => ApiErrorCode::InvalidUsername,
+            RegistrationError::InvalidPassword => ApiErrorCode::InvalidPassword,
+            RegistrationError::UsernameTaken => ApiErrorCode::UsernameTaken,
+            RegistrationError::InternalError => ApiErrorCode::InternalError,
+        };
+
+        Self::new(code, err.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn serialized_errors() {
+        let err = ApiError::from(LoginError::WrongPassword);
+        assert_eq!(err.status_code(), 401);
+        assert_eq!(
+            serde_json::to_string(&err).unwrap(),
+            r#"{"error":"invalid_credentials","message":"The username or password is wrong."}"#
+        );
+
+        // a wrong username looks the same as a wrong password
+        let err = ApiError::from(LoginError::WrongUsername);
+        assert_eq!(err.error, ApiErrorCode::InvalidCredentials);
+
+        let err = ApiError::from(LoginError::LockedOut(30));
+        assert_eq!(err.status_code(), 429);
+        assert_eq!(
+            serde_json::to_value(&err).unwrap()["retry_after"],
+            serde_json::json!(30)
+        );
+
+        let err = ApiError::from(RegistrationError::UsernameTaken);
+        assert_eq!(err.error, ApiErrorCode::UsernameTaken);
+        assert_eq!(err.status_code(), 409);
+    }
+}
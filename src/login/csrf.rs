@@ -0,0 +1,32 @@
+use base64::{Engine as _, engine::general_purpose::URL_SAFE_NO_PAD};
+
+use crate::common::{sign, verify_signature};
+
+/// Signed with the session id, so each session gets it's own token.
+const CSRF_CONTEXT: &[u8] = b"kodama csrf";
+
+/// Returns the token that has to be sent back with every form on the website.
+/// Other sites can't read the session cookie, so they can't come up with this either.
+pub fn csrf_token(sid: &str) -> String {
+    URL_SAFE_NO_PAD.encode(sign(sid, CSRF_CONTEXT))
+}
+
+/// Checks if `token` was created by `csrf_token()` for the same `sid`.
+pub fn verify_csrf_token(sid: &str, token: &str) -> bool {
+    URL_SAFE_NO_PAD
+        .decode(token)
+        .is_ok_and(|signature| verify_signature(sid, CSRF_CONTEXT, &signature))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn csrf_tokens() {
+        let token = csrf_token("sid");
+        assert!(verify_csrf_token("sid", &token));
+        assert!(!verify_csrf_token("other sid", &token));
+        assert!(!verify_csrf_token("sid", ""));
+    }
+}
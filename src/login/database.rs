@@ -1,8 +1,17 @@
 use std::sync::Mutex;
 
+use argon2::{Argon2, PasswordHash, PasswordHasher, PasswordVerifier, password_hash::SaltString};
 use rusqlite::Connection;
+use serde::Serialize;
 
-use crate::ipc::lobby::ServiceAccount;
+use crate::{common::timestamp_secs, config::get_config, ipc::lobby::ServiceAccount};
+
+use super::{
+    AccountRole, Ban, BanEntry, LoginAuditEntry, RegistrationError, otp,
+    registration::generate_invite_code,
+    throttle::{KNOWN_ADDRESS_LIFETIME, lockout_duration},
+    validate_password, validate_username,
+};
 
 pub struct LoginDatabase {
     connection: Mutex<Connection>,
@@ -12,9 +21,45 @@ pub struct LoginDatabase {
 pub enum LoginError {
     WrongUsername,
     WrongPassword,
+    /// Two-factor authentication is enabled, and the one-time password is missing or wrong.
+    WrongOtp,
+    /// The account is banned, and can't log in until it's lifted.
+    Banned(Ban),
+    /// There were too many failed logins, contains the number of seconds until they can try again.
+    LockedOut(u32),
     InternalError,
 }
 
+/// A service account as shown on the account management website.
+#[derive(Debug, Clone, Serialize)]
+pub struct ServiceAccountInfo {
+    pub id: u32,
+    /// The name shown in the lobby, either chosen by the user or the default one.
+    pub name: String,
+    /// Disabled service accounts are hidden from the lobby.
+    pub enabled: bool,
+}
+
+/// Where a session was created, each kind expires on it's own schedule and doesn't replace the other.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SessionKind {
+    /// Logged in through the account management website.
+    Web = 0,
+    /// Logged in through the launcher, and used by the lobby.
+    Game = 1,
+}
+
+impl SessionKind {
+    /// Number of seconds this kind of session can go unused before it expires.
+    fn lifetime(&self) -> u32 {
+        let config = get_config();
+        match self {
+            SessionKind::Web => config.login.web_session_lifetime,
+            SessionKind::Game => config.login.game_session_lifetime,
+        }
+    }
+}
+
 impl Default for LoginDatabase {
     fn default() -> Self {
         Self::new()
@@ -29,12 +74,63 @@ impl LoginDatabase {
         {
             let query = "CREATE TABLE IF NOT EXISTS users (id INTEGER PRIMARY KEY, username TEXT, password TEXT);";
             connection.execute(query, ()).unwrap();
+
+            Self::add_column(&connection, "users", "role", "INTEGER NOT NULL DEFAULT 0");
+        }
+
+        // Create invite codes table, used_by is set once someone registers with it
+        {
+            let query = "CREATE TABLE IF NOT EXISTS invites (code TEXT PRIMARY KEY, created_by INTEGER, created INTEGER, used_by INTEGER);";
+            connection.execute(query, ()).unwrap();
+        }
+
+        // Create failed login tracking table, keyed by "ip:<address>" or "user:<username>"
+        {
+            let query = "CREATE TABLE IF NOT EXISTS login_attempts (key TEXT PRIMARY KEY, failures INTEGER, last_failure INTEGER, locked_until INTEGER);";
+            connection.execute(query, ()).unwrap();
+        }
+
+        // Create failed login audit table
+        {
+            let query = "CREATE TABLE IF NOT EXISTS login_audit (time INTEGER, ip TEXT, username TEXT, reason TEXT);";
+            connection.execute(query, ()).unwrap();
+        }
+
+        // Create known addresses table, which IP addresses logged into which username and when they last did
+        {
+            let query = "CREATE TABLE IF NOT EXISTS known_addresses (username TEXT, ip TEXT, last_login INTEGER, PRIMARY KEY (username, ip));";
+            connection.execute(query, ()).unwrap();
+        }
+
+        // Create bans table, user_id is set for banned accounts and service_account_id for suspended service accounts
+        {
+            let query = "CREATE TABLE IF NOT EXISTS bans (user_id INTEGER, service_account_id INTEGER, reason TEXT, created INTEGER, expires INTEGER);";
+            connection.execute(query, ()).unwrap();
         }
 
         // Create active sessions table
         {
-            let query =
-                "CREATE TABLE IF NOT EXISTS sessions (user_id INTEGER PRIMARY KEY, sid TEXT);";
+            // sessions used to be keyed by user, they are only temporary so it's easier to start over
+            let has_kind: bool = connection
+                .query_row(
+                    "SELECT EXISTS(SELECT 1 FROM pragma_table_info('sessions') WHERE name = 'kind');",
+                    (),
+                    |row| row.get(0),
+                )
+                .unwrap();
+            let has_table: bool = connection
+                .query_row(
+                    "SELECT EXISTS(SELECT 1 FROM sqlite_master WHERE type = 'table' AND name = 'sessions');",
+                    (),
+                    |row| row.get(0),
+                )
+                .unwrap();
+            if has_table && !has_kind {
+                tracing::info!("Upgrading the sessions table, everyone will have to log in again");
+                connection.execute("DROP TABLE sessions;", ()).unwrap();
+            }
+
+            let query = "CREATE TABLE IF NOT EXISTS sessions (sid TEXT PRIMARY KEY, user_id INTEGER, kind INTEGER, created INTEGER, last_seen INTEGER);";
             connection.execute(query, ()).unwrap();
         }
 
@@ -42,6 +138,34 @@ impl LoginDatabase {
         {
             let query = "CREATE TABLE IF NOT EXISTS service_accounts (id INTEGER PRIMARY KEY, user_id INTEGER);";
             connection.execute(query, ()).unwrap();
+
+            Self::add_column(&connection, "service_accounts", "name", "TEXT");
+            Self::add_column(
+                &connection,
+                "service_accounts",
+                "enabled",
+                "INTEGER NOT NULL DEFAULT 1",
+            );
+        }
+
+        // Create two-factor authentication table, secrets stay disabled until the user proves they set it up
+        {
+            let query = "CREATE TABLE IF NOT EXISTS otp (user_id INTEGER PRIMARY KEY, secret TEXT, enabled INTEGER, last_step INTEGER NOT NULL DEFAULT 0);";
+            connection.execute(query, ()).unwrap();
+
+            // the last step a one-time password was accepted for, so it can't be used again
+            Self::add_column(
+                &connection,
+                "otp",
+                "last_step",
+                "INTEGER NOT NULL DEFAULT 0",
+            );
+        }
+
+        // Create recovery codes table, these are hashed like passwords
+        {
+            let query = "CREATE TABLE IF NOT EXISTS recovery_codes (user_id INTEGER, code TEXT);";
+            connection.execute(query, ()).unwrap();
         }
 
         Self {
@@ -49,44 +173,207 @@ impl LoginDatabase {
         }
     }
 
+    /// Adds `column` to `table` if it doesn't exist yet, for databases created before it was introduced.
+    fn add_column(connection: &Connection, table: &str, column: &str, definition: &str) {
+        let mut stmt = connection
+            .prepare(&format!(
+                "SELECT 1 FROM pragma_table_info('{table}') WHERE name = ?1"
+            ))
+            .unwrap();
+        if !stmt.exists((column,)).unwrap() {
+            connection
+                .execute(
+                    &format!("ALTER TABLE {table} ADD COLUMN {column} {definition};"),
+                    (),
+                )
+                .unwrap();
+        }
+    }
+
     fn generate_account_id() -> u32 {
         fastrand::u32(..)
     }
 
-    /// Adds a new user to the database.
-    pub fn add_user(&self, username: &str, password: &str) {
-        if self.check_username(username) {
-            tracing::info!("{username} already taken!");
-            return;
+    /// Hashes `password` with a new random salt, and returns it as a PHC string.
+    /// Argon2 is slow on purpose, so this runs on a blocking thread instead of holding up the runtime.
+    async fn hash_password(password: &str) -> Option<String> {
+        let password = password.to_string();
+
+        tokio::task::spawn_blocking(move || {
+            let mut salt = [0; 16];
+            getrandom::fill(&mut salt).ok()?;
+            let salt = SaltString::encode_b64(&salt).ok()?;
+
+            Argon2::default()
+                .hash_password(password.as_bytes(), &salt)
+                .ok()
+                .map(|hash| hash.to_string())
+        })
+        .await
+        .ok()
+        .flatten()
+    }
+
+    /// Hash of a random password nobody knows, checked when the username doesn't exist so that takes as long as a wrong password.
+    const DUMMY_PASSWORD_HASH: &str = "$argon2id$v=19$m=19456,t=2,p=1$t+wMnzXtmuNR/bD4Ixr3+A$f+j8mph2bYcl8RG6ACg7YMo9oYfzi1qDOo7BuQjt75I";
+
+    /// Returns whether `stored` is a password hash, instead of a plaintext password from before they were hashed.
+    fn is_password_hash(stored: &str) -> bool {
+        stored.starts_with("$argon2")
+    }
+
+    /// Checks `password` against what's stored in the database, which is either a hash or plaintext.
+    /// Like `hash_password`, hashes are checked on a blocking thread.
+    async fn verify_password(stored: &str, password: &str) -> bool {
+        if !Self::is_password_hash(stored) {
+            return stored == password;
         }
 
+        let stored = stored.to_string();
+        let password = password.to_string();
+
+        tokio::task::spawn_blocking(move || {
+            PasswordHash::new(&stored).is_ok_and(|hash| {
+                Argon2::default()
+                    .verify_password(password.as_bytes(), &hash)
+                    .is_ok()
+            })
+        })
+        .await
+        .unwrap_or_default()
+    }
+
+    /// Replaces the stored password of `user_id` with a hash of `password`.
+    async fn upgrade_password(&self, user_id: u32, password: &str) {
+        let Some(hash) = Self::hash_password(password).await else {
+            tracing::warn!("Failed to hash the password of {user_id}, it will stay in plaintext!");
+            return;
+        };
+
+        let connection = self.connection.lock().unwrap();
+        connection
+            .execute(
+                "UPDATE users SET password = ?1 WHERE id = ?2;",
+                (hash, user_id),
+            )
+            .expect("Failed to write password to database!");
+
+        tracing::info!("Upgraded the password of {user_id} to a hash");
+    }
+
+    /// Returns the usernames of users whose password is still stored in plaintext.
+    /// These are upgraded the next time they log in.
+    pub fn get_unmigrated_users(&self) -> Vec<String> {
+        let connection = self.connection.lock().unwrap();
+
+        let mut stmt = connection
+            .prepare("SELECT username, password FROM users")
+            .unwrap();
+        stmt.query_map((), |row| {
+            Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?))
+        })
+        .unwrap()
+        .filter_map(|row| row.ok())
+        .filter(|(_, password)| !Self::is_password_hash(password))
+        .map(|(username, _)| username)
+        .collect()
+    }
+
+    /// Adds a new user to the database, and returns their id.
+    /// If `invite` is given, it has to be an unused invite code, which is used up.
+    pub async fn add_user(
+        &self,
+        username: &str,
+        password: &str,
+        invite: Option<&str>,
+    ) -> Result<u32, RegistrationError> {
+        validate_username(username)?;
+        validate_password(password)?;
+
         let user_id = Self::generate_account_id();
 
+        let Some(password) = Self::hash_password(password).await else {
+            tracing::warn!("Failed to hash the password for {username}!");
+            return Err(RegistrationError::InternalError);
+        };
+
         // add user
         {
             let connection = self.connection.lock().unwrap();
 
+            let mut stmt = connection
+                .prepare("SELECT id FROM users WHERE username = ?1")
+                .unwrap();
+            if stmt.exists((username,)).unwrap() {
+                tracing::info!("{username} already taken!");
+                return Err(RegistrationError::UsernameTaken);
+            }
+
+            if let Some(invite) = invite {
+                let used = connection
+                    .execute(
+                        "UPDATE invites SET used_by = ?1 WHERE code = ?2 AND used_by IS NULL;",
+                        (user_id, invite),
+                    )
+                    .map_err(|_| RegistrationError::InternalError)?;
+                if used == 0 {
+                    return Err(RegistrationError::InvalidInvite);
+                }
+            }
+
             tracing::info!("Adding user with username {username}");
 
-            let query = "INSERT INTO users VALUES (?1, ?2, ?3);";
+            let query = "INSERT INTO users (id, username, password) VALUES (?1, ?2, ?3);";
             connection
                 .execute(query, (user_id, username, password))
                 .expect("Failed to write user to database!");
         }
 
         // add service account
-        {
-            let connection = self.connection.lock().unwrap();
+        self.add_service_account(user_id);
 
-            let query = "INSERT INTO service_accounts VALUES (?1, ?2);";
-            connection
-                .execute(query, (Self::generate_account_id(), user_id))
-                .expect("Failed to write service account to database!");
-        }
+        Ok(user_id)
+    }
+
+    /// Creates a new invite code, which can be used once to register.
+    pub fn create_invite(&self, created_by: u32) -> Option<String> {
+        let connection = self.connection.lock().unwrap();
+
+        let code = generate_invite_code()?;
+        connection
+            .execute(
+                "INSERT INTO invites VALUES (?1, ?2, ?3, NULL);",
+                (&code, created_by, timestamp_secs()),
+            )
+            .ok()?;
+
+        tracing::info!("{created_by} created invite code {code}");
+
+        Some(code)
+    }
+
+    /// Returns every invite code that hasn't been used yet.
+    pub fn get_unused_invites(&self) -> Vec<String> {
+        let connection = self.connection.lock().unwrap();
+
+        let mut stmt = connection
+            .prepare("SELECT code FROM invites WHERE used_by IS NULL ORDER BY created")
+            .unwrap();
+        stmt.query_map((), |row| row.get(0))
+            .unwrap()
+            .filter_map(|code| code.ok())
+            .collect()
     }
 
     /// Login as user, returns a session id.
-    pub fn login_user(&self, username: &str, password: &str) -> Result<String, LoginError> {
+    /// `otp` is only checked if the user enabled two-factor authentication, and may also be a recovery code.
+    pub async fn login_user(
+        &self,
+        username: &str,
+        password: &str,
+        otp: &str,
+        kind: SessionKind,
+    ) -> Result<String, LoginError> {
         let selected_row: Result<(u32, String), rusqlite::Error>;
 
         tracing::info!("Finding user with username {username}");
@@ -100,78 +387,261 @@ impl LoginDatabase {
             selected_row = stmt.query_row((username,), |row| Ok((row.get(0)?, row.get(1)?)));
         }
 
-        if let Ok((_id, their_password)) = selected_row {
-            if their_password == password {
-                return self.create_session(_id).ok_or(LoginError::InternalError);
+        if let Ok((id, their_password)) = selected_row {
+            if Self::verify_password(&their_password, password).await {
+                // passwords from before they were hashed are upgraded now that we know it
+                if !Self::is_password_hash(&their_password) {
+                    self.upgrade_password(id, password).await;
+                }
+
+                if !self.check_otp(id, otp).await {
+                    return Err(LoginError::WrongOtp);
+                }
+
+                if let Some(ban) = self.get_ban(id) {
+                    return Err(LoginError::Banned(ban));
+                }
+
+                return self
+                    .create_session(id, kind)
+                    .ok_or(LoginError::InternalError);
             } else {
                 return Err(LoginError::WrongPassword);
             }
         }
 
+        // so it can't be told from the time it took whether the username exists
+        Self::verify_password(Self::DUMMY_PASSWORD_HASH, password).await;
+
         Err(LoginError::WrongUsername)
     }
 
-    fn generate_sid() -> String {
-        let random_id: String =
-            String::from_utf8((0..56).map(|_| fastrand::alphanumeric() as u8).collect())
-                .expect("Failed to create random SID");
-        random_id.to_lowercase()
+    /// Same as `login_user`, but refuses IP addresses and usernames with too many failed logins, and records the failures.
+    pub async fn attempt_login(
+        &self,
+        ip: &str,
+        username: &str,
+        password: &str,
+        otp: &str,
+        kind: SessionKind,
+    ) -> Result<String, LoginError> {
+        if let Some(remaining) = self.get_lockout(ip, username) {
+            return Err(LoginError::LockedOut(remaining));
+        }
+
+        let result = self.login_user(username, password, otp, kind).await;
+        match &result {
+            Ok(_) => self.record_successful_login(ip, username),
+            Err(LoginError::WrongUsername) => {
+                self.record_failed_login(ip, username, "Wrong username")
+            }
+            Err(LoginError::WrongPassword) => {
+                self.record_failed_login(ip, username, "Wrong password")
+            }
+            Err(LoginError::WrongOtp) => {
+                self.record_failed_login(ip, username, "Wrong one-time password")
+            }
+            Err(_) => {}
+        }
+
+        result
+    }
+
+    /// Generates a new random SID, 56 lowercase hex characters long.
+    fn generate_sid() -> Option<String> {
+        let mut bytes = [0; 28];
+        getrandom::fill(&mut bytes).ok()?;
+
+        Some(bytes.iter().map(|byte| format!("{byte:02x}")).collect())
     }
 
-    /// Create a new session for user, which replaces the last one (if any)
-    pub fn create_session(&self, user_id: u32) -> Option<String> {
+    /// Create a new session for user. Users can have any number of sessions, e.g. one on the website and another in the game.
+    pub fn create_session(&self, user_id: u32, kind: SessionKind) -> Option<String> {
         let connection = self.connection.lock().unwrap();
 
-        let sid = Self::generate_sid();
+        let sid = Self::generate_sid()?;
+        let now = timestamp_secs();
 
         connection
             .execute(
-                "INSERT OR REPLACE INTO sessions VALUES (?1, ?2);",
-                (user_id, &sid),
+                "INSERT INTO sessions VALUES (?1, ?2, ?3, ?4, ?5);",
+                (&sid, user_id, kind as u8, now, now),
             )
             .ok()?;
 
-        tracing::info!("Created new session for account {user_id}: {sid}");
+        tracing::info!("Created new {kind:?} session for account {user_id}");
 
         Some(sid)
     }
 
-    /// Gets the service account list
-    pub fn check_session(&self, sid: &str) -> Vec<ServiceAccount> {
+    /// Returns the user the session belongs to, if it's valid and hasn't expired. This also keeps the session alive.
+    fn validate_session(connection: &Connection, sid: &str) -> Option<u32> {
+        let (user_id, kind, last_seen): (u32, u8, u32) = connection
+            .query_row(
+                "SELECT user_id, kind, last_seen FROM sessions WHERE sid = ?1",
+                (sid,),
+                |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+            )
+            .ok()?;
+
+        let kind = match kind {
+            0 => SessionKind::Web,
+            _ => SessionKind::Game,
+        };
+
+        let now = timestamp_secs();
+        if now.saturating_sub(last_seen) > kind.lifetime() {
+            connection
+                .execute("DELETE FROM sessions WHERE sid = ?1;", (sid,))
+                .ok()?;
+            return None;
+        }
+
+        connection
+            .execute(
+                "UPDATE sessions SET last_seen = ?1 WHERE sid = ?2;",
+                (now, sid),
+            )
+            .ok()?;
+
+        Some(user_id)
+    }
+
+    /// Removes a session, e.g. when logging out.
+    pub fn remove_session(&self, sid: &str) {
         let connection = self.connection.lock().unwrap();
 
-        // get user id
-        let user_id: u32;
-        {
-            let mut stmt = connection
-                .prepare("SELECT user_id FROM sessions WHERE sid = ?1")
-                .ok()
-                .unwrap();
-            if let Ok(found_user_id) = stmt.query_row((sid,), |row| row.get(0)) {
-                user_id = found_user_id;
-            } else {
-                return Vec::default();
-            }
+        connection
+            .execute("DELETE FROM sessions WHERE sid = ?1;", (sid,))
+            .expect("Failed to remove session from database!");
+    }
+
+    /// Removes every session of a user, so they have to log in again everywhere.
+    pub fn remove_user_sessions(&self, user_id: u32) {
+        let connection = self.connection.lock().unwrap();
+
+        connection
+            .execute("DELETE FROM sessions WHERE user_id = ?1;", (user_id,))
+            .expect("Failed to remove sessions from database!");
+
+        tracing::info!("Removed all sessions for account {user_id}");
+    }
+
+    /// Removes every expired session, and returns how many there were.
+    pub fn cleanup_sessions(&self) -> usize {
+        let connection = self.connection.lock().unwrap();
+
+        let now = timestamp_secs();
+
+        connection
+            .execute(
+                "DELETE FROM sessions WHERE (kind = ?1 AND ?3 - last_seen > ?4) OR (kind = ?2 AND ?3 - last_seen > ?5);",
+                (
+                    SessionKind::Web as u8,
+                    SessionKind::Game as u8,
+                    now,
+                    SessionKind::Web.lifetime(),
+                    SessionKind::Game.lifetime(),
+                ),
+            )
+            .unwrap_or_default()
+    }
+
+    /// Checks `password` against the one stored for `user_id`, e.g. to confirm changes to their account.
+    pub async fn check_password(&self, user_id: u32, password: &str) -> bool {
+        let stored: Result<String, rusqlite::Error> = {
+            let connection = self.connection.lock().unwrap();
+            connection.query_row(
+                "SELECT password FROM users WHERE id = ?1",
+                (user_id,),
+                |row| row.get(0),
+            )
+        };
+
+        match stored {
+            Ok(stored) => Self::verify_password(&stored, password).await,
+            Err(_) => false,
         }
+    }
+
+    /// Changes the password of `user_id`, and logs them out everywhere.
+    pub async fn set_password(&self, user_id: u32, password: &str) -> Result<(), LoginError> {
+        let hash = Self::hash_password(password)
+            .await
+            .ok_or(LoginError::InternalError)?;
 
-        // service accounts
         {
-            let mut stmt = connection
-                .prepare("SELECT id FROM service_accounts WHERE user_id = ?1")
-                .ok()
-                .unwrap();
-            let accounts = stmt.query_map((user_id,), |row| row.get(0)).unwrap();
-
-            let mut service_accounts = Vec::new();
-            for (index, id) in accounts.enumerate() {
-                service_accounts.push(ServiceAccount {
-                    id: id.unwrap(),
-                    index: index as u32,
-                    name: format!("FINAL FANTASY XIV {}", index + 1), // TODO: don't add the "1" if you only have one service account
-                });
-            }
+            let connection = self.connection.lock().unwrap();
+            connection
+                .execute(
+                    "UPDATE users SET password = ?1 WHERE id = ?2;",
+                    (hash, user_id),
+                )
+                .map_err(|_| LoginError::InternalError)?;
+        }
+
+        self.remove_user_sessions(user_id);
+
+        Ok(())
+    }
+
+    /// Gets the service account list
+    pub fn check_session(&self, sid: &str) -> Vec<ServiceAccount> {
+        let connection = self.connection.lock().unwrap();
+
+        let Some(user_id) = Self::validate_session(&connection, sid) else {
+            return Vec::default();
+        };
+
+        // service accounts, disabled ones are hidden from the lobby
+        Self::list_service_accounts(&connection, user_id)
+            .into_iter()
+            .filter(|account| account.enabled)
+            .take(ServiceAccount::MAX_PER_PACKET)
+            .enumerate()
+            .map(|(index, account)| ServiceAccount {
+                id: account.id as u64,
+                index: index as u32,
+                name: account.name,
+            })
+            .collect()
+    }
+
+    /// Returns every service account of `user_id` in the order they were created.
+    fn list_service_accounts(connection: &Connection, user_id: u32) -> Vec<ServiceAccountInfo> {
+        let mut stmt = connection
+            .prepare(
+                "SELECT id, name, enabled FROM service_accounts WHERE user_id = ?1 ORDER BY rowid",
+            )
+            .unwrap();
+        let accounts: Vec<(u32, Option<String>, bool)> = stmt
+            .query_map((user_id,), |row| {
+                Ok((row.get(0)?, row.get(1)?, row.get(2)?))
+            })
+            .unwrap()
+            .filter_map(|row| row.ok())
+            .collect();
+
+        let count = accounts.len();
+        accounts
+            .into_iter()
+            .enumerate()
+            .map(|(index, (id, name, enabled))| ServiceAccountInfo {
+                id,
+                name: name
+                    .filter(|name| !name.is_empty())
+                    .unwrap_or_else(|| Self::default_service_account_name(index, count)),
+                enabled,
+            })
+            .collect()
+    }
 
-            service_accounts
+    /// The name of a service account the user hasn't named, numbered only if they have more than one.
+    fn default_service_account_name(index: usize, count: usize) -> String {
+        if count == 1 {
+            "FINAL FANTASY XIV".to_string()
+        } else {
+            format!("FINAL FANTASY XIV {}", index + 1)
         }
     }
 
@@ -189,14 +659,11 @@ impl LoginDatabase {
         selected_row.is_ok()
     }
 
-    pub fn get_user_id(&self, sid: &str) -> u32 {
+    /// Returns the user the session belongs to, or `None` if it's invalid or expired.
+    pub fn get_user_id(&self, sid: &str) -> Option<u32> {
         let connection = self.connection.lock().unwrap();
 
-        let mut stmt = connection
-            .prepare("SELECT user_id FROM sessions WHERE sid = ?1")
-            .ok()
-            .unwrap();
-        stmt.query_row((sid,), |row| row.get(0)).unwrap()
+        Self::validate_session(&connection, sid)
     }
 
     pub fn get_username(&self, user_id: u32) -> String {
@@ -209,14 +676,629 @@ impl LoginDatabase {
         stmt.query_row((user_id,), |row| row.get(0)).unwrap()
     }
 
-    /// TODO: only works for one
-    pub fn get_service_account(&self, user_id: u32) -> u32 {
+    /// Returns every service account owned by `user_id`, including disabled ones.
+    pub fn get_service_accounts(&self, user_id: u32) -> Vec<ServiceAccountInfo> {
+        let connection = self.connection.lock().unwrap();
+
+        Self::list_service_accounts(&connection, user_id)
+    }
+
+    /// Returns whether `user_id` owns `service_account_id`.
+    pub fn owns_service_account(&self, user_id: u32, service_account_id: u32) -> bool {
+        let connection = self.connection.lock().unwrap();
+
+        let mut stmt = connection
+            .prepare("SELECT id FROM service_accounts WHERE id = ?1 AND user_id = ?2")
+            .unwrap();
+        stmt.exists((service_account_id, user_id)).unwrap()
+    }
+
+    /// Adds a new service account to `user_id`, and returns it's id.
+    /// Returns `None` if they already have as many as the lobby can show.
+    pub fn add_service_account(&self, user_id: u32) -> Option<u32> {
+        let connection = self.connection.lock().unwrap();
+
+        let count: usize = connection
+            .query_row(
+                "SELECT COUNT(*) FROM service_accounts WHERE user_id = ?1",
+                (user_id,),
+                |row| row.get(0),
+            )
+            .ok()?;
+        if count >= ServiceAccount::MAX_PER_PACKET {
+            return None;
+        }
+
+        let id = Self::generate_account_id();
+        connection
+            .execute(
+                "INSERT INTO service_accounts (id, user_id, name, enabled) VALUES (?1, ?2, NULL, 1);",
+                (id, user_id),
+            )
+            .expect("Failed to write service account to database!");
+
+        tracing::info!("Added service account {id} to {user_id}");
+
+        Some(id)
+    }
+
+    /// Renames a service account, an empty name goes back to the default one.
+    /// Returns false if the name doesn't fit in `ServiceAccount`.
+    pub fn rename_service_account(&self, service_account_id: u32, name: &str) -> bool {
+        if name.len() >= ServiceAccount::NAME_SIZE {
+            return false;
+        }
+
+        let connection = self.connection.lock().unwrap();
+
+        let name = (!name.is_empty()).then_some(name);
+        connection
+            .execute(
+                "UPDATE service_accounts SET name = ?1 WHERE id = ?2;",
+                (name, service_account_id),
+            )
+            .expect("Failed to write service account to database!");
+
+        true
+    }
+
+    /// Enables or disables a service account, disabled ones are hidden from the lobby.
+    pub fn set_service_account_enabled(&self, service_account_id: u32, enabled: bool) {
+        let connection = self.connection.lock().unwrap();
+
+        connection
+            .execute(
+                "UPDATE service_accounts SET enabled = ?1 WHERE id = ?2;",
+                (enabled, service_account_id),
+            )
+            .expect("Failed to write service account to database!");
+    }
+
+    /// Removes a service account. It's characters live on the world servers, and have to be removed separately.
+    pub fn remove_service_account(&self, service_account_id: u32) {
+        let connection = self.connection.lock().unwrap();
+
+        connection
+            .execute(
+                "DELETE FROM service_accounts WHERE id = ?1;",
+                (service_account_id,),
+            )
+            .expect("Failed to remove service account from database!");
+
+        tracing::info!("Removed service account {service_account_id}");
+    }
+
+    /// Removes a user along with their service accounts and sessions.
+    /// Their characters live on the world servers, and have to be removed separately.
+    pub fn remove_user(&self, user_id: u32) {
+        let connection = self.connection.lock().unwrap();
+
+        connection
+            .execute("DELETE FROM sessions WHERE user_id = ?1;", (user_id,))
+            .expect("Failed to remove sessions from database!");
+        connection
+            .execute("DELETE FROM otp WHERE user_id = ?1;", (user_id,))
+            .expect("Failed to remove one-time password secret from database!");
+        connection
+            .execute("DELETE FROM recovery_codes WHERE user_id = ?1;", (user_id,))
+            .expect("Failed to remove recovery codes from database!");
+        connection
+            .execute(
+                "DELETE FROM bans WHERE user_id = ?1 OR service_account_id IN (SELECT id FROM service_accounts WHERE user_id = ?1);",
+                (user_id,),
+            )
+            .expect("Failed to remove bans from database!");
+        connection
+            .execute(
+                "DELETE FROM service_accounts WHERE user_id = ?1;",
+                (user_id,),
+            )
+            .expect("Failed to remove service accounts from database!");
+        connection
+            .execute(
+                "DELETE FROM known_addresses WHERE username = (SELECT username FROM users WHERE id = ?1);",
+                (user_id,),
+            )
+            .expect("Failed to remove known addresses from database!");
+        connection
+            .execute("DELETE FROM users WHERE id = ?1;", (user_id,))
+            .expect("Failed to remove user from database!");
+
+        tracing::info!("Removed account {user_id}");
+    }
+
+    /// Returns whether `user_id` has two-factor authentication enabled.
+    pub fn is_otp_enabled(&self, user_id: u32) -> bool {
         let connection = self.connection.lock().unwrap();
 
         let mut stmt = connection
-            .prepare("SELECT id FROM service_accounts WHERE user_id = ?1")
+            .prepare("SELECT user_id FROM otp WHERE user_id = ?1 AND enabled = 1")
+            .unwrap();
+        stmt.exists((user_id,)).unwrap()
+    }
+
+    /// Returns the secret to show while setting up two-factor authentication, creating one if needed.
+    /// Returns `None` if it's already enabled.
+    pub fn begin_otp_enrollment(&self, user_id: u32) -> Option<String> {
+        let connection = self.connection.lock().unwrap();
+
+        let existing: Result<(String, bool), rusqlite::Error> = connection.query_row(
+            "SELECT secret, enabled FROM otp WHERE user_id = ?1",
+            (user_id,),
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        );
+
+        match existing {
+            Ok((_, true)) => None,
+            Ok((secret, false)) => Some(secret),
+            Err(_) => {
+                let secret = otp::generate_secret()?;
+                connection
+                    .execute(
+                        "INSERT INTO otp (user_id, secret, enabled) VALUES (?1, ?2, 0);",
+                        (user_id, &secret),
+                    )
+                    .ok()?;
+
+                Some(secret)
+            }
+        }
+    }
+
+    /// Enables two-factor authentication if `otp` matches the secret from `begin_otp_enrollment`.
+    /// Returns the recovery codes, which are only stored as hashes and can't be shown again.
+    pub async fn enable_otp(&self, user_id: u32, otp: &str) -> Option<Vec<String>> {
+        let secret = self.begin_otp_enrollment(user_id)?;
+        let step = otp::check_otp(&secret, otp)?;
+
+        let codes = otp::generate_recovery_codes()?;
+        let mut hashes = Vec::with_capacity(codes.len());
+        for code in &codes {
+            hashes.push(Self::hash_password(code).await?);
+        }
+
+        let connection = self.connection.lock().unwrap();
+        for hash in hashes {
+            connection
+                .execute(
+                    "INSERT INTO recovery_codes VALUES (?1, ?2);",
+                    (user_id, hash),
+                )
+                .ok()?;
+        }
+        connection
+            .execute(
+                "UPDATE otp SET enabled = 1, last_step = ?1 WHERE user_id = ?2;",
+                (step, user_id),
+            )
+            .ok()?;
+
+        tracing::info!("Enabled two-factor authentication for {user_id}");
+
+        Some(codes)
+    }
+
+    /// Disables two-factor authentication, and throws away the secret and recovery codes.
+    pub fn disable_otp(&self, user_id: u32) {
+        let connection = self.connection.lock().unwrap();
+
+        connection
+            .execute("DELETE FROM otp WHERE user_id = ?1;", (user_id,))
+            .expect("Failed to remove one-time password secret from database!");
+        connection
+            .execute("DELETE FROM recovery_codes WHERE user_id = ?1;", (user_id,))
+            .expect("Failed to remove recovery codes from database!");
+
+        tracing::info!("Disabled two-factor authentication for {user_id}");
+    }
+
+    /// Checks `otp` if `user_id` has two-factor authentication enabled, otherwise it's ignored.
+    /// Recovery codes are accepted too, but only once.
+    async fn check_otp(&self, user_id: u32, otp: &str) -> bool {
+        let recovery_codes: Vec<(i64, String)> = {
+            let connection = self.connection.lock().unwrap();
+
+            let secret: Result<(String, u64), rusqlite::Error> = connection.query_row(
+                "SELECT secret, last_step FROM otp WHERE user_id = ?1 AND enabled = 1",
+                (user_id,),
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            );
+            let Ok((secret, last_step)) = secret else {
+                return true;
+            };
+
+            if let Some(step) = otp::check_otp(&secret, otp) {
+                // each one-time password is only accepted once
+                if step <= last_step {
+                    tracing::info!("{user_id} tried to reuse a one-time password");
+                    return false;
+                }
+
+                connection
+                    .execute(
+                        "UPDATE otp SET last_step = ?1 WHERE user_id = ?2;",
+                        (step, user_id),
+                    )
+                    .expect("Failed to write one-time password step to database!");
+
+                return true;
+            }
+
+            if !otp::is_recovery_code(otp.trim()) {
+                return false;
+            }
+
+            let mut stmt = connection
+                .prepare("SELECT rowid, code FROM recovery_codes WHERE user_id = ?1")
+                .unwrap();
+            stmt.query_map((user_id,), |row| Ok((row.get(0)?, row.get(1)?)))
+                .unwrap()
+                .filter_map(|row| row.ok())
+                .collect()
+        };
+
+        // the database isn't locked while hashing, so the code could have been used in the meantime
+        for (rowid, code) in recovery_codes {
+            if !Self::verify_password(&code, otp.trim()).await {
+                continue;
+            }
+
+            let connection = self.connection.lock().unwrap();
+            let used = connection
+                .execute("DELETE FROM recovery_codes WHERE rowid = ?1;", (rowid,))
+                .expect("Failed to remove recovery code from database!");
+            if used == 0 {
+                return false;
+            }
+
+            tracing::info!("{user_id} used a recovery code");
+
+            return true;
+        }
+
+        false
+    }
+
+    /// Returns how many seconds are left until `ip` and `username` can try to log in again, if either is locked out.
+    /// Usernames aren't locked out for IP addresses that logged into them before.
+    pub fn get_lockout(&self, ip: &str, username: &str) -> Option<u32> {
+        let connection = self.connection.lock().unwrap();
+
+        let mut stmt = connection
+            .prepare("SELECT 1 FROM known_addresses WHERE username = ?1 AND ip = ?2")
+            .unwrap();
+        let known = stmt.exists((username, ip)).unwrap_or_default();
+
+        let locked_until: Option<u32> = connection
+            .query_row(
+                "SELECT MAX(locked_until) FROM login_attempts WHERE key = ?1 OR (key = ?2 AND NOT ?3)",
+                (format!("ip:{ip}"), format!("user:{username}"), known),
+                |row| row.get(0),
+            )
             .ok()
+            .flatten();
+
+        let remaining = locked_until?.saturating_sub(timestamp_secs());
+        (remaining > 0).then_some(remaining)
+    }
+
+    /// Records a failed login from `ip` for `username`, locking both out if there were too many.
+    pub fn record_failed_login(&self, ip: &str, username: &str, reason: &str) {
+        let config = get_config().login;
+        let connection = self.connection.lock().unwrap();
+
+        let now = timestamp_secs();
+        for key in [format!("ip:{ip}"), format!("user:{username}")] {
+            let (mut failures, last_failure): (u32, u32) = connection
+                .query_row(
+                    "SELECT failures, last_failure FROM login_attempts WHERE key = ?1",
+                    (&key,),
+                    |row| Ok((row.get(0)?, row.get(1)?)),
+                )
+                .unwrap_or_default();
+
+            // old failures are forgiven
+            if now.saturating_sub(last_failure) > config.max_lockout_duration {
+                failures = 0;
+            }
+            failures = failures.saturating_add(1);
+
+            let lockout = lockout_duration(
+                failures,
+                config.login_attempts,
+                config.lockout_duration,
+                config.max_lockout_duration,
+            );
+            if lockout > 0 {
+                tracing::warn!(
+                    "Too many failed logins for {key}, locking out for {lockout} seconds"
+                );
+            }
+
+            connection
+                .execute(
+                    "INSERT OR REPLACE INTO login_attempts VALUES (?1, ?2, ?3, ?4);",
+                    (&key, failures, now, now + lockout),
+                )
+                .expect("Failed to write login attempt to database!");
+        }
+
+        connection
+            .execute(
+                "INSERT INTO login_audit VALUES (?1, ?2, ?3, ?4);",
+                (now, ip, username, reason),
+            )
+            .expect("Failed to write login audit to database!");
+    }
+
+    /// Forgets the failed logins for `username` after they logged in, and remembers `ip` as one of theirs.
+    /// Failures from the IP address are kept, so logging into another account doesn't reset them.
+    pub fn record_successful_login(&self, ip: &str, username: &str) {
+        let connection = self.connection.lock().unwrap();
+
+        connection
+            .execute(
+                "DELETE FROM login_attempts WHERE key = ?1;",
+                (format!("user:{username}"),),
+            )
+            .expect("Failed to remove login attempts from database!");
+        connection
+            .execute(
+                "INSERT OR REPLACE INTO known_addresses VALUES (?1, ?2, ?3);",
+                (username, ip, timestamp_secs()),
+            )
+            .expect("Failed to write known address to database!");
+    }
+
+    /// Removes failed logins that have been forgiven, and returns how many there were.
+    /// Old audit log entries and known addresses are removed too.
+    pub fn cleanup_login_attempts(&self) -> usize {
+        let config = get_config().login;
+        let connection = self.connection.lock().unwrap();
+
+        let now = timestamp_secs();
+        connection
+            .execute(
+                "DELETE FROM login_audit WHERE ?1 - time > ?2;",
+                (now, config.login_audit_lifetime),
+            )
+            .expect("Failed to remove login audit from database!");
+        connection
+            .execute(
+                "DELETE FROM known_addresses WHERE ?1 - last_login > ?2;",
+                (now, KNOWN_ADDRESS_LIFETIME),
+            )
+            .expect("Failed to remove known addresses from database!");
+
+        connection
+            .execute(
+                "DELETE FROM login_attempts WHERE ?1 - last_failure > ?2 AND locked_until <= ?1;",
+                (now, config.max_lockout_duration),
+            )
+            .unwrap_or_default()
+    }
+
+    /// Returns the most recent failed logins, newest first.
+    pub fn get_login_audit(&self, limit: u32) -> Vec<LoginAuditEntry> {
+        let connection = self.connection.lock().unwrap();
+
+        let mut stmt = connection
+            .prepare(
+                "SELECT time, ip, username, reason FROM login_audit ORDER BY time DESC LIMIT ?1",
+            )
             .unwrap();
-        stmt.query_row((user_id,), |row| row.get(0)).unwrap()
+        stmt.query_map((limit,), |row| {
+            Ok(LoginAuditEntry {
+                time: row.get(0)?,
+                ip: row.get(1)?,
+                username: row.get(2)?,
+                reason: row.get(3)?,
+            })
+        })
+        .unwrap()
+        .filter_map(|row| row.ok())
+        .collect()
+    }
+
+    /// Returns the id of the user named `username`, if any.
+    pub fn find_user_id(&self, username: &str) -> Option<u32> {
+        let connection = self.connection.lock().unwrap();
+
+        connection
+            .query_row(
+                "SELECT id FROM users WHERE username = ?1",
+                (username,),
+                |row| row.get(0),
+            )
+            .ok()
+    }
+
+    /// Returns the role of `user_id`, users that don't exist are treated as players.
+    pub fn get_role(&self, user_id: u32) -> AccountRole {
+        let connection = self.connection.lock().unwrap();
+
+        connection
+            .query_row("SELECT role FROM users WHERE id = ?1", (user_id,), |row| {
+                row.get(0)
+            })
+            .map(AccountRole::from_u8)
+            .unwrap_or_default()
+    }
+
+    /// Returns the role of the user owning `service_account_id`, if it exists.
+    pub fn get_service_account_role(&self, service_account_id: u32) -> Option<AccountRole> {
+        let connection = self.connection.lock().unwrap();
+
+        connection
+            .query_row(
+                "SELECT users.role FROM service_accounts JOIN users ON users.id = service_accounts.user_id WHERE service_accounts.id = ?1",
+                (service_account_id,),
+                |row| row.get(0),
+            )
+            .map(AccountRole::from_u8)
+            .ok()
+    }
+
+    pub fn set_role(&self, user_id: u32, role: AccountRole) {
+        let connection = self.connection.lock().unwrap();
+
+        connection
+            .execute(
+                "UPDATE users SET role = ?1 WHERE id = ?2;",
+                (role as u8, user_id),
+            )
+            .expect("Failed to write role to database!");
+
+        tracing::info!("{user_id} is now {role:?}");
+    }
+
+    /// Returns the active ban matching `condition`, the one lasting the longest if there are several.
+    fn find_ban(connection: &Connection, condition: &str, id: u32) -> Option<Ban> {
+        connection
+            .query_row(
+                &format!(
+                    "SELECT reason, expires FROM bans WHERE {condition} = ?1 AND (expires IS NULL OR expires > ?2) ORDER BY expires IS NULL DESC, expires DESC"
+                ),
+                (id, timestamp_secs()),
+                |row| {
+                    Ok(Ban {
+                        reason: row.get(0)?,
+                        expires: row.get(1)?,
+                    })
+                },
+            )
+            .ok()
+    }
+
+    /// Returns the active ban of `user_id`, if they are banned.
+    pub fn get_ban(&self, user_id: u32) -> Option<Ban> {
+        let connection = self.connection.lock().unwrap();
+
+        Self::find_ban(&connection, "user_id", user_id)
+    }
+
+    /// Returns the active suspension of `service_account_id`, if it's suspended.
+    pub fn get_suspension(&self, service_account_id: u32) -> Option<Ban> {
+        let connection = self.connection.lock().unwrap();
+
+        Self::find_ban(&connection, "service_account_id", service_account_id)
+    }
+
+    /// Bans `user_id` from logging in, and logs them out everywhere.
+    pub fn ban_user(&self, user_id: u32, ban: &Ban) {
+        {
+            let connection = self.connection.lock().unwrap();
+
+            connection
+                .execute(
+                    "INSERT INTO bans VALUES (?1, NULL, ?2, ?3, ?4);",
+                    (user_id, &ban.reason, timestamp_secs(), ban.expires),
+                )
+                .expect("Failed to write ban to database!");
+        }
+
+        tracing::info!("Banned {user_id}: {}", ban.describe());
+
+        self.remove_user_sessions(user_id);
+    }
+
+    /// Lifts every ban on `user_id`.
+    pub fn unban_user(&self, user_id: u32) {
+        let connection = self.connection.lock().unwrap();
+
+        connection
+            .execute("DELETE FROM bans WHERE user_id = ?1;", (user_id,))
+            .expect("Failed to remove ban from database!");
+
+        tracing::info!("Unbanned {user_id}");
+    }
+
+    /// Suspends `service_account_id`, the user can still log in but can't use it in the lobby.
+    pub fn suspend_service_account(&self, service_account_id: u32, ban: &Ban) {
+        let connection = self.connection.lock().unwrap();
+
+        connection
+            .execute(
+                "INSERT INTO bans VALUES (NULL, ?1, ?2, ?3, ?4);",
+                (
+                    service_account_id,
+                    &ban.reason,
+                    timestamp_secs(),
+                    ban.expires,
+                ),
+            )
+            .expect("Failed to write suspension to database!");
+
+        tracing::info!("Suspended {service_account_id}: {}", ban.describe());
+    }
+
+    /// Lifts every suspension on `service_account_id`.
+    pub fn unsuspend_service_account(&self, service_account_id: u32) {
+        let connection = self.connection.lock().unwrap();
+
+        connection
+            .execute(
+                "DELETE FROM bans WHERE service_account_id = ?1;",
+                (service_account_id,),
+            )
+            .expect("Failed to remove suspension from database!");
+
+        tracing::info!("Unsuspended {service_account_id}");
+    }
+
+    /// Returns every ban and suspension that is still active.
+    pub fn get_active_bans(&self) -> Vec<BanEntry> {
+        let connection = self.connection.lock().unwrap();
+
+        let mut stmt = connection
+            .prepare(
+                "SELECT users.username, bans.service_account_id, bans.reason, bans.expires FROM bans
+                JOIN users ON users.id = COALESCE(bans.user_id, (SELECT user_id FROM service_accounts WHERE id = bans.service_account_id))
+                WHERE bans.expires IS NULL OR bans.expires > ?1",
+            )
+            .unwrap();
+        stmt.query_map((timestamp_secs(),), |row| {
+            let ban = Ban {
+                reason: row.get(2)?,
+                expires: row.get(3)?,
+            };
+
+            Ok(BanEntry {
+                username: row.get(0)?,
+                service_account_id: row.get(1)?,
+                description: ban.describe(),
+            })
+        })
+        .unwrap()
+        .filter_map(|row| row.ok())
+        .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn password_hashing() {
+        let hash = LoginDatabase::hash_password("hunter2").await.unwrap();
+        assert!(LoginDatabase::is_password_hash(&hash));
+        assert!(LoginDatabase::verify_password(&hash, "hunter2").await);
+        assert!(!LoginDatabase::verify_password(&hash, "hunter3").await);
+
+        // each hash gets it's own salt
+        assert_ne!(hash, LoginDatabase::hash_password("hunter2").await.unwrap());
+
+        // plaintext from before passwords were hashed
+        assert!(!LoginDatabase::is_password_hash("hunter2"));
+        assert!(LoginDatabase::verify_password("hunter2", "hunter2").await);
+        assert!(!LoginDatabase::verify_password("hunter2", "hunter3").await);
+    }
+
+    #[test]
+    fn dummy_password_hash() {
+        // otherwise unknown usernames would skip the expensive part
+        let hash = PasswordHash::new(LoginDatabase::DUMMY_PASSWORD_HASH).unwrap();
+        assert_eq!(hash.algorithm.as_str(), "argon2id");
     }
 }
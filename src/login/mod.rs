@@ -1,2 +1,20 @@
 mod database;
-pub use database::{LoginDatabase, LoginError};
+pub use database::{LoginDatabase, LoginError, ServiceAccountInfo, SessionKind};
+
+mod otp;
+pub use otp::generate_qr_code;
+
+mod moderation;
+pub use moderation::{AccountRole, Ban, BanEntry};
+
+mod registration;
+pub use registration::{RegistrationError, validate_password, validate_username};
+
+mod throttle;
+pub use throttle::{LoginAuditEntry, client_ip};
+
+mod api;
+pub use api::{ApiError, ApiErrorCode};
+
+mod csrf;
+pub use csrf::{csrf_token, verify_csrf_token};
@@ -0,0 +1,88 @@
+use serde::{Deserialize, Serialize};
+
+use crate::common::timestamp_secs;
+
+/// What a user is allowed to do, each role can do everything the ones before it can.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum AccountRole {
+    #[default]
+    Player = 0,
+    /// Can ban and suspend players, and use GM commands in the game.
+    Gm = 1,
+    /// Can also change the roles of other users.
+    Admin = 2,
+}
+
+impl AccountRole {
+    pub fn from_u8(value: u8) -> Self {
+        match value {
+            1 => AccountRole::Gm,
+            2 => AccountRole::Admin,
+            _ => AccountRole::Player,
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "player" => Some(AccountRole::Player),
+            "gm" => Some(AccountRole::Gm),
+            "admin" => Some(AccountRole::Admin),
+            _ => None,
+        }
+    }
+}
+
+/// Why a user was banned, or a service account was suspended.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Ban {
+    pub reason: String,
+    /// UNIX timestamp of when the ban is lifted, `None` if it's permanent.
+    pub expires: Option<u32>,
+}
+
+impl Ban {
+    /// Returns a message explaining the ban, e.g. "griefing (3 days left)".
+    pub fn describe(&self) -> String {
+        match self.expires {
+            Some(expires) => {
+                let remaining = expires.saturating_sub(timestamp_secs());
+                let (amount, unit) = if remaining >= 24 * 60 * 60 {
+                    (remaining / (24 * 60 * 60), "days")
+                } else if remaining >= 60 * 60 {
+                    (remaining / (60 * 60), "hours")
+                } else {
+                    (remaining.div_ceil(60), "minutes")
+                };
+
+                format!("{} ({amount} {unit} left)", self.reason)
+            }
+            None => format!("{} (permanent)", self.reason),
+        }
+    }
+}
+
+/// An active ban or suspension, as shown to GMs on the website.
+#[derive(Debug, Clone, Serialize)]
+pub struct BanEntry {
+    pub username: String,
+    /// The suspended service account, or `None` if the whole account is banned.
+    pub service_account_id: Option<u32>,
+    pub description: String,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn roles() {
+        assert!(AccountRole::Admin > AccountRole::Gm);
+        assert!(AccountRole::Gm > AccountRole::Player);
+
+        for role in [AccountRole::Player, AccountRole::Gm, AccountRole::Admin] {
+            assert_eq!(AccountRole::from_u8(role as u8), role);
+        }
+        assert_eq!(AccountRole::from_name("gm"), Some(AccountRole::Gm));
+        assert_eq!(AccountRole::from_name("owner"), None);
+    }
+}
@@ -0,0 +1,116 @@
+use qrcode::{QrCode, render::svg};
+use totp_rs::{Algorithm, Secret, TOTP};
+
+use crate::common::timestamp_secs;
+
+/// Shown in authenticator apps next to the username.
+const ISSUER: &str = "Kodama";
+
+/// Number of seconds each one-time password is valid for.
+const STEP: u64 = 30;
+
+/// Number of steps a one-time password can be off by, to allow for clock drift.
+const ALLOWED_DRIFT: u64 = 1;
+
+/// Number of recovery codes handed out when enabling two-factor authentication.
+pub const RECOVERY_CODE_COUNT: usize = 10;
+
+/// Length of each recovery code.
+const RECOVERY_CODE_LENGTH: usize = 10;
+
+/// Generates a new random secret, encoded in base32 like authenticator apps expect.
+pub fn generate_secret() -> Option<String> {
+    let mut secret = [0; 20];
+    getrandom::fill(&mut secret).ok()?;
+
+    Some(Secret::Raw(secret.to_vec()).to_encoded().to_string())
+}
+
+/// Sets up TOTP with the same parameters as most authenticator apps: SHA1, 6 digits and 30 second steps.
+/// Clock drift is handled by `check_otp`, so it knows which step matched.
+fn create_totp(secret: &str, username: &str) -> Option<TOTP> {
+    let secret = Secret::Encoded(secret.to_string()).to_bytes().ok()?;
+
+    TOTP::new(
+        Algorithm::SHA1,
+        6,
+        0,
+        STEP,
+        secret,
+        Some(ISSUER.to_string()),
+        username.replace(':', ""),
+    )
+    .ok()
+}
+
+/// Checks a one-time password against `secret`, and returns the step it belongs to.
+/// Callers should only accept steps newer than the last one that was used, so a password can't be used twice.
+pub fn check_otp(secret: &str, otp: &str) -> Option<u64> {
+    let totp = create_totp(secret, "")?;
+    let current_step = timestamp_secs() as u64 / STEP;
+
+    (current_step.saturating_sub(ALLOWED_DRIFT)..=current_step + ALLOWED_DRIFT)
+        .find(|step| totp.check(otp.trim(), step * STEP))
+}
+
+/// Returns a QR code as an SVG image, which can be scanned by authenticator apps to add `secret`.
+pub fn generate_qr_code(secret: &str, username: &str) -> Option<String> {
+    let totp = create_totp(secret, username)?;
+    let code = QrCode::new(totp.get_url()).ok()?;
+
+    Some(code.render::<svg::Color>().min_dimensions(200, 200).build())
+}
+
+/// Characters used in recovery codes.
+const RECOVERY_CODE_ALPHABET: &[u8; 32] = b"abcdefghijklmnopqrstuvwxyz234567";
+
+/// Generates single-use codes that can be used instead of a one-time password, e.g. if the user lost their phone.
+pub fn generate_recovery_codes() -> Option<Vec<String>> {
+    let mut codes = Vec::with_capacity(RECOVERY_CODE_COUNT);
+    for _ in 0..RECOVERY_CODE_COUNT {
+        let mut bytes = [0; RECOVERY_CODE_LENGTH];
+        getrandom::fill(&mut bytes).ok()?;
+
+        codes.push(
+            bytes
+                .iter()
+                .map(|byte| RECOVERY_CODE_ALPHABET[(*byte & 31) as usize] as char)
+                .collect(),
+        );
+    }
+
+    Some(codes)
+}
+
+/// Returns whether `code` could be a recovery code, so the stored hashes aren't checked for anything else.
+pub fn is_recovery_code(code: &str) -> bool {
+    code.len() == RECOVERY_CODE_LENGTH
+        && code
+            .bytes()
+            .all(|byte| RECOVERY_CODE_ALPHABET.contains(&byte))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn one_time_passwords() {
+        let secret = generate_secret().unwrap();
+        let totp = create_totp(&secret, "username").unwrap();
+
+        let now = timestamp_secs() as u64;
+        let otp = totp.generate(now);
+        assert_eq!(check_otp(&secret, &otp), Some(now / STEP));
+        assert!(check_otp(&secret, "").is_none());
+        assert!(check_otp(&generate_secret().unwrap(), &otp).is_none());
+    }
+
+    #[test]
+    fn recovery_codes() {
+        let codes = generate_recovery_codes().unwrap();
+        assert_eq!(codes.len(), RECOVERY_CODE_COUNT);
+        assert!(codes.iter().all(|code| is_recovery_code(code)));
+        assert!(!is_recovery_code("123456"));
+    }
+}
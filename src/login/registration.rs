@@ -0,0 +1,96 @@
+/// Why an account couldn't be registered.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RegistrationError {
+    Closed,
+    InvalidInvite,
+    InvalidUsername,
+    InvalidPassword,
+    UsernameTaken,
+    InternalError,
+}
+
+impl std::fmt::Display for RegistrationError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RegistrationError::Closed => write!(f, "Registration is closed."),
+            RegistrationError::InvalidInvite => {
+                write!(f, "The invite code is invalid, or was already used.")
+            }
+            RegistrationError::InvalidUsername => write!(
+                f,
+                "Usernames must be {USERNAME_MIN_LENGTH} to {USERNAME_MAX_LENGTH} characters long, and only contain letters, numbers, - and _."
+            ),
+            RegistrationError::InvalidPassword => write!(
+                f,
+                "Passwords must be {PASSWORD_MIN_LENGTH} to {PASSWORD_MAX_LENGTH} characters long, and can't contain spaces."
+            ),
+            RegistrationError::UsernameTaken => write!(f, "That username is already taken."),
+            RegistrationError::InternalError => {
+                write!(f, "Something went wrong, please try again later.")
+            }
+        }
+    }
+}
+
+const USERNAME_MIN_LENGTH: usize = 3;
+/// Same as the ID field of the retail login form.
+const USERNAME_MAX_LENGTH: usize = 16;
+
+const PASSWORD_MIN_LENGTH: usize = 8;
+/// Same as the password field of the retail login form.
+const PASSWORD_MAX_LENGTH: usize = 32;
+
+/// Checks that a username can be typed into the retail login form.
+pub fn validate_username(username: &str) -> Result<(), RegistrationError> {
+    if (USERNAME_MIN_LENGTH..=USERNAME_MAX_LENGTH).contains(&username.len())
+        && username
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+    {
+        Ok(())
+    } else {
+        Err(RegistrationError::InvalidUsername)
+    }
+}
+
+/// Checks that a password can be typed into the retail login form.
+pub fn validate_password(password: &str) -> Result<(), RegistrationError> {
+    if (PASSWORD_MIN_LENGTH..=PASSWORD_MAX_LENGTH).contains(&password.len())
+        && password.chars().all(|c| c.is_ascii_graphic())
+    {
+        Ok(())
+    } else {
+        Err(RegistrationError::InvalidPassword)
+    }
+}
+
+/// Generates a new random invite code.
+pub fn generate_invite_code() -> Option<String> {
+    let mut bytes = [0; 12];
+    getrandom::fill(&mut bytes).ok()?;
+
+    Some(bytes.iter().map(|byte| format!("{byte:02x}")).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn usernames() {
+        assert!(validate_username("kodama").is_ok());
+        assert!(validate_username("ko-da_ma123").is_ok());
+        assert!(validate_username("ko").is_err());
+        assert!(validate_username("kodamakodamakodam").is_err());
+        assert!(validate_username("ko dama").is_err());
+        assert!(validate_username("kodamá").is_err());
+    }
+
+    #[test]
+    fn passwords() {
+        assert!(validate_password("hunter22").is_ok());
+        assert!(validate_password("hunter2").is_err());
+        assert!(validate_password(&"a".repeat(33)).is_err());
+        assert!(validate_password("hunter 22").is_err());
+    }
+}
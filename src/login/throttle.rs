@@ -0,0 +1,76 @@
+use std::net::IpAddr;
+
+use serde::Serialize;
+
+/// Number of seconds an IP address is remembered after logging into an account, and not locked out with it's username.
+pub const KNOWN_ADDRESS_LIFETIME: u32 = 90 * 24 * 60 * 60; // 90 days
+
+/// A failed login, as shown to admins on the website.
+#[derive(Debug, Clone, Serialize)]
+pub struct LoginAuditEntry {
+    /// UNIX timestamp of the attempt.
+    pub time: u32,
+    pub ip: String,
+    pub username: String,
+    /// Why the attempt failed, e.g. "WrongPassword".
+    pub reason: String,
+}
+
+/// Returns how many seconds to lock out an IP or username after `failures` failed attempts in a row.
+/// The first `free_attempts` failures aren't punished, after that the lockout doubles each time.
+pub fn lockout_duration(failures: u32, free_attempts: u32, base: u32, max: u32) -> u32 {
+    if failures < free_attempts {
+        return 0;
+    }
+
+    let doublings = (failures - free_attempts).min(31);
+    base.saturating_mul(1 << doublings).min(max)
+}
+
+/// Returns the address of the client that made a request, which came from `peer`.
+/// If `peer` is a trusted proxy, the last address in it's `X-Forwarded-For` header that isn't a trusted proxy is used instead.
+pub fn client_ip(peer: IpAddr, forwarded_for: Option<&str>, trusted_proxies: &[IpAddr]) -> IpAddr {
+    if !trusted_proxies.contains(&peer) {
+        return peer;
+    }
+
+    // anything before the last untrusted address could have been made up by the client
+    forwarded_for
+        .unwrap_or_default()
+        .rsplit(',')
+        .filter_map(|address| address.trim().parse::<IpAddr>().ok())
+        .find(|address| !trusted_proxies.contains(address))
+        .unwrap_or(peer)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn exponential_backoff() {
+        assert_eq!(lockout_duration(0, 5, 30, 3600), 0);
+        assert_eq!(lockout_duration(4, 5, 30, 3600), 0);
+        assert_eq!(lockout_duration(5, 5, 30, 3600), 30);
+        assert_eq!(lockout_duration(6, 5, 30, 3600), 60);
+        assert_eq!(lockout_duration(7, 5, 30, 3600), 120);
+        assert_eq!(lockout_duration(12, 5, 30, 3600), 3600);
+        assert_eq!(lockout_duration(u32::MAX, 5, 30, 3600), 3600);
+    }
+
+    #[test]
+    fn forwarded_client_ip() {
+        let proxy: IpAddr = "10.0.0.1".parse().unwrap();
+        let client: IpAddr = "203.0.113.7".parse().unwrap();
+        let trusted = [proxy];
+
+        // only trusted proxies can tell us who the client is
+        assert_eq!(client_ip(client, Some("198.51.100.1"), &trusted), client);
+        assert_eq!(client_ip(proxy, Some("203.0.113.7"), &trusted), client);
+        assert_eq!(
+            client_ip(proxy, Some("198.51.100.1, 203.0.113.7, 10.0.0.1"), &trusted),
+            client
+        );
+        assert_eq!(client_ip(proxy, None, &trusted), proxy);
+    }
+}
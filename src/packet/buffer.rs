@@ -0,0 +1,75 @@
+use super::PacketHeader;
+
+/// The packet header claimed a size smaller than the header itself, so the stream can't be trusted anymore.
+#[derive(Debug)]
+pub struct InvalidPacketSize;
+
+/// Collects data as it's read from a socket, and hands out packets once they fully arrived.
+/// A single read may contain only part of a packet, or several packets at once.
+#[derive(Debug, Default)]
+pub struct PacketBuffer {
+    received: Vec<u8>,
+}
+
+impl PacketBuffer {
+    pub fn push(&mut self, data: &[u8]) {
+        self.received.extend_from_slice(data);
+    }
+
+    /// Removes the next whole packet from the buffer, or returns `None` if it hasn't fully arrived yet.
+    pub fn next_packet(&mut self) -> Result<Option<Vec<u8>>, InvalidPacketSize> {
+        if self.received.len() < std::mem::size_of::<PacketHeader>() {
+            return Ok(None);
+        }
+
+        // the size is stored in the packet header
+        let size = u16::from_le_bytes([self.received[4], self.received[5]]) as usize;
+        if size < std::mem::size_of::<PacketHeader>() {
+            return Err(InvalidPacketSize);
+        }
+        if self.received.len() < size {
+            return Ok(None);
+        }
+
+        Ok(Some(self.received.drain(..size).collect()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn packet(size: u16, fill: u8) -> Vec<u8> {
+        let mut packet = vec![fill; size as usize];
+        packet[4..6].copy_from_slice(&size.to_le_bytes());
+        packet
+    }
+
+    #[test]
+    fn split_and_coalesced_packets() {
+        let first = packet(24, 1);
+        let second = packet(32, 2);
+        let stream = [first.clone(), second.clone()].concat();
+
+        let mut buffer = PacketBuffer::default();
+
+        // the first packet arrives in two parts, with the start of the second one
+        buffer.push(&stream[..10]);
+        assert_eq!(buffer.next_packet().unwrap(), None);
+        buffer.push(&stream[10..30]);
+        assert_eq!(buffer.next_packet().unwrap(), Some(first));
+        assert_eq!(buffer.next_packet().unwrap(), None);
+
+        buffer.push(&stream[30..]);
+        assert_eq!(buffer.next_packet().unwrap(), Some(second));
+        assert_eq!(buffer.next_packet().unwrap(), None);
+    }
+
+    #[test]
+    fn invalid_size() {
+        let mut buffer = PacketBuffer::default();
+        buffer.push(&packet(16, 0)[..16]);
+        buffer.received[4..6].copy_from_slice(&4u16.to_le_bytes());
+        assert!(buffer.next_packet().is_err());
+    }
+}
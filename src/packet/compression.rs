@@ -26,7 +26,7 @@ pub(crate) fn decompress<T: ReadWriteIpcSegment>(
     let size = header.size as usize - std::mem::size_of::<PacketHeader>();
 
     let mut data = vec![0; size];
-    reader.read_exact(&mut data).unwrap();
+    reader.read_exact(&mut data)?;
 
     let data = data; // TODO: implement compression
 
@@ -0,0 +1,147 @@
+use std::time::Duration;
+
+use tokio::{io::AsyncReadExt, net::TcpStream};
+
+use crate::{
+    RECEIVE_BUFFER_SIZE,
+    common::{sign, verify_signature},
+    config::get_config,
+    ipc::kodama::{AUTH_CHALLENGE_SIZE, CustomIpcData, CustomIpcSegment, CustomIpcType},
+};
+
+use super::{PacketState, SegmentData, parse_packet, send_custom_ipc};
+
+/// How long the connecting server has to finish authenticating.
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Prepended to the challenge before signing, so these signatures can't be confused with other uses of the shared secret.
+const CHALLENGE_CONTEXT: &[u8] = b"kodama custom ipc";
+
+fn challenge_message(challenge: &[u8]) -> Vec<u8> {
+    [CHALLENGE_CONTEXT, challenge].concat()
+}
+
+/// Tracks whether the other side of a connection proved it knows the shared secret.
+/// Custom IPC must be ignored until `is_authenticated()` returns true.
+#[derive(Debug, Default)]
+pub struct CustomIpcAuth {
+    /// The challenge we sent, if any.
+    challenge: Option<Vec<u8>>,
+    authenticated: bool,
+}
+
+impl CustomIpcAuth {
+    pub fn is_authenticated(&self) -> bool {
+        self.authenticated
+    }
+
+    /// Handles the authentication handshake. If `segment` is part of it, this returns the response to send back.
+    pub fn handle_handshake(&mut self, segment: &CustomIpcSegment) -> Option<CustomIpcSegment> {
+        let (op_code, data) = match &segment.data {
+            CustomIpcData::RequestAuthChallenge {} => {
+                let mut challenge = vec![0; AUTH_CHALLENGE_SIZE];
+                getrandom::fill(&mut challenge).expect("Failed to generate a challenge!");
+
+                self.challenge = Some(challenge.clone());
+                self.authenticated = false;
+
+                (
+                    CustomIpcType::AuthChallenge,
+                    CustomIpcData::AuthChallenge { challenge },
+                )
+            }
+            CustomIpcData::Authenticate { signature } => {
+                // a challenge can only be answered once
+                let shared_secret = get_config().shared_secret;
+                self.authenticated = match self.challenge.take() {
+                    // anyone can sign with an empty secret
+                    Some(_) if shared_secret.is_empty() => false,
+                    Some(challenge) => {
+                        verify_signature(&shared_secret, &challenge_message(&challenge), signature)
+                    }
+                    None => false,
+                };
+
+                (
+                    CustomIpcType::Authenticated,
+                    CustomIpcData::Authenticated {
+                        accepted: self.authenticated,
+                    },
+                )
+            }
+            _ => return None,
+        };
+
+        Some(CustomIpcSegment {
+            request_id: segment.request_id,
+            op_code,
+            data,
+            ..Default::default()
+        })
+    }
+}
+
+/// Reads custom IPC from `stream` until we get something.
+async fn read_custom_ipc(stream: &mut TcpStream, state: &mut PacketState) -> Option<CustomIpcData> {
+    let mut buf = vec![0; RECEIVE_BUFFER_SIZE];
+    let n = stream.read(&mut buf).await.ok()?;
+    if n == 0 {
+        return None;
+    }
+
+    let (segments, _) = parse_packet::<CustomIpcSegment>(&buf[..n], state);
+    segments.into_iter().find_map(|segment| match segment.data {
+        SegmentData::KodamaIpc { data } => Some(data.data),
+        _ => None,
+    })
+}
+
+/// Proves to the server on the other side of `stream` that we know the shared secret.
+/// This must be done before sending any other custom IPC.
+pub async fn authenticate_custom_ipc(stream: &mut TcpStream, state: &mut PacketState) -> bool {
+    // the other side refuses an empty secret anyway
+    if get_config().shared_secret.is_empty() {
+        return false;
+    }
+
+    let handshake = async {
+        send_custom_ipc(
+            stream,
+            state,
+            CustomIpcSegment {
+                op_code: CustomIpcType::RequestAuthChallenge,
+                data: CustomIpcData::RequestAuthChallenge {},
+                ..Default::default()
+            },
+        )
+        .await;
+
+        let Some(CustomIpcData::AuthChallenge { challenge }) = read_custom_ipc(stream, state).await
+        else {
+            return false;
+        };
+
+        let signature = sign(&get_config().shared_secret, &challenge_message(&challenge));
+        send_custom_ipc(
+            stream,
+            state,
+            CustomIpcSegment {
+                op_code: CustomIpcType::Authenticate,
+                data: CustomIpcData::Authenticate {
+                    signature: signature.to_vec(),
+                },
+                ..Default::default()
+            },
+        )
+        .await;
+
+        matches!(
+            read_custom_ipc(stream, state).await,
+            Some(CustomIpcData::Authenticated { accepted: true })
+        )
+    };
+
+    tokio::time::timeout(HANDSHAKE_TIMEOUT, handshake)
+        .await
+        .unwrap_or(false)
+}
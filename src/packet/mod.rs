@@ -4,6 +4,9 @@ pub use parsing::{
     parse_packet,
 };
 
+mod buffer;
+pub use buffer::{InvalidPacketSize, PacketBuffer};
+
 mod compression;
 pub use compression::CompressionType;
 
@@ -17,4 +20,16 @@ pub use ipc::{IPC_HEADER_SIZE, IpcSegment, ReadWriteIpcSegment};
 #[cfg(not(target_family = "wasm"))]
 mod send_helpers;
 #[cfg(not(target_family = "wasm"))]
-pub use send_helpers::{send_custom_world_packet, send_keep_alive, send_packet};
+pub use send_helpers::{send_custom_ipc, send_keep_alive, send_packet};
+
+/// Authentication of server-to-server communication.
+#[cfg(not(target_family = "wasm"))]
+mod custom_ipc_auth;
+#[cfg(not(target_family = "wasm"))]
+pub use custom_ipc_auth::{CustomIpcAuth, authenticate_custom_ipc};
+
+/// Long-lived connection to a world server.
+#[cfg(not(target_family = "wasm"))]
+mod world_channel;
+#[cfg(not(target_family = "wasm"))]
+pub use world_channel::WorldChannel;
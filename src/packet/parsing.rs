@@ -181,17 +181,27 @@ pub fn parse_packet<T: ReadWriteIpcSegment>(
 ) -> (Vec<PacketSegment<T>>, ConnectionType) {
     let mut cursor = Cursor::new(data);
 
-    match Packet::read_le_args(
-        &mut cursor,
-        (state.client_key.as_ref().map(|s: &[u8; 16]| s.as_slice()),),
-    ) {
-        Ok(packet) => (packet.segments, packet.header.connection_type),
-        Err(err) => {
-            tracing::error!("{err}");
-
-            (Vec::new(), ConnectionType::None)
+    let mut segments = Vec::new();
+    let mut connection_type = ConnectionType::None;
+
+    // more than one packet may have arrived at once
+    while (cursor.position() as usize) < data.len() {
+        match Packet::read_le_args(
+            &mut cursor,
+            (state.client_key.as_ref().map(|s: &[u8; 16]| s.as_slice()),),
+        ) {
+            Ok(mut packet) => {
+                segments.append(&mut packet.segments);
+                connection_type = packet.header.connection_type;
+            }
+            Err(err) => {
+                tracing::error!("{err}");
+                break;
+            }
         }
     }
+
+    (segments, connection_type)
 }
 
 #[cfg(test)]
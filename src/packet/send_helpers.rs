@@ -1,27 +1,23 @@
 use std::io::Cursor;
 
 use binrw::BinWrite;
-use tokio::{
-    io::{AsyncReadExt, AsyncWriteExt},
-    net::TcpStream,
-};
+use tokio::{io::AsyncWriteExt, net::TcpStream};
 
-use crate::{
-    RECEIVE_BUFFER_SIZE, common::timestamp_msecs, config::get_config, ipc::kodama::CustomIpcSegment,
-};
+use crate::{common::timestamp_msecs, ipc::kodama::CustomIpcSegment};
 
 use super::{
     CompressionType, ConnectionType, PacketHeader, PacketSegment, PacketState, ReadWriteIpcSegment,
-    SegmentData, SegmentType, compression::compress, parse_packet,
+    SegmentData, SegmentType, compression::compress,
 };
 
+/// Returns whether the packet was written to `socket`.
 pub async fn send_packet<T: ReadWriteIpcSegment>(
     socket: &mut TcpStream,
     state: &mut PacketState,
     connection_type: ConnectionType,
     compression_type: CompressionType,
     segments: &[PacketSegment<T>],
-) {
+) -> bool {
     let data = compress(state, &compression_type, segments);
     let size = std::mem::size_of::<PacketHeader>() + data.len();
 
@@ -44,7 +40,10 @@ pub async fn send_packet<T: ReadWriteIpcSegment>(
 
     if let Err(e) = socket.write_all(&buffer).await {
         tracing::warn!("Failed to send packet: {e}");
+        return false;
     }
+
+    true
 }
 
 pub async fn send_keep_alive<T: ReadWriteIpcSegment>(
@@ -69,43 +68,22 @@ pub async fn send_keep_alive<T: ReadWriteIpcSegment>(
     .await;
 }
 
-/// Sends a custom IPC packet to the world server, meant for private server-to-server communication.
-/// Returns the first custom IPC segment returned.
-pub async fn send_custom_world_packet(segment: CustomIpcSegment) -> Option<CustomIpcSegment> {
-    let config = get_config();
-
-    let addr = config.world.get_public_socketaddr();
-
-    let mut stream = TcpStream::connect(addr).await.unwrap();
-
-    let mut packet_state = PacketState { client_key: None };
-
-    let segment: PacketSegment<CustomIpcSegment> = PacketSegment {
-        segment_type: SegmentType::KodamaIpc,
-        data: SegmentData::KodamaIpc { data: segment },
-        ..Default::default()
-    };
-
-    send_packet(
-        &mut stream,
-        &mut packet_state,
+/// Sends a single custom IPC segment, meant for private server-to-server communication.
+pub async fn send_custom_ipc(
+    socket: &mut TcpStream,
+    state: &mut PacketState,
+    segment: CustomIpcSegment,
+) {
+    send_packet::<CustomIpcSegment>(
+        socket,
+        state,
         ConnectionType::None,
         CompressionType::Uncompressed,
-        &[segment],
+        &[PacketSegment {
+            segment_type: SegmentType::KodamaIpc,
+            data: SegmentData::KodamaIpc { data: segment },
+            ..Default::default()
+        }],
     )
     .await;
-
-    // read response
-    let mut buf = vec![0; RECEIVE_BUFFER_SIZE];
-    let n = stream.read(&mut buf).await.expect("Failed to read data!");
-    if n != 0 {
-        let (segments, _) = parse_packet::<CustomIpcSegment>(&buf[..n], &mut packet_state);
-
-        return match &segments[0].data {
-            SegmentData::KodamaIpc { data } => Some(data.clone()),
-            _ => None,
-        };
-    }
-
-    None
 }
@@ -0,0 +1,279 @@
+use std::{
+    collections::HashMap,
+    net::SocketAddr,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicU32, Ordering},
+    },
+    time::Duration,
+};
+
+use tokio::{
+    io::AsyncReadExt,
+    net::TcpStream,
+    sync::{
+        mpsc::{UnboundedReceiver, UnboundedSender, unbounded_channel},
+        oneshot,
+    },
+};
+
+use crate::{RECEIVE_BUFFER_SIZE, common::timestamp_secs, ipc::kodama::CustomIpcSegment};
+
+use super::{
+    CompressionType, ConnectionType, PacketBuffer, PacketSegment, PacketState, SegmentData,
+    SegmentType, authenticate_custom_ipc, parse_packet, send_packet,
+};
+
+/// How long to wait for a connection to the world server.
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How long to wait for the world server to respond to a request.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How many times a request is attempted before giving up.
+const MAX_ATTEMPTS: u32 = 3;
+
+/// How long to wait before the second attempt, this is doubled for every attempt after that.
+const RETRY_DELAY: Duration = Duration::from_millis(500);
+
+/// What happened to a request, sent back to `request()` by the connection loop.
+/// If the sender is dropped instead, the request was written but the connection was lost before the response arrived.
+#[derive(Debug)]
+enum RequestOutcome {
+    Response(CustomIpcSegment),
+    /// The request was never written, so it's safe to send it again.
+    NotSent,
+}
+
+/// Requests waiting for a response, keyed by request id.
+type PendingRequests = Arc<Mutex<HashMap<u32, oneshot::Sender<RequestOutcome>>>>;
+
+/// A single live connection to a world server.
+#[derive(Debug)]
+struct ChannelConnection {
+    /// Segments to write to the world server.
+    sender: UnboundedSender<CustomIpcSegment>,
+    pending: PendingRequests,
+}
+
+/// A long-lived connection to a world server, meant for private server-to-server communication.
+/// Many requests can be in flight at once, and each response is matched to it's request by the request id.
+#[derive(Debug)]
+pub struct WorldChannel {
+    addr: SocketAddr,
+    next_request_id: AtomicU32,
+    connection: tokio::sync::Mutex<Option<ChannelConnection>>,
+}
+
+impl WorldChannel {
+    /// Creates a channel to the world server at `addr`. The connection is made when the first request is sent.
+    pub fn new(addr: SocketAddr) -> Self {
+        Self {
+            addr,
+            next_request_id: AtomicU32::new(1),
+            connection: tokio::sync::Mutex::new(None),
+        }
+    }
+
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// Returns the sender & pending requests of the current connection, reconnecting if needed.
+    async fn get_connection(&self) -> Option<(UnboundedSender<CustomIpcSegment>, PendingRequests)> {
+        let mut connection = self.connection.lock().await;
+
+        if let Some(connection) = connection.as_ref()
+            && !connection.sender.is_closed()
+        {
+            return Some((connection.sender.clone(), connection.pending.clone()));
+        }
+
+        let mut stream =
+            match tokio::time::timeout(CONNECT_TIMEOUT, TcpStream::connect(self.addr)).await {
+                Ok(Ok(stream)) => stream,
+                _ => {
+                    tracing::warn!(
+                        "Failed to connect to the world server at {}, is it running?",
+                        self.addr
+                    );
+                    return None;
+                }
+            };
+
+        if !authenticate_custom_ipc(&mut stream, &mut PacketState { client_key: None }).await {
+            tracing::warn!(
+                "The world server at {} refused to authenticate us, is the shared secret the same?",
+                self.addr
+            );
+            return None;
+        }
+
+        let (sender, receiver) = unbounded_channel();
+        let pending = PendingRequests::default();
+
+        tokio::spawn(connection_loop(stream, receiver, pending.clone()));
+
+        *connection = Some(ChannelConnection {
+            sender: sender.clone(),
+            pending: pending.clone(),
+        });
+
+        Some((sender, pending))
+    }
+
+    /// Sends `segment` to the world server and waits for the response.
+    /// If the request couldn't be written, the connection is re-established and it's sent again.
+    /// Requests that were written are never retried (even if the connection was lost or it timed out) as the world server may have already acted on them.
+    pub async fn request(&self, mut segment: CustomIpcSegment) -> Option<CustomIpcSegment> {
+        for attempt in 1..=MAX_ATTEMPTS {
+            if attempt > 1 {
+                tokio::time::sleep(RETRY_DELAY * 2u32.pow(attempt - 2)).await;
+            }
+
+            let Some((sender, pending)) = self.get_connection().await else {
+                continue;
+            };
+
+            let request_id = self.next_request_id.fetch_add(1, Ordering::Relaxed);
+            segment.request_id = request_id;
+            segment.timestamp = timestamp_secs();
+
+            let (response_sender, response_receiver) = oneshot::channel();
+            pending.lock().unwrap().insert(request_id, response_sender);
+
+            if sender.send(segment.clone()).is_err() {
+                pending.lock().unwrap().remove(&request_id);
+                continue;
+            }
+
+            match tokio::time::timeout(REQUEST_TIMEOUT, response_receiver).await {
+                Ok(Ok(RequestOutcome::Response(response))) => return Some(response),
+                Ok(Ok(RequestOutcome::NotSent)) => {
+                    tracing::warn!(
+                        "Lost connection to the world server at {} before sending {:?} (attempt {attempt} of {MAX_ATTEMPTS})",
+                        self.addr,
+                        segment.op_code
+                    );
+                }
+                Ok(Err(_)) => {
+                    tracing::warn!(
+                        "Lost connection to the world server at {} while waiting for a response to {:?}, it may or may not have been handled!",
+                        self.addr,
+                        segment.op_code
+                    );
+                    return None;
+                }
+                Err(_) => {
+                    pending.lock().unwrap().remove(&request_id);
+                    tracing::warn!(
+                        "The world server at {} didn't respond to {:?} in time!",
+                        self.addr,
+                        segment.op_code
+                    );
+                    return None;
+                }
+            }
+        }
+
+        tracing::warn!(
+            "Giving up on sending {:?} to the world server at {}",
+            segment.op_code,
+            self.addr
+        );
+
+        None
+    }
+}
+
+/// Writes outgoing requests and hands out responses as they arrive, until the connection is closed.
+/// Once this returns, requests that were never written are told so they can be sent again, and the rest are dropped.
+async fn connection_loop(
+    mut stream: TcpStream,
+    mut receiver: UnboundedReceiver<CustomIpcSegment>,
+    pending: PendingRequests,
+) {
+    let mut state = PacketState { client_key: None };
+
+    let mut received = PacketBuffer::default();
+    let mut buf = vec![0; RECEIVE_BUFFER_SIZE];
+
+    'connection: loop {
+        tokio::select! {
+            segment = receiver.recv() => {
+                let Some(segment) = segment else {
+                    // the channel was dropped
+                    break 'connection;
+                };
+
+                let request_id = segment.request_id;
+                let sent = send_packet::<CustomIpcSegment>(
+                    &mut stream,
+                    &mut state,
+                    ConnectionType::None,
+                    CompressionType::Uncompressed,
+                    &[PacketSegment {
+                        segment_type: SegmentType::KodamaIpc,
+                        data: SegmentData::KodamaIpc { data: segment },
+                        ..Default::default()
+                    }],
+                )
+                .await;
+
+                if !sent {
+                    if let Some(response_sender) = pending.lock().unwrap().remove(&request_id) {
+                        let _ = response_sender.send(RequestOutcome::NotSent);
+                    }
+                    break 'connection;
+                }
+            }
+            n = stream.read(&mut buf) => {
+                let n = match n {
+                    Ok(n) if n > 0 => n,
+                    _ => break 'connection,
+                };
+
+                received.push(&buf[..n]);
+
+                loop {
+                    let packet = match received.next_packet() {
+                        Ok(Some(packet)) => packet,
+                        Ok(None) => break,
+                        Err(_) => {
+                            tracing::warn!("Got a packet with an invalid size from the world server, dropping the connection!");
+                            break 'connection;
+                        }
+                    };
+
+                    let (segments, _) = parse_packet::<CustomIpcSegment>(&packet, &mut state);
+                    for segment in segments {
+                        let SegmentData::KodamaIpc { data } = segment.data else {
+                            continue;
+                        };
+
+                        match pending.lock().unwrap().remove(&data.request_id) {
+                            Some(response_sender) => {
+                                let _ = response_sender.send(RequestOutcome::Response(data));
+                            }
+                            None => tracing::warn!(
+                                "Got a response for unknown request {} from the world server, did it time out?",
+                                data.request_id
+                            ),
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    // stop accepting new requests first, so none of them get lost after this
+    receiver.close();
+
+    let mut pending = pending.lock().unwrap();
+    while let Ok(segment) = receiver.try_recv() {
+        if let Some(response_sender) = pending.remove(&segment.request_id) {
+            let _ = response_sender.send(RequestOutcome::NotSent);
+        }
+    }
+    pending.clear();
+}
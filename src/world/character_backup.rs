@@ -0,0 +1,291 @@
+use std::{
+    ffi::OsStr,
+    fmt,
+    path::{Path, PathBuf},
+};
+
+use base64::{Engine as _, engine::general_purpose::STANDARD};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    common::{CharaInfo, Position},
+    config::get_config,
+    ipc::{kodama::CHARACTER_BACKUP_MAX_LENGTH, lobby::CharacterFlag},
+};
+
+use super::WorldDatabase;
+
+/// A portable copy of a character, used to move characters between servers or restore them later.
+/// This is stored as JSON, new data should be added as `#[serde(default)]` fields so older backups can still be read.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CharacterBackup {
+    /// Version of the backup format, see `CharacterBackup::VERSION`.
+    pub version: u32,
+    pub name: String,
+    pub chara_info: CharaInfo,
+    pub city_state: u8,
+    pub zone_id: u16,
+    pub classjob_id: i32,
+    pub position: Position,
+    pub rotation: f32,
+    /// Items the character owns. The world server doesn't store these yet, so this is currently always empty.
+    #[serde(default)]
+    pub inventory: Vec<BackupItem>,
+    /// Class levels & experience. The world server doesn't store these yet, so this is currently always empty.
+    #[serde(default)]
+    pub class_levels: Vec<BackupClassLevel>,
+    /// Client settings uploaded through the lobby. These are only in backup files, as they are too large for custom IPC and are copied separately when moving a character.
+    #[serde(default)]
+    pub settings: Vec<BackupSettings>,
+}
+
+impl CharacterBackup {
+    /// The newest version of the backup format we can read.
+    pub const VERSION: u32 = 1;
+}
+
+/// An item in a `CharacterBackup`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BackupItem {
+    pub container: u16,
+    pub slot: u16,
+    pub item_id: u32,
+    pub quantity: u32,
+}
+
+/// A class level in a `CharacterBackup`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BackupClassLevel {
+    pub classjob_id: u8,
+    pub level: u16,
+    pub exp: u32,
+}
+
+/// Client settings in a `CharacterBackup`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BackupSettings {
+    pub kind: u8,
+    /// Base64 encoded settings, as uploaded by the client.
+    pub data: String,
+}
+
+/// Reasons importing or exporting a character can fail.
+#[derive(Debug)]
+pub enum BackupError {
+    Io(std::io::Error),
+    Json(serde_json::Error),
+    Database(rusqlite::Error),
+    /// The backup was made by a newer version of Kodama.
+    UnsupportedVersion(u32),
+    /// Another character already has the name in the backup.
+    NameTaken(String),
+    CharacterNotFound,
+    /// The service account already has as many characters as this world allows.
+    TooManyCharacters,
+    /// The character is already being moved to another world.
+    AlreadyTransferring,
+    /// The character has retainers, which aren't part of `CharacterBackup` and would be left behind.
+    HasRetainers,
+    /// The backup doesn't fit in custom IPC, see `CHARACTER_BACKUP_MAX_LENGTH`.
+    TooLarge(usize),
+    /// The backup file name isn't a plain file name inside the backups directory.
+    InvalidPath,
+}
+
+impl fmt::Display for BackupError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BackupError::Io(err) => write!(f, "failed to access the backup file: {err}"),
+            BackupError::Json(err) => write!(f, "the backup is invalid: {err}"),
+            BackupError::Database(err) => write!(f, "failed to access the world database: {err}"),
+            BackupError::UnsupportedVersion(version) => {
+                write!(f, "backup version {version} is not supported")
+            }
+            BackupError::NameTaken(name) => write!(f, "the name {name} is already taken"),
+            BackupError::CharacterNotFound => write!(f, "the character doesn't exist"),
+            BackupError::TooManyCharacters => {
+                write!(f, "the service account has too many characters")
+            }
+            BackupError::AlreadyTransferring => {
+                write!(f, "the character is already being moved to another world")
+            }
+            BackupError::HasRetainers => {
+                write!(f, "the character has retainers, which can't be moved yet")
+            }
+            BackupError::TooLarge(length) => {
+                write!(f, "the backup is too large to send ({length} bytes)")
+            }
+            BackupError::InvalidPath => {
+                write!(
+                    f,
+                    "backups can only be read from or written to the backups directory"
+                )
+            }
+        }
+    }
+}
+
+/// Parses a backup from JSON, checking if it's a version we understand.
+pub fn parse_character_backup(json: &str) -> Result<CharacterBackup, BackupError> {
+    let backup: CharacterBackup = serde_json::from_str(json).map_err(BackupError::Json)?;
+    if backup.version > CharacterBackup::VERSION {
+        return Err(BackupError::UnsupportedVersion(backup.version));
+    }
+
+    Ok(backup)
+}
+
+/// Returns where the backup file called `name` is, which is always inside `WorldConfig::backups_location`.
+fn backup_path(name: &str) -> Result<PathBuf, BackupError> {
+    // only plain file names are allowed, e.g. no separators or ".."
+    let file_name = Path::new(name)
+        .file_name()
+        .filter(|file_name| *file_name == OsStr::new(name))
+        .ok_or(BackupError::InvalidPath)?;
+
+    let directory = PathBuf::from(get_config().world.backups_location);
+    std::fs::create_dir_all(&directory).map_err(BackupError::Io)?;
+    let directory = directory.canonicalize().map_err(BackupError::Io)?;
+
+    let path = directory.join(file_name);
+    match path.canonicalize() {
+        // the file may be a link to somewhere else
+        Ok(resolved) if !resolved.starts_with(&directory) => Err(BackupError::InvalidPath),
+        Ok(resolved) => Ok(resolved),
+        // it doesn't exist yet
+        Err(_) => Ok(path),
+    }
+}
+
+/// Imports the backup file called `name` in the backups directory, and returns the new (content_id, actor_id).
+pub fn import_character_from_file(
+    database: &WorldDatabase,
+    service_account_id: u32,
+    name: &str,
+) -> Result<(u64, u32), BackupError> {
+    let json = std::fs::read_to_string(backup_path(name)?).map_err(BackupError::Io)?;
+    import_character_from_json(database, service_account_id, &json)
+}
+
+/// Creates a new character for `service_account_id` from the backup in `json`, and returns the new (content_id, actor_id).
+pub fn import_character_from_json(
+    database: &WorldDatabase,
+    service_account_id: u32,
+    json: &str,
+) -> Result<(u64, u32), BackupError> {
+    let backup = parse_character_backup(json)?;
+
+    if database.count_characters(service_account_id) >= get_config().world.max_characters {
+        return Err(BackupError::TooManyCharacters);
+    }
+
+    let (content_id, actor_id) = database.import_character(service_account_id, &backup)?;
+
+    for settings in &backup.settings {
+        let stored = STANDARD
+            .decode(&settings.data)
+            .is_ok_and(|data| database.store_settings_chunk(content_id, settings.kind, 0, &data));
+        if !stored {
+            tracing::warn!(
+                "Failed to import settings {} for {content_id}!",
+                settings.kind
+            );
+        }
+    }
+
+    Ok((content_id, actor_id))
+}
+
+/// Exports the character with `content_id` to a backup file called `name` in the backups directory.
+pub fn export_character_to_file(
+    database: &WorldDatabase,
+    content_id: u64,
+    name: &str,
+) -> Result<(), BackupError> {
+    let path = backup_path(name)?;
+
+    let mut backup = database
+        .export_character(content_id)
+        .ok_or(BackupError::CharacterNotFound)?;
+    backup.settings = database
+        .get_character_settings_by_content_id(content_id)
+        .into_iter()
+        .map(|settings| BackupSettings {
+            kind: settings.kind,
+            data: STANDARD.encode(settings.data),
+        })
+        .collect();
+
+    let json = serde_json::to_string_pretty(&backup).map_err(BackupError::Json)?;
+    std::fs::write(path, json).map_err(BackupError::Io)
+}
+
+/// Exports the character with `content_id` as JSON, small enough to be sent over custom IPC.
+pub fn export_character_to_json(
+    database: &WorldDatabase,
+    content_id: u64,
+) -> Result<String, BackupError> {
+    let backup = database
+        .export_character(content_id)
+        .ok_or(BackupError::CharacterNotFound)?;
+
+    let json = serde_json::to_string(&backup).map_err(BackupError::Json)?;
+    if json.len() >= CHARACTER_BACKUP_MAX_LENGTH {
+        return Err(BackupError::TooLarge(json.len()));
+    }
+
+    Ok(json)
+}
+
+/// Locks the character with `content_id` so it can be moved to another world, and returns it's backup as JSON.
+/// The character is marked as traveling until the transfer is cancelled, or it's deleted once moved.
+pub fn begin_character_transfer(
+    database: &WorldDatabase,
+    content_id: u64,
+) -> Result<String, BackupError> {
+    let flags = database
+        .get_character_flags(content_id)
+        .ok_or(BackupError::CharacterNotFound)?;
+    if flags.contains(CharacterFlag::DC_TRAVELING) {
+        return Err(BackupError::AlreadyTransferring);
+    }
+    // TODO: carry retainers in the backup, so they can come along
+    if database.count_retainers(content_id) > 0 {
+        return Err(BackupError::HasRetainers);
+    }
+
+    let json = export_character_to_json(database, content_id)?;
+
+    database.add_character_flags(
+        content_id,
+        CharacterFlag::DC_TRAVELING | CharacterFlag::DC_TRAVELING_MESSAGE,
+    );
+
+    Ok(json)
+}
+
+/// Unlocks a character after a transfer failed, see `begin_character_transfer`.
+pub fn cancel_character_transfer(database: &WorldDatabase, content_id: u64) -> bool {
+    database.remove_character_flags(
+        content_id,
+        CharacterFlag::DC_TRAVELING | CharacterFlag::DC_TRAVELING_MESSAGE,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn backup_paths_stay_in_directory() {
+        for name in [
+            "",
+            "..",
+            "../backup.json",
+            "/etc/passwd",
+            "backups/backup.json",
+        ] {
+            assert!(matches!(backup_path(name), Err(BackupError::InvalidPath)));
+        }
+    }
+}
@@ -1,5 +1,6 @@
 use std::{
-    net::SocketAddr,
+    collections::HashMap,
+    net::{IpAddr, SocketAddr},
     sync::{Arc, Mutex},
     time::Instant,
 };
@@ -7,15 +8,16 @@ use std::{
 use tokio::net::TcpStream;
 
 use crate::{
-    common::timestamp_secs,
-    config::WorldConfig,
+    common::{EntryToken, GameData, PRIVATE_API_HEADER, private_api_token, timestamp_secs},
+    config::{WorldConfig, get_config},
     ipc::{
         chat::ServerChatIpcSegment,
         zone::{ClientZoneIpcSegment, ServerZoneIpcSegment},
     },
+    login::AccountRole,
     packet::{
-        CompressionType, ConnectionType, PacketSegment, PacketState, SegmentData, SegmentType,
-        parse_packet, send_packet,
+        CompressionType, ConnectionType, CustomIpcAuth, PacketBuffer, PacketSegment, PacketState,
+        SegmentData, SegmentType, parse_packet, send_packet,
     },
 };
 
@@ -24,12 +26,23 @@ use super::{
     common::{ClientId, ServerHandle},
 };
 
+/// Permission from the lobby server for a character to enter this world, see `ZoneConnection::check_entry_token()`.
+#[derive(Debug, Clone)]
+pub struct EntryGrant {
+    /// The token the lobby server gave us, it was already checked against the shared secret.
+    pub token: EntryToken,
+    /// The address the client is expected to connect from.
+    pub client_ip: IpAddr,
+}
+
 /// Represents a single connection between an instance of the client and the world server.
 pub struct ZoneConnection {
     pub config: WorldConfig,
     pub socket: TcpStream,
 
     pub state: PacketState,
+    /// Data we received, but that isn't a whole packet yet.
+    pub received: PacketBuffer,
 
     pub ip: SocketAddr,
     pub id: ClientId,
@@ -37,18 +50,38 @@ pub struct ZoneConnection {
 
     pub database: Arc<WorldDatabase>,
     pub lua: Arc<Mutex<mlua::Lua>>,
+    pub gamedata: Arc<Mutex<GameData>>,
     pub last_keep_alive: Instant,
 
     /// Whether the player was gracefully logged out
     pub gracefully_logged_out: bool,
+
+    /// Whether the other side is a server that proved it knows the shared secret, needed for custom IPC.
+    pub custom_ipc_auth: CustomIpcAuth,
+
+    /// Entry tokens handed to us by the lobby server, keyed by actor id. Shared between all connections.
+    pub entry_tokens: Arc<Mutex<HashMap<u32, EntryGrant>>>,
+
+    /// Role of the account the player logged in with, GM commands should check this.
+    pub account_role: AccountRole,
 }
 
 impl ZoneConnection {
+    /// Parses every packet that fully arrived, including `data` which was just read.
+    /// Returns `None` if the data is garbage, and the connection should be dropped.
     pub fn parse_packet(
         &mut self,
         data: &[u8],
-    ) -> (Vec<PacketSegment<ClientZoneIpcSegment>>, ConnectionType) {
-        parse_packet(data, &mut self.state)
+    ) -> Option<Vec<PacketSegment<ClientZoneIpcSegment>>> {
+        self.received.push(data);
+
+        let mut segments = Vec::new();
+        while let Some(packet) = self.received.next_packet().ok()? {
+            let (mut packet_segments, _) = parse_packet(&packet, &mut self.state);
+            segments.append(&mut packet_segments);
+        }
+
+        Some(segments)
     }
 
     pub async fn send_segment(&mut self, segment: PacketSegment<ServerZoneIpcSegment>) {
@@ -73,9 +106,28 @@ impl ZoneConnection {
         .await;
     }
 
+    /// Checks if the lobby server allowed `actor_id` to enter this world from this connection's address.
+    /// The grant is used up, even if the check fails.
+    ///
+    /// This is only an allowlist of (actor id, client address) pairs the lobby server sent us, the client itself doesn't prove anything.
+    /// It's given the token in GameLoginReply, but doesn't seem to send it back to the world. If it does, compare it here too.
+    pub fn check_entry_token(&self, actor_id: u32) -> bool {
+        take_entry_grant(&self.entry_tokens, actor_id, self.ip.ip())
+    }
+
     pub async fn initialize(&mut self, actor_id: u32) {
         tracing::info!("Client {actor_id} is initializing zone session...");
 
+        if let Some(service_account_id) = self.database.find_service_account_id(actor_id) {
+            self.account_role = Self::fetch_account_role(service_account_id)
+                .await
+                .unwrap_or_default();
+            tracing::info!("{actor_id} is logged in as {:?}", self.account_role);
+        }
+
+        // TODO: settings uploaded through the lobby are only stored for now, they should be sent back to the client here.
+        // There's no zone IPC yet (ServerZoneIpcType in opcodes.json is empty), and it's not known which packet retail uses for them.
+
         // We have send THEM a keep alive
         {
             self.send_segment(PacketSegment {
@@ -89,4 +141,103 @@ impl ZoneConnection {
             .await;
         }
     }
+
+    /// Asks the login server for the role of the user owning `service_account_id`.
+    pub async fn fetch_account_role(service_account_id: u32) -> Option<AccountRole> {
+        let config = get_config();
+
+        let Ok(reply) = reqwest::Client::new()
+            .get(format!(
+                "http://{}/_private/role?service_account_id={}",
+                config.login.server_name, service_account_id
+            ))
+            .header(PRIVATE_API_HEADER, private_api_token(&config.shared_secret))
+            .send()
+            .await
+        else {
+            tracing::warn!("Failed to contact login server, is it running?");
+            return None;
+        };
+
+        let body = reply.text().await.ok()?;
+        serde_json::from_str::<Option<AccountRole>>(&body)
+            .ok()
+            .flatten()
+    }
+}
+
+/// Removes the grant for `actor_id` from `entry_tokens`, and returns whether it allowed a connection from `ip`.
+fn take_entry_grant(
+    entry_tokens: &Mutex<HashMap<u32, EntryGrant>>,
+    actor_id: u32,
+    ip: IpAddr,
+) -> bool {
+    let grant = {
+        let mut entry_tokens = entry_tokens.lock().unwrap();
+        entry_tokens.retain(|_, grant| !grant.token.is_expired());
+
+        entry_tokens.remove(&actor_id)
+    };
+
+    let Some(grant) = grant else {
+        return false;
+    };
+
+    if grant.client_ip != ip.to_canonical() {
+        tracing::warn!(
+            "Actor {actor_id} was let in from {}, but is connecting from {ip}!",
+            grant.client_ip,
+        );
+        return false;
+    }
+
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+
+    use super::*;
+
+    fn grants(actor_id: u32, client_ip: IpAddr) -> Mutex<HashMap<u32, EntryGrant>> {
+        Mutex::new(HashMap::from([(
+            actor_id,
+            EntryGrant {
+                token: EntryToken::new(1, 2, actor_id, 60),
+                client_ip,
+            },
+        )]))
+    }
+
+    #[test]
+    fn entry_without_grant() {
+        let entry_tokens = grants(3, Ipv4Addr::LOCALHOST.into());
+        assert!(!take_entry_grant(
+            &entry_tokens,
+            4,
+            Ipv4Addr::LOCALHOST.into()
+        ));
+    }
+
+    #[test]
+    fn entry_from_other_address() {
+        let entry_tokens = grants(3, Ipv4Addr::LOCALHOST.into());
+        assert!(!take_entry_grant(
+            &entry_tokens,
+            3,
+            Ipv4Addr::new(10, 0, 0, 1).into()
+        ));
+        // the grant is gone, even though it wasn't used
+        assert!(entry_tokens.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn entry_grant_used_once() {
+        let entry_tokens = grants(3, Ipv4Addr::LOCALHOST.into());
+        // the world sees IPv4 clients as mapped addresses when listening on IPv6
+        let ip: IpAddr = Ipv4Addr::LOCALHOST.to_ipv6_mapped().into();
+        assert!(take_entry_grant(&entry_tokens, 3, ip));
+        assert!(!take_entry_grant(&entry_tokens, 3, ip));
+    }
 }
@@ -1,19 +1,43 @@
-use std::io::Cursor;
+use std::{io::Cursor, net::Ipv6Addr};
 
 use crate::{
-    common::CharaInfo,
+    common::{CharaInfo, EntryToken},
     config::get_config,
-    ipc::kodama::{CustomIpcData, CustomIpcSegment, CustomIpcType},
+    ipc::{
+        kodama::{
+            CHARACTER_LIST_PAGE_SIZE, CustomIpcData, CustomIpcSegment, CustomIpcType,
+            RETAINER_LIST_PAGE_SIZE, SETTINGS_CHUNK_SIZE,
+        },
+        lobby::CharacterFlag,
+    },
     packet::{
-        CompressionType, ConnectionType, PacketSegment, SegmentData, SegmentType, send_packet,
+        CompressionType, ConnectionType, PacketSegment, SegmentData, SegmentType, send_custom_ipc,
+        send_packet,
     },
 };
 use base64::{Engine as _, engine::general_purpose::URL_SAFE};
 use binrw::BinRead;
 
-use super::ZoneConnection;
+use super::{
+    EntryGrant, ZoneConnection, begin_character_transfer, cancel_character_transfer,
+    export_character_to_json, import_character_from_json,
+};
 
 pub async fn handle_custom_ipc(connection: &mut ZoneConnection, data: &CustomIpcSegment) {
+    if let Some(response) = connection.custom_ipc_auth.handle_handshake(data) {
+        send_custom_ipc(&mut connection.socket, &mut connection.state, response).await;
+        return;
+    }
+
+    if !connection.custom_ipc_auth.is_authenticated() {
+        tracing::warn!(
+            "Rejecting unauthenticated custom IPC {:?} from {}!",
+            data.op_code,
+            connection.ip
+        );
+        return;
+    }
+
     match &data.data {
         CustomIpcData::RequestCreateCharacter {
             service_account_id,
@@ -28,15 +52,26 @@ pub async fn handle_custom_ipc(connection: &mut ZoneConnection, data: &CustomIpc
 
             dbg!(&chara_info);
 
-            let (content_id, actor_id) = connection.database.create_player_data(
-                *service_account_id,
-                &name,
-                &serde_json::to_string(&chara_info).unwrap(),
-                chara_info.initial_town,
-                0,
-            );
+            // a content id of 0 tells the lobby we refused
+            let (content_id, actor_id) =
+                if connection.database.count_characters(*service_account_id)
+                    >= get_config().world.max_characters
+                {
+                    tracing::warn!("{service_account_id} has too many characters on this world!");
+                    (0, 0)
+                } else {
+                    let (content_id, actor_id) = connection.database.create_player_data(
+                        *service_account_id,
+                        &name,
+                        &serde_json::to_string(&chara_info).unwrap(),
+                        chara_info.initial_town,
+                        0,
+                    );
+
+                    tracing::info!("Created new player: {content_id} {actor_id}");
 
-            tracing::info!("Created new player: {content_id} {actor_id}");
+                    (content_id, actor_id)
+                };
 
             // send them the new actor and content id
             {
@@ -50,6 +85,7 @@ pub async fn handle_custom_ipc(connection: &mut ZoneConnection, data: &CustomIpc
                                     actor_id,
                                     content_id,
                                 },
+                                request_id: data.request_id,
                                 ..Default::default()
                             },
                         },
@@ -72,6 +108,7 @@ pub async fn handle_custom_ipc(connection: &mut ZoneConnection, data: &CustomIpc
                             data: CustomIpcSegment {
                                 op_code: CustomIpcType::ActorIdFound,
                                 data: CustomIpcData::ActorIdFound { actor_id },
+                                request_id: data.request_id,
                                 ..Default::default()
                             },
                         },
@@ -92,6 +129,7 @@ pub async fn handle_custom_ipc(connection: &mut ZoneConnection, data: &CustomIpc
                             data: CustomIpcSegment {
                                 op_code: CustomIpcType::NameIsAvailableResponse,
                                 data: CustomIpcData::NameIsAvailableResponse { free: is_name_free },
+                                request_id: data.request_id,
                                 ..Default::default()
                             },
                         },
@@ -100,15 +138,31 @@ pub async fn handle_custom_ipc(connection: &mut ZoneConnection, data: &CustomIpc
                     .await;
             }
         }
-        CustomIpcData::RequestCharacterList { service_account_id } => {
+        CustomIpcData::RequestCharacterList {
+            service_account_id,
+            offset,
+        } => {
             let config = get_config();
 
-            let world_name = "Gilgamesh"; // TODO: hardcoded
-            let characters = connection.database.get_character_list(
-                *service_account_id,
-                config.world.world_id,
-                &world_name,
-            );
+            let characters = {
+                let mut game_data = connection.gamedata.lock().unwrap();
+                let world_name = game_data.get_world_name(config.world.world_id);
+
+                connection.database.get_character_list(
+                    *service_account_id,
+                    config.world.world_id,
+                    &world_name,
+                    &mut game_data,
+                )
+            };
+
+            // only send one page at a time, the lobby asks for the rest
+            let total = characters.len() as u32;
+            let characters: Vec<_> = characters
+                .into_iter()
+                .skip(*offset as usize)
+                .take(CHARACTER_LIST_PAGE_SIZE)
+                .collect();
 
             // send response
             {
@@ -122,7 +176,11 @@ pub async fn handle_custom_ipc(connection: &mut ZoneConnection, data: &CustomIpc
                         data: SegmentData::KodamaIpc {
                             data: CustomIpcSegment {
                                 op_code: CustomIpcType::RequestCharacterListRepsonse,
-                                data: CustomIpcData::RequestCharacterListRepsonse { characters },
+                                data: CustomIpcData::RequestCharacterListRepsonse {
+                                    total,
+                                    characters,
+                                },
+                                request_id: data.request_id,
                                 ..Default::default()
                             },
                         },
@@ -148,6 +206,7 @@ pub async fn handle_custom_ipc(connection: &mut ZoneConnection, data: &CustomIpc
                             data: CustomIpcSegment {
                                 op_code: CustomIpcType::CharacterDeleted,
                                 data: CustomIpcData::CharacterDeleted { deleted: 1 },
+                                request_id: data.request_id,
                                 ..Default::default()
                             },
                         },
@@ -157,6 +216,415 @@ pub async fn handle_custom_ipc(connection: &mut ZoneConnection, data: &CustomIpc
                 .await;
             }
         }
+        CustomIpcData::ImportCharacter {
+            service_account_id,
+            backup,
+        } => {
+            let content_id =
+                match import_character_from_json(&connection.database, *service_account_id, backup)
+                {
+                    Ok((content_id, actor_id)) => {
+                        tracing::info!("Imported a character as {content_id} {actor_id}");
+                        content_id
+                    }
+                    Err(err) => {
+                        tracing::warn!("Failed to import a character: {err}");
+                        0
+                    }
+                };
+
+            send_custom_ipc(
+                &mut connection.socket,
+                &mut connection.state,
+                CustomIpcSegment {
+                    op_code: CustomIpcType::CharacterImported,
+                    data: CustomIpcData::CharacterImported { content_id },
+                    request_id: data.request_id,
+                    ..Default::default()
+                },
+            )
+            .await;
+        }
+        CustomIpcData::ExportCharacter { content_id } => {
+            let backup = match export_character_to_json(&connection.database, *content_id) {
+                Ok(backup) => {
+                    tracing::info!("Exported {content_id}");
+                    backup
+                }
+                Err(err) => {
+                    tracing::warn!("Failed to export {content_id}: {err}");
+                    String::default()
+                }
+            };
+
+            send_custom_ipc(
+                &mut connection.socket,
+                &mut connection.state,
+                CustomIpcSegment {
+                    op_code: CustomIpcType::CharacterExported,
+                    data: CustomIpcData::CharacterExported { backup },
+                    request_id: data.request_id,
+                    ..Default::default()
+                },
+            )
+            .await;
+        }
+        CustomIpcData::RemakeCharacter {
+            content_id,
+            encoded,
+        } => {
+            let remade = URL_SAFE
+                .decode(encoded)
+                .ok()
+                .and_then(|data| CharaInfo::read_le(&mut Cursor::new(data)).ok())
+                .is_some_and(|chara_info| {
+                    connection
+                        .database
+                        .remake_character(*content_id, &serde_json::to_string(&chara_info).unwrap())
+                });
+
+            tracing::info!("Remaking {content_id}, success? {remade}");
+
+            send_custom_ipc(
+                &mut connection.socket,
+                &mut connection.state,
+                CustomIpcSegment {
+                    op_code: CustomIpcType::CharacterRemade,
+                    data: CustomIpcData::CharacterRemade {
+                        content_id: if remade { *content_id } else { 0 },
+                    },
+                    request_id: data.request_id,
+                    ..Default::default()
+                },
+            )
+            .await;
+        }
+        CustomIpcData::RenameCharacter { content_id, name } => {
+            // players can only pick a new name when a GM asked them to
+            let name_change_required = connection
+                .database
+                .get_character_flags(*content_id)
+                .is_some_and(|flags| flags.contains(CharacterFlag::NAME_CHANGE_REQUIRED));
+
+            let not_allowed = !name_change_required;
+            let name_taken = name_change_required && !connection.database.check_is_name_free(name);
+
+            let renamed = if not_allowed {
+                tracing::warn!("{content_id} tried to rename themselves without being asked to!");
+                false
+            } else {
+                !name_taken && connection.database.rename_character(*content_id, name)
+            };
+
+            tracing::info!("Renaming {content_id} to {name}, success? {renamed}");
+
+            send_custom_ipc(
+                &mut connection.socket,
+                &mut connection.state,
+                CustomIpcSegment {
+                    op_code: CustomIpcType::CharacterRenamed,
+                    data: CustomIpcData::CharacterRenamed {
+                        renamed,
+                        name_taken,
+                        not_allowed,
+                    },
+                    request_id: data.request_id,
+                    ..Default::default()
+                },
+            )
+            .await;
+        }
+        CustomIpcData::GrantWorldEntry {
+            token: encoded,
+            client_ip,
+        } => {
+            let config = get_config();
+
+            let accepted = match EntryToken::decode(&config.shared_secret, encoded) {
+                Some(token)
+                    if connection
+                        .database
+                        .get_character_flags(token.content_id)
+                        .is_some_and(|flags| flags.contains(CharacterFlag::DC_TRAVELING)) =>
+                {
+                    tracing::warn!(
+                        "{} is traveling to another world, they can't log in here!",
+                        token.content_id
+                    );
+                    false
+                }
+                Some(token) if !token.is_expired() => {
+                    tracing::info!(
+                        "The lobby server let {} in as actor {}",
+                        token.content_id,
+                        token.actor_id
+                    );
+
+                    connection.entry_tokens.lock().unwrap().insert(
+                        token.actor_id,
+                        EntryGrant {
+                            token,
+                            client_ip: Ipv6Addr::from(*client_ip).to_canonical(),
+                        },
+                    );
+
+                    true
+                }
+                _ => {
+                    tracing::warn!("The lobby server gave us an invalid or expired entry token!");
+                    false
+                }
+            };
+
+            send_custom_ipc(
+                &mut connection.socket,
+                &mut connection.state,
+                CustomIpcSegment {
+                    op_code: CustomIpcType::WorldEntryGranted,
+                    data: CustomIpcData::WorldEntryGranted { accepted },
+                    request_id: data.request_id,
+                    ..Default::default()
+                },
+            )
+            .await;
+        }
+        CustomIpcData::BeginTransfer { content_id } => {
+            let backup = match begin_character_transfer(&connection.database, *content_id) {
+                Ok(backup) => {
+                    tracing::info!("{content_id} is leaving for another world");
+                    backup
+                }
+                Err(err) => {
+                    tracing::warn!("Can't transfer {content_id}: {err}");
+                    String::default()
+                }
+            };
+
+            send_custom_ipc(
+                &mut connection.socket,
+                &mut connection.state,
+                CustomIpcSegment {
+                    op_code: CustomIpcType::TransferBegun,
+                    data: CustomIpcData::TransferBegun { backup },
+                    request_id: data.request_id,
+                    ..Default::default()
+                },
+            )
+            .await;
+        }
+        CustomIpcData::ReceiveTransfer {
+            service_account_id,
+            backup,
+        } => {
+            let (actor_id, content_id) =
+                match import_character_from_json(&connection.database, *service_account_id, backup)
+                {
+                    Ok((content_id, actor_id)) => {
+                        tracing::info!("A character arrived from another world as {content_id}");
+                        (actor_id, content_id)
+                    }
+                    Err(err) => {
+                        tracing::warn!("Failed to receive a character from another world: {err}");
+                        (0, 0)
+                    }
+                };
+
+            send_custom_ipc(
+                &mut connection.socket,
+                &mut connection.state,
+                CustomIpcSegment {
+                    op_code: CustomIpcType::TransferReceived,
+                    data: CustomIpcData::TransferReceived {
+                        actor_id,
+                        content_id,
+                    },
+                    request_id: data.request_id,
+                    ..Default::default()
+                },
+            )
+            .await;
+        }
+        CustomIpcData::CancelTransfer { content_id } => {
+            let cancelled = cancel_character_transfer(&connection.database, *content_id);
+
+            tracing::info!("Cancelling the transfer of {content_id}, success? {cancelled}");
+
+            send_custom_ipc(
+                &mut connection.socket,
+                &mut connection.state,
+                CustomIpcSegment {
+                    op_code: CustomIpcType::TransferCancelled,
+                    data: CustomIpcData::TransferCancelled { cancelled },
+                    request_id: data.request_id,
+                    ..Default::default()
+                },
+            )
+            .await;
+        }
+        CustomIpcData::StoreSettings {
+            content_id,
+            kind,
+            offset,
+            data: settings,
+        } => {
+            let stored =
+                connection
+                    .database
+                    .store_settings_chunk(*content_id, *kind, *offset, settings);
+            if !stored {
+                tracing::warn!(
+                    "Failed to store settings {kind} for {content_id} at offset {offset}!"
+                );
+            }
+
+            send_custom_ipc(
+                &mut connection.socket,
+                &mut connection.state,
+                CustomIpcSegment {
+                    op_code: CustomIpcType::SettingsStored,
+                    data: CustomIpcData::SettingsStored { stored },
+                    request_id: data.request_id,
+                    ..Default::default()
+                },
+            )
+            .await;
+        }
+        CustomIpcData::FetchSettings {
+            content_id,
+            index,
+            offset,
+        } => {
+            let settings = connection
+                .database
+                .get_character_settings_by_content_id(*content_id)
+                .into_iter()
+                .nth(*index as usize);
+
+            let response = match settings {
+                Some(settings) => CustomIpcData::SettingsFetched {
+                    found: true,
+                    kind: settings.kind,
+                    total: settings.data.len() as u32,
+                    data: settings
+                        .data
+                        .iter()
+                        .skip(*offset as usize)
+                        .take(SETTINGS_CHUNK_SIZE)
+                        .copied()
+                        .collect(),
+                },
+                None => CustomIpcData::SettingsFetched {
+                    found: false,
+                    kind: 0,
+                    total: 0,
+                    data: Vec::new(),
+                },
+            };
+
+            send_custom_ipc(
+                &mut connection.socket,
+                &mut connection.state,
+                CustomIpcSegment {
+                    op_code: CustomIpcType::SettingsFetched,
+                    data: response,
+                    request_id: data.request_id,
+                    ..Default::default()
+                },
+            )
+            .await;
+        }
+        CustomIpcData::RequestRetainerList {
+            service_account_id,
+            offset,
+        } => {
+            let retainers = connection.database.get_retainer_list(*service_account_id);
+            let total_slots = connection.database.count_characters(*service_account_id)
+                * get_config().world.max_retainers;
+
+            // only send one page at a time, the lobby asks for the rest
+            let total = retainers.len() as u32;
+            let retainers: Vec<_> = retainers
+                .into_iter()
+                .skip(*offset as usize)
+                .take(RETAINER_LIST_PAGE_SIZE)
+                .collect();
+
+            send_custom_ipc(
+                &mut connection.socket,
+                &mut connection.state,
+                CustomIpcSegment {
+                    op_code: CustomIpcType::RetainerListResponse,
+                    data: CustomIpcData::RetainerListResponse {
+                        total,
+                        total_slots,
+                        retainers,
+                    },
+                    request_id: data.request_id,
+                    ..Default::default()
+                },
+            )
+            .await;
+        }
+        CustomIpcData::RemakeRetainer {
+            retainer_id,
+            service_account_id,
+            name,
+        } => {
+            let owned = connection
+                .database
+                .get_retainer_service_account(*retainer_id)
+                == Some(*service_account_id);
+            let name_taken = !connection.database.check_is_retainer_name_free(name);
+
+            let remade = if !owned {
+                tracing::warn!(
+                    "{service_account_id} tried to remake retainer {retainer_id}, which they don't own!"
+                );
+                false
+            } else {
+                !name_taken && connection.database.rename_retainer(*retainer_id, name)
+            };
+
+            tracing::info!("Remaking retainer {retainer_id} as {name}, success? {remade}");
+
+            send_custom_ipc(
+                &mut connection.socket,
+                &mut connection.state,
+                CustomIpcSegment {
+                    op_code: CustomIpcType::RetainerRemade,
+                    data: CustomIpcData::RetainerRemade { remade, name_taken },
+                    request_id: data.request_id,
+                    ..Default::default()
+                },
+            )
+            .await;
+        }
+        CustomIpcData::RemoveServiceAccount { service_account_id } => {
+            let content_ids = connection.database.get_content_ids(*service_account_id);
+            for content_id in &content_ids {
+                connection.database.delete_character(*content_id);
+            }
+
+            tracing::info!(
+                "Removed service account {service_account_id}, deleting {} characters",
+                content_ids.len()
+            );
+
+            send_custom_ipc(
+                &mut connection.socket,
+                &mut connection.state,
+                CustomIpcSegment {
+                    op_code: CustomIpcType::ServiceAccountRemoved,
+                    data: CustomIpcData::ServiceAccountRemoved {
+                        deleted_characters: content_ids.len() as u32,
+                        unreachable_worlds: 0,
+                    },
+                    request_id: data.request_id,
+                    ..Default::default()
+                },
+            )
+            .await;
+        }
         _ => {
             panic!("The server is recieving a response or unknown custom IPC!")
         }
@@ -3,14 +3,25 @@ use std::sync::Mutex;
 use rusqlite::Connection;
 
 use crate::{
-    common::{CharaInfo, Position},
-    ipc::lobby::{CharacterDetails, CharacterFlag, FaceInfo, NeoClientSelectData},
+    common::{CharaInfo, GameData, Position},
+    ipc::lobby::{CharacterDetails, CharacterFlag, FaceInfo, NeoClientSelectData, RetainerInfo},
+    world::{BackupError, CharacterBackup},
 };
 
+/// Largest settings blob we store for a single character, anything bigger is refused.
+const MAX_SETTINGS_SIZE: usize = 1024 * 1024;
+
 pub struct WorldDatabase {
     connection: Mutex<Connection>,
 }
 
+/// Client settings uploaded through the lobby, such as keybinds, hotbars or the UI layout.
+pub struct CharacterSettings {
+    /// What kind of settings these are, always the same for now. See `SETTINGS_KIND` in the lobby.
+    pub kind: u8,
+    pub data: Vec<u8>,
+}
+
 pub struct CharacterData {
     pub name: String,
     pub city_state: u8,
@@ -30,10 +41,24 @@ impl WorldDatabase {
 
         // Create characters table
         {
-            let query = "CREATE TABLE IF NOT EXISTS characters (content_id INTEGER PRIMARY KEY, service_account_id INTEGER, actor_id INTEGER);";
+            let query = "CREATE TABLE IF NOT EXISTS characters (content_id INTEGER PRIMARY KEY, service_account_id INTEGER, actor_id INTEGER, flags INTEGER NOT NULL DEFAULT 0, remake_allowed INTEGER NOT NULL DEFAULT 0);";
             connection.execute(query, ()).unwrap();
         }
 
+        // Columns added after the tables were first created
+        Self::add_column(
+            &connection,
+            "characters",
+            "flags",
+            "INTEGER NOT NULL DEFAULT 0",
+        );
+        Self::add_column(
+            &connection,
+            "characters",
+            "remake_allowed",
+            "INTEGER NOT NULL DEFAULT 0",
+        );
+
         // Create characters data table
         {
             let query = "CREATE TABLE IF NOT EXISTS character_data
@@ -50,11 +75,49 @@ impl WorldDatabase {
             connection.execute(query, ()).unwrap();
         }
 
+        // Create retainers table
+        {
+            let query = "CREATE TABLE IF NOT EXISTS retainers
+                (retainer_id INTEGER PRIMARY KEY,
+                owner_content_id INTEGER,
+                slot_id INTEGER,
+                name STRING,
+                status INTEGER NOT NULL DEFAULT 0);";
+            connection.execute(query, ()).unwrap();
+        }
+
+        // Create character settings table
+        {
+            let query = "CREATE TABLE IF NOT EXISTS character_settings
+                (content_id INTEGER,
+                kind INTEGER,
+                data BLOB,
+                PRIMARY KEY (content_id, kind));";
+            connection.execute(query, ()).unwrap();
+        }
+
         Self {
             connection: Mutex::new(connection),
         }
     }
 
+    /// Adds `column` to `table` if it doesn't exist yet, for databases created before it was introduced.
+    fn add_column(connection: &Connection, table: &str, column: &str, definition: &str) {
+        let mut stmt = connection
+            .prepare(&format!(
+                "SELECT 1 FROM pragma_table_info('{table}') WHERE name = ?1"
+            ))
+            .unwrap();
+        if !stmt.exists((column,)).unwrap() {
+            connection
+                .execute(
+                    &format!("ALTER TABLE {table} ADD COLUMN {column} {definition};"),
+                    (),
+                )
+                .unwrap();
+        }
+    }
+
     pub fn find_actor_id(&self, content_id: u64) -> u32 {
         let connection = self.connection.lock().unwrap();
 
@@ -70,21 +133,22 @@ impl WorldDatabase {
         service_account_id: u32,
         world_id: u16,
         world_name: &str,
+        game_data: &mut GameData,
     ) -> Vec<CharacterDetails> {
         let connection = self.connection.lock().unwrap();
 
-        let content_actor_ids: Vec<(u32, u32)>;
+        let content_actor_ids: Vec<(u32, u32, u8)>;
 
         // find the content ids associated with the service account
         {
             let mut stmt = connection
-                .prepare(
-                    "SELECT content_id, actor_id FROM characters WHERE service_account_id = ?1",
-                )
+                .prepare("SELECT content_id, actor_id, flags FROM characters WHERE service_account_id = ?1 ORDER BY content_id")
                 .unwrap();
 
             content_actor_ids = stmt
-                .query_map((service_account_id,), |row| Ok((row.get(0)?, row.get(1)?)))
+                .query_map((service_account_id,), |row| {
+                    Ok((row.get(0)?, row.get(1)?, row.get(2)?))
+                })
                 .unwrap()
                 .map(|x| x.unwrap())
                 .collect();
@@ -92,7 +156,7 @@ impl WorldDatabase {
 
         let mut characters = Vec::new();
 
-        for (index, (content_id, actor_id)) in content_actor_ids.iter().enumerate() {
+        for (index, (content_id, actor_id, flags)) in content_actor_ids.iter().enumerate() {
             let mut stmt = connection
                 .prepare(
                     "SELECT name, chara_info, zone_id, classjob_id FROM character_data WHERE content_id = ?1",
@@ -117,12 +181,14 @@ impl WorldDatabase {
                 });
 
             if let Ok(query) = result {
+                let zone_name = game_data.get_zone_name(query.zone_id);
+
                 characters.push(CharacterDetails {
                     unk2: 0,
                     player_id: *actor_id, // TODO: not correct
                     index: index as u8,
-                    flags: CharacterFlag::NONE,
-                    zone_id: 0,
+                    flags: CharacterFlag::from_bits_truncate(*flags),
+                    zone_id: query.zone_id as u32,
                     unk1: 0,
                     character_name: query.name.clone(),
                     server_name: world_name.to_string(),
@@ -163,7 +229,7 @@ impl WorldDatabase {
                         unk7: 1,
                         tribe: query.chara_info.tribe,
                         unk8: 0xe22222aa,
-                        location1: "prv0Inn01".to_string(),
+                        location1: zone_name,
                         location2: "defaultTerritory".to_lowercase(),
                         guardian: query.chara_info.guardian,
                         birth_month: query.chara_info.birth_month,
@@ -190,6 +256,10 @@ impl WorldDatabase {
         fastrand::u32(..)
     }
 
+    fn generate_retainer_id() -> u32 {
+        fastrand::u32(..)
+    }
+
     /// Gives (content_id, actor_id)
     pub fn create_player_data(
         &self,
@@ -207,7 +277,7 @@ impl WorldDatabase {
         // insert ids
         connection
             .execute(
-                "INSERT INTO characters VALUES (?1, ?2, ?3);",
+                "INSERT INTO characters (content_id, service_account_id, actor_id) VALUES (?1, ?2, ?3);",
                 (content_id, service_account_id, actor_id),
             )
             .unwrap();
@@ -234,10 +304,483 @@ impl WorldDatabase {
         !stmt.exists((name,)).unwrap()
     }
 
+    /// Returns how many characters `service_account_id` has on this world.
+    pub fn count_characters(&self, service_account_id: u32) -> u32 {
+        let connection = self.connection.lock().unwrap();
+
+        let mut stmt = connection
+            .prepare("SELECT COUNT(*) FROM characters WHERE service_account_id = ?1")
+            .unwrap();
+
+        stmt.query_row((service_account_id,), |row| row.get(0))
+            .unwrap()
+    }
+
+    /// Returns the service account that the character with `actor_id` belongs to.
+    pub fn find_service_account_id(&self, actor_id: u32) -> Option<u32> {
+        let connection = self.connection.lock().unwrap();
+
+        let mut stmt = connection
+            .prepare("SELECT service_account_id FROM characters WHERE actor_id = ?1")
+            .unwrap();
+
+        stmt.query_row((actor_id,), |row| row.get(0)).ok()
+    }
+
+    /// Returns the content ids of every character `service_account_id` has on this world.
+    pub fn get_content_ids(&self, service_account_id: u32) -> Vec<u64> {
+        let connection = self.connection.lock().unwrap();
+
+        let mut stmt = connection
+            .prepare("SELECT content_id FROM characters WHERE service_account_id = ?1")
+            .unwrap();
+
+        stmt.query_map((service_account_id,), |row| row.get(0))
+            .unwrap()
+            .filter_map(|content_id| content_id.ok())
+            .collect()
+    }
+
+    /// Returns the content id of the character named `name`, if any.
+    pub fn find_content_id(&self, name: &str) -> Option<u64> {
+        let connection = self.connection.lock().unwrap();
+
+        let mut stmt = connection
+            .prepare("SELECT content_id FROM character_data WHERE name = ?1")
+            .unwrap();
+
+        stmt.query_row((name,), |row| row.get(0)).ok()
+    }
+
+    /// Returns the flags of a character, or `None` if it doesn't exist.
+    pub fn get_character_flags(&self, content_id: u64) -> Option<CharacterFlag> {
+        let connection = self.connection.lock().unwrap();
+
+        let mut stmt = connection
+            .prepare("SELECT flags FROM characters WHERE content_id = ?1")
+            .unwrap();
+
+        stmt.query_row((content_id,), |row| row.get(0))
+            .ok()
+            .map(CharacterFlag::from_bits_truncate)
+    }
+
+    /// Removes `flags` from a character. Returns false if the character doesn't exist.
+    pub fn remove_character_flags(&self, content_id: u64, flags: CharacterFlag) -> bool {
+        let connection = self.connection.lock().unwrap();
+
+        let mut stmt = connection
+            .prepare("UPDATE characters SET flags = flags & ~?1 WHERE content_id = ?2")
+            .unwrap();
+        stmt.execute((flags.bits(), content_id)).unwrap() != 0
+    }
+
+    /// Sets `flags` on a character, in addition to the ones it already has.
+    pub fn add_character_flags(&self, content_id: u64, flags: CharacterFlag) {
+        let connection = self.connection.lock().unwrap();
+
+        let mut stmt = connection
+            .prepare("UPDATE characters SET flags = flags | ?1 WHERE content_id = ?2")
+            .unwrap();
+        stmt.execute((flags.bits(), content_id)).unwrap();
+    }
+
+    /// Renames a character, if `name` isn't already taken. This also clears `NAME_CHANGE_REQUIRED`.
+    pub fn rename_character(&self, content_id: u64, name: &str) -> bool {
+        let connection = self.connection.lock().unwrap();
+
+        // check if the name is free
+        {
+            let mut stmt = connection
+                .prepare("SELECT content_id FROM character_data WHERE name = ?1")
+                .unwrap();
+            if stmt.exists((name,)).unwrap() {
+                return false;
+            }
+        }
+
+        // update the name
+        {
+            let mut stmt = connection
+                .prepare("UPDATE character_data SET name = ?1 WHERE content_id = ?2")
+                .unwrap();
+            if stmt.execute((name, content_id)).unwrap() == 0 {
+                return false;
+            }
+        }
+
+        // they don't need a name change anymore
+        {
+            let mut stmt = connection
+                .prepare("UPDATE characters SET flags = flags & ~?1 WHERE content_id = ?2")
+                .unwrap();
+            stmt.execute((CharacterFlag::NAME_CHANGE_REQUIRED.bits(), content_id))
+                .unwrap();
+        }
+
+        true
+    }
+
+    /// Allows (or disallows) a character to remake their appearance once.
+    pub fn set_remake_allowed(&self, content_id: u64, allowed: bool) {
+        let connection = self.connection.lock().unwrap();
+
+        let mut stmt = connection
+            .prepare("UPDATE characters SET remake_allowed = ?1 WHERE content_id = ?2")
+            .unwrap();
+        stmt.execute((allowed, content_id)).unwrap();
+    }
+
+    /// Replaces the appearance of a character, if they're allowed to remake it. This uses up their remake.
+    pub fn remake_character(&self, content_id: u64, chara_info_str: &str) -> bool {
+        let connection = self.connection.lock().unwrap();
+
+        // check if they're allowed to
+        {
+            let mut stmt = connection
+                .prepare("SELECT remake_allowed FROM characters WHERE content_id = ?1")
+                .unwrap();
+            let allowed: bool = stmt
+                .query_row((content_id,), |row| row.get(0))
+                .unwrap_or(false);
+            if !allowed {
+                return false;
+            }
+        }
+
+        // replace the appearance
+        {
+            let mut stmt = connection
+                .prepare("UPDATE character_data SET chara_info = ?1 WHERE content_id = ?2")
+                .unwrap();
+            stmt.execute((chara_info_str, content_id)).unwrap();
+        }
+
+        // remakes are only allowed once
+        {
+            let mut stmt = connection
+                .prepare("UPDATE characters SET remake_allowed = 0 WHERE content_id = ?1")
+                .unwrap();
+            stmt.execute((content_id,)).unwrap();
+        }
+
+        true
+    }
+
+    /// Creates a backup of the character with `content_id`.
+    pub fn export_character(&self, content_id: u64) -> Option<CharacterBackup> {
+        let connection = self.connection.lock().unwrap();
+
+        let mut stmt = connection
+            .prepare("SELECT name, chara_info, city_state, zone_id, classjob_id, pos_x, pos_y, pos_z, rotation FROM character_data WHERE content_id = ?1")
+            .unwrap();
+
+        stmt.query_row((content_id,), |row| {
+            Ok(CharacterBackup {
+                version: CharacterBackup::VERSION,
+                name: row.get(0)?,
+                chara_info: row.get(1)?,
+                city_state: row.get(2)?,
+                zone_id: row.get(3)?,
+                classjob_id: row.get(4)?,
+                position: Position {
+                    x: row.get(5)?,
+                    y: row.get(6)?,
+                    z: row.get(7)?,
+                },
+                rotation: row.get(8)?,
+                inventory: Vec::new(),
+                class_levels: Vec::new(),
+                // these are too large for custom IPC, so they are added by the caller if needed
+                settings: Vec::new(),
+            })
+        })
+        .ok()
+    }
+
+    /// Creates a new character from a backup, and returns the new (content_id, actor_id).
+    /// Nothing is created if the name in the backup is already taken, or the database fails.
+    pub fn import_character(
+        &self,
+        service_account_id: u32,
+        backup: &CharacterBackup,
+    ) -> Result<(u64, u32), BackupError> {
+        let chara_info = serde_json::to_string(&backup.chara_info).map_err(BackupError::Json)?;
+
+        let mut connection = self.connection.lock().unwrap();
+        let transaction = connection.transaction().map_err(BackupError::Database)?;
+
+        // checked in the same transaction, so the name can't be taken before the character is inserted
+        let name_taken = transaction
+            .prepare("SELECT content_id FROM character_data WHERE name = ?1")
+            .and_then(|mut stmt| stmt.exists((&backup.name,)))
+            .map_err(BackupError::Database)?;
+        if name_taken {
+            return Err(BackupError::NameTaken(backup.name.clone()));
+        }
+
+        // insert ids, which are random so pick another one if it's already used by a different character
+        let (content_id, actor_id) = loop {
+            let content_id = Self::generate_content_id();
+            let actor_id = Self::generate_actor_id();
+
+            match transaction.execute(
+                "INSERT INTO characters (content_id, service_account_id, actor_id) VALUES (?1, ?2, ?3);",
+                (content_id, service_account_id, actor_id),
+            ) {
+                Ok(_) => break (content_id, actor_id),
+                Err(rusqlite::Error::SqliteFailure(err, _))
+                    if err.extended_code == rusqlite::ffi::SQLITE_CONSTRAINT_PRIMARYKEY =>
+                {
+                    continue;
+                }
+                Err(err) => return Err(BackupError::Database(err)),
+            }
+        };
+
+        // insert char data
+        transaction
+            .execute(
+                "INSERT INTO character_data VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10);",
+                (
+                    content_id,
+                    &backup.name,
+                    chara_info,
+                    backup.city_state,
+                    backup.zone_id,
+                    backup.classjob_id,
+                    backup.position.x,
+                    backup.position.y,
+                    backup.position.z,
+                    backup.rotation,
+                ),
+            )
+            .map_err(BackupError::Database)?;
+
+        transaction.commit().map_err(BackupError::Database)?;
+
+        Ok((content_id as u64, actor_id))
+    }
+
+    /// Returns the retainers of every character on `service_account_id`.
+    pub fn get_retainer_list(&self, service_account_id: u32) -> Vec<RetainerInfo> {
+        let connection = self.connection.lock().unwrap();
+
+        let mut stmt = connection
+            .prepare("SELECT retainers.retainer_id, retainers.owner_content_id, retainers.slot_id, retainers.status, retainers.name FROM retainers JOIN characters ON retainers.owner_content_id = characters.content_id WHERE characters.service_account_id = ?1 ORDER BY retainers.owner_content_id, retainers.slot_id")
+            .unwrap();
+
+        stmt.query_map((service_account_id,), |row| {
+            Ok(RetainerInfo {
+                id: row.get(0)?,
+                owner_id: row.get(1)?,
+                slot_id: row.get(2)?,
+                status: row.get(3)?,
+                name: row.get(4)?,
+                ..Default::default()
+            })
+        })
+        .unwrap()
+        .map(|x| x.unwrap())
+        .collect()
+    }
+
+    /// Checks if `name` is already used by a retainer.
+    pub fn check_is_retainer_name_free(&self, name: &str) -> bool {
+        let connection = self.connection.lock().unwrap();
+
+        let mut stmt = connection
+            .prepare("SELECT retainer_id FROM retainers WHERE name = ?1")
+            .unwrap();
+
+        !stmt.exists((name,)).unwrap()
+    }
+
+    /// Hires a new retainer for `owner_content_id` in the first free slot, and returns it's id.
+    /// Returns `None` if the owner doesn't exist, has no free slots or the name is taken.
+    pub fn add_retainer(
+        &self,
+        owner_content_id: u64,
+        name: &str,
+        max_retainers: u32,
+    ) -> Option<u64> {
+        if !self.check_is_retainer_name_free(name) {
+            return None;
+        }
+
+        let connection = self.connection.lock().unwrap();
+
+        // the owner has to exist
+        {
+            let mut stmt = connection
+                .prepare("SELECT content_id FROM characters WHERE content_id = ?1")
+                .unwrap();
+            if !stmt.exists((owner_content_id,)).unwrap() {
+                return None;
+            }
+        }
+
+        let used_slots: Vec<u32> = {
+            let mut stmt = connection
+                .prepare("SELECT slot_id FROM retainers WHERE owner_content_id = ?1")
+                .unwrap();
+            stmt.query_map((owner_content_id,), |row| row.get(0))
+                .unwrap()
+                .map(|x| x.unwrap())
+                .collect()
+        };
+        let slot_id = (0..max_retainers).find(|slot_id| !used_slots.contains(slot_id))?;
+
+        // ids are random, so pick another one if it's already used by a different retainer
+        loop {
+            let retainer_id = Self::generate_retainer_id();
+
+            match connection.execute(
+                "INSERT INTO retainers (retainer_id, owner_content_id, slot_id, name, status) VALUES (?1, ?2, ?3, ?4, ?5);",
+                (
+                    retainer_id,
+                    owner_content_id,
+                    slot_id,
+                    name,
+                    RetainerInfo::STATUS_ACTIVE,
+                ),
+            ) {
+                Ok(_) => return Some(retainer_id as u64),
+                Err(rusqlite::Error::SqliteFailure(err, _))
+                    if err.extended_code == rusqlite::ffi::SQLITE_CONSTRAINT_PRIMARYKEY =>
+                {
+                    continue;
+                }
+                Err(err) => {
+                    tracing::warn!("Failed to hire retainer {name}: {err:?}");
+                    return None;
+                }
+            }
+        }
+    }
+
+    /// Returns the service account that owns `retainer_id`, through the character that hired it.
+    pub fn get_retainer_service_account(&self, retainer_id: u64) -> Option<u32> {
+        let connection = self.connection.lock().unwrap();
+
+        let mut stmt = connection
+            .prepare("SELECT characters.service_account_id FROM retainers JOIN characters ON retainers.owner_content_id = characters.content_id WHERE retainers.retainer_id = ?1")
+            .unwrap();
+        stmt.query_row((retainer_id,), |row| row.get(0)).ok()
+    }
+
+    /// Returns how many retainers the character with `content_id` has hired.
+    pub fn count_retainers(&self, content_id: u64) -> u32 {
+        let connection = self.connection.lock().unwrap();
+
+        let mut stmt = connection
+            .prepare("SELECT COUNT(*) FROM retainers WHERE owner_content_id = ?1")
+            .unwrap();
+
+        stmt.query_row((content_id,), |row| row.get(0)).unwrap()
+    }
+
+    /// Renames a retainer, if `name` isn't already taken by another retainer.
+    pub fn rename_retainer(&self, retainer_id: u64, name: &str) -> bool {
+        if !self.check_is_retainer_name_free(name) {
+            return false;
+        }
+
+        let connection = self.connection.lock().unwrap();
+
+        let mut stmt = connection
+            .prepare("UPDATE retainers SET name = ?1 WHERE retainer_id = ?2")
+            .unwrap();
+        stmt.execute((name, retainer_id)).unwrap() != 0
+    }
+
+    /// Stores a chunk of uploaded settings. Chunks have to arrive in order, an `offset` of 0 starts over.
+    /// Returns false if the character doesn't exist, the chunk is out of order or the settings are too large.
+    pub fn store_settings_chunk(
+        &self,
+        content_id: u64,
+        kind: u8,
+        offset: u32,
+        data: &[u8],
+    ) -> bool {
+        let connection = self.connection.lock().unwrap();
+
+        // the character has to exist
+        {
+            let mut stmt = connection
+                .prepare("SELECT content_id FROM characters WHERE content_id = ?1")
+                .unwrap();
+            if !stmt.exists((content_id,)).unwrap() {
+                return false;
+            }
+        }
+
+        let mut settings: Vec<u8> = if offset == 0 {
+            Vec::new()
+        } else {
+            let mut stmt = connection
+                .prepare("SELECT data FROM character_settings WHERE content_id = ?1 AND kind = ?2")
+                .unwrap();
+            stmt.query_row((content_id, kind), |row| row.get(0))
+                .unwrap_or_default()
+        };
+
+        if settings.len() != offset as usize || settings.len() + data.len() > MAX_SETTINGS_SIZE {
+            return false;
+        }
+        settings.extend_from_slice(data);
+
+        connection
+            .execute(
+                "INSERT OR REPLACE INTO character_settings (content_id, kind, data) VALUES (?1, ?2, ?3);",
+                (content_id, kind, settings),
+            )
+            .unwrap();
+
+        true
+    }
+
+    /// Returns all of the settings uploaded for the character with `content_id`, ordered by kind.
+    pub fn get_character_settings_by_content_id(&self, content_id: u64) -> Vec<CharacterSettings> {
+        let connection = self.connection.lock().unwrap();
+
+        let mut stmt = connection
+            .prepare(
+                "SELECT kind, data FROM character_settings WHERE content_id = ?1 ORDER BY kind",
+            )
+            .unwrap();
+
+        stmt.query_map((content_id,), |row| {
+            Ok(CharacterSettings {
+                kind: row.get(0)?,
+                data: row.get(1)?,
+            })
+        })
+        .unwrap()
+        .map(|x| x.unwrap())
+        .collect()
+    }
+
     /// Deletes a character and all associated data
     pub fn delete_character(&self, content_id: u64) {
         let connection = self.connection.lock().unwrap();
 
+        // delete retainers
+        {
+            let mut stmt = connection
+                .prepare("DELETE FROM retainers WHERE owner_content_id = ?1")
+                .unwrap();
+            stmt.execute((content_id,)).unwrap();
+        }
+
+        // delete settings
+        {
+            let mut stmt = connection
+                .prepare("DELETE FROM character_settings WHERE content_id = ?1")
+                .unwrap();
+            stmt.execute((content_id,)).unwrap();
+        }
+
         // delete data
         {
             let mut stmt = connection
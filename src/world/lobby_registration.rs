@@ -0,0 +1,119 @@
+use std::{
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicU32, Ordering},
+    },
+    time::Duration,
+};
+
+use tokio::{io::AsyncReadExt, net::TcpStream};
+
+use crate::{
+    RECEIVE_BUFFER_SIZE,
+    common::GameData,
+    config::get_config,
+    ipc::kodama::{CustomIpcData, CustomIpcSegment, CustomIpcType},
+    packet::{PacketState, SegmentData, authenticate_custom_ipc, parse_packet, send_custom_ipc},
+};
+
+/// How long to wait before trying to reach the lobby server again.
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+
+/// Registers this world with the lobby server, and then keeps it updated with heartbeats.
+/// If the lobby server goes away, we keep trying to register again.
+pub async fn lobby_registration_loop(population: Arc<AtomicU32>, gamedata: Arc<Mutex<GameData>>) {
+    let config = get_config();
+
+    let lobby_addr = config.lobby.get_public_socketaddr();
+    let world_name = gamedata
+        .lock()
+        .unwrap()
+        .get_world_name(config.world.world_id);
+
+    loop {
+        let Ok(mut stream) = TcpStream::connect(lobby_addr).await else {
+            tracing::warn!("Failed to connect to the lobby server at {lobby_addr}, is it running?");
+            tokio::time::sleep(RECONNECT_DELAY).await;
+            continue;
+        };
+
+        let mut state = PacketState { client_key: None };
+
+        if !authenticate_custom_ipc(&mut stream, &mut state).await {
+            tracing::warn!(
+                "The lobby server refused to authenticate us, is the shared secret the same?"
+            );
+            tokio::time::sleep(RECONNECT_DELAY).await;
+            continue;
+        }
+
+        send_custom_ipc(
+            &mut stream,
+            &mut state,
+            CustomIpcSegment {
+                op_code: CustomIpcType::RegisterWorld,
+                data: CustomIpcData::RegisterWorld {
+                    world_id: config.world.world_id,
+                    port: config.world.port,
+                    capacity: config.world.capacity,
+                    population: population.load(Ordering::Relaxed),
+                    server_name: config.world.server_name.clone(),
+                    name: world_name.clone(),
+                },
+                ..Default::default()
+            },
+        )
+        .await;
+
+        let mut interval =
+            tokio::time::interval(Duration::from_secs(config.world.heartbeat_interval));
+        interval.tick().await; // the first tick completes immediately
+
+        let mut buf = vec![0; RECEIVE_BUFFER_SIZE];
+        loop {
+            tokio::select! {
+                n = stream.read(&mut buf) => {
+                    match n {
+                        Ok(n) if n > 0 => {
+                            let (segments, _) = parse_packet::<CustomIpcSegment>(&buf[..n], &mut state);
+                            for segment in &segments {
+                                if let SegmentData::KodamaIpc { data } = &segment.data {
+                                    match &data.data {
+                                        CustomIpcData::WorldRegistered { accepted: true } => {
+                                            tracing::info!("Registered with the lobby server at {lobby_addr}");
+                                        }
+                                        CustomIpcData::WorldRegistered { accepted: false } => {
+                                            tracing::warn!("The lobby server refused to register this world!");
+                                        }
+                                        _ => tracing::warn!("Unexpected custom IPC from the lobby server: {data:#?}"),
+                                    }
+                                }
+                            }
+                        }
+                        _ => {
+                            tracing::warn!("Lost connection to the lobby server, reconnecting...");
+                            break;
+                        }
+                    }
+                }
+                _ = interval.tick() => {
+                    send_custom_ipc(
+                        &mut stream,
+                        &mut state,
+                        CustomIpcSegment {
+                            op_code: CustomIpcType::WorldHeartbeat,
+                            data: CustomIpcData::WorldHeartbeat {
+                                world_id: config.world.world_id,
+                                population: population.load(Ordering::Relaxed),
+                            },
+                            ..Default::default()
+                        },
+                    )
+                    .await;
+                }
+            }
+        }
+
+        tokio::time::sleep(RECONNECT_DELAY).await;
+    }
+}
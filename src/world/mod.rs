@@ -1,12 +1,26 @@
 mod connection;
-pub use connection::ZoneConnection;
+pub use connection::{EntryGrant, ZoneConnection};
 
 mod database;
-pub use database::{CharacterData, WorldDatabase};
+pub use database::{CharacterData, CharacterSettings, WorldDatabase};
 
 mod server;
 pub use server::server_main_loop;
 
+mod lobby_registration;
+pub use lobby_registration::lobby_registration_loop;
+
+mod character_backup;
+pub use character_backup::{
+    BackupClassLevel, BackupError, BackupItem, BackupSettings, CharacterBackup,
+    begin_character_transfer, cancel_character_transfer, export_character_to_file,
+    export_character_to_json, import_character_from_file, import_character_from_json,
+    parse_character_backup,
+};
+
+mod rcon;
+pub use rcon::handle_rcon_command;
+
 mod custom_ipc_handler;
 pub use custom_ipc_handler::handle_custom_ipc;
 
@@ -0,0 +1,95 @@
+use crate::{config::get_config, ipc::lobby::CharacterFlag};
+
+use super::{WorldDatabase, export_character_to_file, import_character_from_file};
+
+/// Runs an RCON command, and returns the text to send back.
+pub fn handle_rcon_command(database: &WorldDatabase, command: &str) -> String {
+    let mut args = command.split_whitespace();
+    let Some(name) = args.next() else {
+        return String::default();
+    };
+    let args: Vec<&str> = args.collect();
+
+    match name {
+        "forcerename" => {
+            if args.is_empty() {
+                return "Usage: forcerename <character name>".to_string();
+            }
+
+            // character names contain spaces
+            let character_name = args.join(" ");
+            let Some(content_id) = database.find_content_id(&character_name) else {
+                return format!("Couldn't find a character named {character_name}!");
+            };
+
+            database.add_character_flags(content_id, CharacterFlag::NAME_CHANGE_REQUIRED);
+
+            format!("{character_name} will have to pick a new name before logging in.")
+        }
+        "allowremake" => {
+            if args.is_empty() {
+                return "Usage: allowremake <character name>".to_string();
+            }
+
+            let character_name = args.join(" ");
+            let Some(content_id) = database.find_content_id(&character_name) else {
+                return format!("Couldn't find a character named {character_name}!");
+            };
+
+            database.set_remake_allowed(content_id, true);
+
+            format!("{character_name} can now remake their appearance once.")
+        }
+        "importcharacter" => {
+            let [service_account_id, path] = args[..] else {
+                return "Usage: importcharacter <service account id> <file name>".to_string();
+            };
+            let Ok(service_account_id) = service_account_id.parse() else {
+                return format!("{service_account_id} is not a valid service account id!");
+            };
+
+            match import_character_from_file(database, service_account_id, path) {
+                Ok((content_id, _)) => format!("Imported {path} as {content_id}."),
+                Err(err) => format!("Failed to import {path}: {err}"),
+            }
+        }
+        "exportcharacter" => {
+            if args.len() < 2 {
+                return "Usage: exportcharacter <file name> <character name>".to_string();
+            }
+
+            let path = args[0];
+            let character_name = args[1..].join(" ");
+            let Some(content_id) = database.find_content_id(&character_name) else {
+                return format!("Couldn't find a character named {character_name}!");
+            };
+
+            match export_character_to_file(database, content_id, path) {
+                Ok(()) => format!("Exported {character_name} to {path}."),
+                Err(err) => format!("Failed to export {character_name}: {err}"),
+            }
+        }
+        "addretainer" => {
+            if args.len() < 2 {
+                return "Usage: addretainer <retainer name> <character name>".to_string();
+            }
+
+            let retainer_name = args[0];
+            let character_name = args[1..].join(" ");
+            let Some(content_id) = database.find_content_id(&character_name) else {
+                return format!("Couldn't find a character named {character_name}!");
+            };
+
+            match database.add_retainer(content_id, retainer_name, get_config().world.max_retainers)
+            {
+                Some(retainer_id) => {
+                    format!("{character_name} hired {retainer_name} ({retainer_id}).")
+                }
+                None => format!(
+                    "{character_name} couldn't hire {retainer_name}, either the name is taken or they have no free retainer slots."
+                ),
+            }
+        }
+        _ => format!("Unknown command {name}!"),
+    }
+}
@@ -1,6 +1,9 @@
 use std::{
     collections::HashMap,
-    sync::{Arc, Mutex},
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicU32, Ordering},
+    },
 };
 use tokio::sync::mpsc::Receiver;
 
@@ -15,7 +18,11 @@ struct WorldServer {
     clients: HashMap<ClientId, (ClientHandle, ClientState)>,
 }
 
-pub async fn server_main_loop(mut recv: Receiver<ToServer>) -> Result<(), std::io::Error> {
+/// `population` is kept updated with the number of connected clients.
+pub async fn server_main_loop(
+    mut recv: Receiver<ToServer>,
+    population: Arc<AtomicU32>,
+) -> Result<(), std::io::Error> {
     let data = Arc::new(Mutex::new(WorldServer::default()));
 
     while let Some(msg) = recv.recv().await {
@@ -45,6 +52,8 @@ pub async fn server_main_loop(mut recv: Receiver<ToServer>) -> Result<(), std::i
             for remove_id in data.to_remove.clone() {
                 data.clients.remove(&remove_id);
             }
+
+            population.store(data.clients.len() as u32, Ordering::Relaxed);
         }
     }
     Ok(())